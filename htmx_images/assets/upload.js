@@ -6,9 +6,124 @@ htmx.on('#img-upload-form', 'htmx:xhr:progress', function(evt) {
     document.getElementById('progress').style.width = progress + '%';
 });
 
+// Rejected uploads are still shown, either as an alert or in the grid.
+// Placeholders that poll an image are replaced by the error, so that they
+// stop polling.
+htmx.on('htmx:beforeSwap', function(evt) {
+    var elt = evt.detail.elt;
+    var status = evt.detail.xhr.status;
+    var rejected = elt && elt.id === 'img-upload-form' && (status < 500 || status === 503);
+    var polling = elt && elt.classList.contains('grid-placeholder');
+    if (rejected || polling) {
+        evt.detail.shouldSwap = true;
+        evt.detail.isError = false;
+    }
+});
+
+htmx.on('htmx:beforeRequest', function(evt) {
+    if (evt.detail.elt && evt.detail.elt.id === 'img-upload-form') {
+        document.getElementById('upload-alerts').innerHTML = '';
+    }
+});
+
 htmx.on('htmx:afterRequest', function(evt) {
     if (evt.detail.elt && evt.detail.elt.id === 'img-upload-form') {
         document.getElementById('progress').style.width = '0%'; // Reset progress bar
         document.getElementById('form-file').value = ''; // Clear file input field
     }
-});
\ No newline at end of file
+});
+// Dropped files are uploaded one request per file, each with its own
+// placeholder and progress bar in the grid.
+function uploadFile(file) {
+    var placeholder = document.createElement('div');
+    placeholder.className = 'grid-item grid-placeholder flex-column';
+    placeholder.title = file.name;
+    placeholder.innerHTML = '<div class="progress w-75" role="progressbar"><div class="progress-bar" style="width:0%;"></div></div>';
+    document.getElementById('all-images').prepend(placeholder);
+
+    var bar = placeholder.querySelector('.progress-bar');
+    var data = new FormData();
+    data.append('file', file);
+
+    var xhr = new XMLHttpRequest();
+    xhr.upload.onprogress = function(evt) {
+        bar.style.width = ((evt.loaded / evt.total) * 100) + '%';
+    };
+    xhr.onload = function() {
+        // The session ended, log in again.
+        if (xhr.status === 401) {
+            window.location = '/login';
+            return;
+        }
+        // Rejected requests answer with an alert, rejected files with grid items.
+        if (xhr.status < 500 && !xhr.getResponseHeader('HX-Retarget')) {
+            // The new grid items poll until they are processed.
+            var template = document.createElement('template');
+            template.innerHTML = xhr.responseText;
+            var items = Array.from(template.content.children);
+            placeholder.replaceWith.apply(placeholder, items);
+            items.forEach(function(item) { htmx.process(item); });
+        } else {
+            var template = document.createElement('template');
+            template.innerHTML = xhr.responseText;
+            uploadFailed(placeholder, template.content.textContent.trim() || xhr.statusText);
+        }
+    };
+    xhr.onerror = function() {
+        uploadFailed(placeholder, 'network error');
+    };
+    xhr.open('POST', '/images');
+    xhr.send(data);
+}
+
+function uploadFailed(placeholder, error) {
+    placeholder.className = 'grid-item grid-placeholder text-danger';
+    placeholder.title = placeholder.title + ': ' + error;
+    placeholder.innerHTML = '<i class="bi bi-exclamation-triangle fs-3"></i>';
+}
+
+document.addEventListener('DOMContentLoaded', function() {
+    var zone = document.getElementById('drop-zone');
+    if (!zone) {
+        return;
+    }
+
+    ['dragenter', 'dragover'].forEach(function(name) {
+        zone.addEventListener(name, function(evt) {
+            evt.preventDefault();
+            zone.classList.add('drop-zone-active');
+        });
+    });
+    ['dragleave', 'drop'].forEach(function(name) {
+        zone.addEventListener(name, function() {
+            zone.classList.remove('drop-zone-active');
+        });
+    });
+    zone.addEventListener('drop', function(evt) {
+        evt.preventDefault();
+        Array.from(evt.dataTransfer.files).forEach(uploadFile);
+    });
+});
+
+// Clicking the image in the modal centers its thumbnail on that point, the
+// grid item shows the progress until the new thumbnail is ready.
+document.addEventListener('click', function(evt) {
+    var img = evt.target.closest('.focal-picker');
+    if (!img) {
+        return;
+    }
+
+    var x = evt.offsetX / img.clientWidth;
+    var y = evt.offsetY / img.clientHeight;
+    htmx.ajax('POST', img.dataset.focalUrl, {
+        target: '#image-' + img.dataset.imageId,
+        swap: 'outerHTML',
+        values: { x: x.toFixed(3), y: y.toFixed(3) },
+    });
+    bootstrap.Modal.getOrCreateInstance(document.getElementById('modals-here')).hide();
+});
+
+// The grid item of a deleted image is removed, its modal is closed.
+htmx.on('image-deleted', function() {
+    bootstrap.Modal.getOrCreateInstance(document.getElementById('modals-here')).hide();
+});
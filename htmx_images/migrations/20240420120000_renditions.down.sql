@@ -0,0 +1 @@
+DROP TABLE rendition;
\ No newline at end of file
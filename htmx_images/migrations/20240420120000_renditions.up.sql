@@ -0,0 +1,15 @@
+CREATE TABLE rendition (
+    image_id BIGINT NOT NULL REFERENCES image (id) ON DELETE CASCADE,
+    name VARCHAR(32) NOT NULL,
+    mime_type VARCHAR(100) NOT NULL,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (image_id, name)
+);
+
+-- Images used to only keep a 150px thumbnail, it becomes their only rendition.
+INSERT INTO rendition (image_id, name, mime_type, width, height, data)
+SELECT id, 'thumbnail', COALESCE(mime_type, 'image/webp'), 150, 150, image_data
+FROM image
+WHERE image_data IS NOT NULL;
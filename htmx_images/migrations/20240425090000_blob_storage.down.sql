@@ -0,0 +1,4 @@
+-- Fails while blobs are kept outside of the database, move them back first
+-- with `migrate-blobs <store> database`.
+ALTER TABLE rendition
+ALTER COLUMN data SET NOT NULL;
@@ -0,0 +1,3 @@
+-- Renditions can keep their data outside of the database.
+ALTER TABLE rendition
+ALTER COLUMN data DROP NOT NULL;
@@ -0,0 +1,5 @@
+ALTER TABLE image
+DROP COLUMN status,
+DROP COLUMN error;
+
+DROP TYPE image_status;
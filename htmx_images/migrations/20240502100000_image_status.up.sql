@@ -0,0 +1,6 @@
+CREATE TYPE image_status AS ENUM ('processing', 'ready', 'failed');
+
+-- Uploads are processed in the background, existing images are done.
+ALTER TABLE image
+ADD status image_status NOT NULL DEFAULT 'ready',
+ADD error TEXT NULL;
@@ -0,0 +1 @@
+DROP INDEX image_created_at_id;
@@ -0,0 +1,2 @@
+-- The gallery is paginated newest first.
+CREATE INDEX image_created_at_id ON image (created_at DESC, id DESC);
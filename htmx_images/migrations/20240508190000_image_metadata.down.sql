@@ -0,0 +1,5 @@
+ALTER TABLE image
+DROP COLUMN camera,
+DROP COLUMN taken_at,
+DROP COLUMN width,
+DROP COLUMN height;
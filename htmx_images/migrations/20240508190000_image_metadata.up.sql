@@ -0,0 +1,6 @@
+-- Selected EXIF fields, the location isn't kept.
+ALTER TABLE image
+ADD camera TEXT NULL,
+ADD taken_at TIMESTAMP NULL,
+ADD width INT NULL,
+ADD height INT NULL;
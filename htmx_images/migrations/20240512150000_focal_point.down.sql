@@ -0,0 +1,4 @@
+ALTER TABLE image
+DROP COLUMN focal_x,
+DROP COLUMN focal_y,
+DROP COLUMN renditions_version;
@@ -0,0 +1,5 @@
+-- Where thumbnails are centered, chosen by the user.
+ALTER TABLE image
+ADD focal_x REAL NULL,
+ADD focal_y REAL NULL,
+ADD renditions_version INT NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+DROP TABLE image_tag;
+DROP TABLE tag;
+DROP TABLE album_image;
+DROP TABLE album;
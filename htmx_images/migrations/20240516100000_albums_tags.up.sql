@@ -0,0 +1,27 @@
+CREATE TABLE album (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE album_image (
+    album_id BIGINT NOT NULL REFERENCES album (id) ON DELETE CASCADE,
+    image_id BIGINT NOT NULL REFERENCES image (id) ON DELETE CASCADE,
+    PRIMARY KEY (album_id, image_id)
+);
+
+CREATE INDEX album_image_image_id ON album_image (image_id);
+
+-- Tags are free-form, they exist as long as an image has them.
+CREATE TABLE tag (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE image_tag (
+    image_id BIGINT NOT NULL REFERENCES image (id) ON DELETE CASCADE,
+    tag_id BIGINT NOT NULL REFERENCES tag (id) ON DELETE CASCADE,
+    PRIMARY KEY (image_id, tag_id)
+);
+
+CREATE INDEX image_tag_tag_id ON image_tag (tag_id);
@@ -0,0 +1,5 @@
+-- The main colors of an image, the dominant color first.
+ALTER TABLE image
+ADD palette INT[] NOT NULL DEFAULT '{}';
+
+UPDATE image SET palette = ARRAY[dominant_color] WHERE dominant_color IS NOT NULL;
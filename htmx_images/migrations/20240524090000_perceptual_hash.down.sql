@@ -0,0 +1,3 @@
+ALTER TABLE image
+DROP COLUMN phash,
+DROP COLUMN duplicate_of;
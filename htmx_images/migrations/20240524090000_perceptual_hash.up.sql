@@ -0,0 +1,5 @@
+-- A hash of how an image looks, similar images have hashes with few
+-- different bits. Uploads before this have none.
+ALTER TABLE image
+ADD phash BIGINT NULL,
+ADD duplicate_of BIGINT NULL REFERENCES image (id) ON DELETE SET NULL;
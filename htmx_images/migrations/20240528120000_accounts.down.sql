@@ -0,0 +1,5 @@
+ALTER TABLE image
+DROP COLUMN owner_id;
+
+DROP TABLE session;
+DROP TABLE account;
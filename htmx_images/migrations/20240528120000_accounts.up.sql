@@ -0,0 +1,20 @@
+-- Uploads belong to accounts, admins are made with `make-admin`.
+CREATE TABLE account (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE session (
+    token TEXT PRIMARY KEY,
+    account_id BIGINT NOT NULL REFERENCES account (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- Images uploaded before accounts have no owner, only admins change them.
+ALTER TABLE image
+ADD owner_id BIGINT NULL REFERENCES account (id) ON DELETE SET NULL;
+
+CREATE INDEX image_owner_id ON image (owner_id);
@@ -0,0 +1,3 @@
+-- Albums made before they had owners are changed by admins only.
+ALTER TABLE album
+ADD owner_id BIGINT NULL REFERENCES account (id) ON DELETE SET NULL;
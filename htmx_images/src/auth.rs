@@ -0,0 +1,210 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    db,
+    error::BoxError,
+    state::{AppState, User},
+};
+
+const COOKIE_NAME: &str = "session";
+/// How long a login lasts.
+const SESSION_DAYS: u64 = 30;
+/// How many wrong passwords lock an account name for `LOCKOUT`.
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Why a request isn't allowed.
+#[derive(Debug)]
+pub(crate) enum AuthError {
+    LoggedOut,
+    /// Only the owner or an admin can change an image.
+    Forbidden,
+}
+
+/// Logged out users log in first, htmx loads the login page instead of
+/// swapping it into a fragment.
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthError::LoggedOut => (
+                StatusCode::UNAUTHORIZED,
+                [("HX-Redirect", "/login")],
+                "log in first",
+            )
+                .into_response(),
+            AuthError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "only the owner or an admin can change this",
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The user of the session cookie, handlers that take a `User` need a login
+/// and `Option<User>` works for everyone.
+#[async_trait]
+impl FromRequestParts<AppState> for User {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = session_token(&parts.headers).ok_or(AuthError::LoggedOut)?;
+        match db::get_session_user(state, token).await {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => Err(AuthError::LoggedOut),
+            Err(err) => {
+                tracing::error!("could not load session: {err}");
+                Err(AuthError::LoggedOut)
+            }
+        }
+    }
+}
+
+pub(crate) fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find_map(|(name, value)| (name == COOKIE_NAME).then_some(value))
+}
+
+/// Starts a session, the returned cookie keeps it.
+pub(crate) async fn login(state: &AppState, user: &User) -> Result<String, BoxError> {
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let expires_at = chrono::Utc::now() + chrono::Days::new(SESSION_DAYS);
+    db::insert_session(state, &token, user.id, expires_at).await?;
+
+    let max_age = SESSION_DAYS * 24 * 60 * 60;
+    Ok(format!(
+        "{COOKIE_NAME}={token}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax"
+    ))
+}
+
+/// The cookie that removes the session cookie.
+pub(crate) fn logout_cookie() -> String {
+    format!("{COOKIE_NAME}=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax")
+}
+
+/// Failed logins per account name, too many in a row lock the name for a
+/// while so that passwords can't be guessed.
+#[derive(Default)]
+pub(crate) struct LoginThrottle {
+    /// The failures and when the last one was.
+    failures: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl LoginThrottle {
+    pub(crate) fn is_locked(&self, name: &str, now: Instant) -> bool {
+        let failures = self.failures.lock().unwrap();
+        failures.get(name).is_some_and(|&(count, last)| {
+            count >= MAX_FAILURES && now.duration_since(last) < LOCKOUT
+        })
+    }
+
+    pub(crate) fn failed(&self, name: &str, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        // Names that weren't tried for a while start over.
+        failures.retain(|_, (_, last)| now.duration_since(*last) < LOCKOUT);
+        let (count, last) = failures.entry(name.to_string()).or_insert((0, now));
+        *count += 1;
+        *last = now;
+    }
+
+    pub(crate) fn succeeded(&self, name: &str) {
+        self.failures.lock().unwrap().remove(name);
+    }
+}
+
+/// Hashing is slow on purpose, callers keep it off the async runtime.
+pub(crate) fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// A hash no password matches, checking it for names without an account
+/// takes as long as for real ones so that names can't be told apart.
+pub(crate) fn unknown_user_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let password = uuid::Uuid::new_v4().to_string();
+        hash_password(&password).unwrap_or_default()
+    })
+}
+
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn passwords() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("battery staple", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+        assert!(PasswordHash::new(unknown_user_hash()).is_ok());
+        assert!(!verify_password("", unknown_user_hash()));
+    }
+
+    #[test]
+    fn login_throttle() {
+        let throttle = LoginThrottle::default();
+        let start = Instant::now();
+        for _ in 0..MAX_FAILURES - 1 {
+            throttle.failed("alice", start);
+        }
+        assert!(!throttle.is_locked("alice", start));
+
+        throttle.failed("alice", start);
+        assert!(throttle.is_locked("alice", start));
+        assert!(!throttle.is_locked("bob", start));
+        assert!(!throttle.is_locked("alice", start + LOCKOUT));
+
+        throttle.succeeded("alice");
+        assert!(!throttle.is_locked("alice", start));
+    }
+
+    #[test]
+    fn session_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_token(&headers), None);
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; session=abc123; other=1"),
+        );
+        assert_eq!(session_token(&headers), Some("abc123"));
+    }
+}
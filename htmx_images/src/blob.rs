@@ -0,0 +1,351 @@
+use std::{fmt, io::ErrorKind, path::PathBuf};
+
+use async_trait::async_trait;
+use s3::{creds::Credentials, Bucket, Region};
+use sqlx::PgPool;
+
+use crate::error::BoxError;
+
+/// The data of an image: the original upload or one of its renditions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BlobKey {
+    Original(i64),
+    Rendition(i64, String),
+}
+
+impl BlobKey {
+    /// The path of the blob, e.g. `42/original` or `42/thumbnail`.
+    fn path(&self) -> String {
+        match self {
+            BlobKey::Original(id) => format!("{id}/original"),
+            BlobKey::Rendition(id, name) => format!("{id}/{name}"),
+        }
+    }
+}
+
+impl fmt::Display for BlobKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path())
+    }
+}
+
+/// Where image data is stored, the rows in the database only keep metadata.
+#[async_trait]
+pub(crate) trait BlobStore: Send + Sync {
+    async fn put(&self, key: &BlobKey, data: &[u8]) -> Result<(), BoxError>;
+
+    /// The data of a blob, `None` if it isn't stored.
+    async fn get(&self, key: &BlobKey) -> Result<Option<Vec<u8>>, BoxError>;
+
+    /// Deletes a blob, deleting missing blobs is fine.
+    async fn delete(&self, key: &BlobKey) -> Result<(), BoxError>;
+
+    /// Where the blobs are kept, stores at the same location share them.
+    fn location(&self) -> String;
+}
+
+/// Selects a store by name: `database` (the default), `filesystem` or `s3`.
+///
+/// - filesystem: `BLOB_DIR` is the directory, `blobs` by default.
+/// - s3: `S3_BUCKET`, `S3_ENDPOINT`, `S3_REGION`, `S3_ACCESS_KEY` and
+///   `S3_SECRET_KEY` configure the bucket.
+pub(crate) fn from_config(name: &str, database: &PgPool) -> Result<Box<dyn BlobStore>, BoxError> {
+    let store: Box<dyn BlobStore> = match name {
+        "database" => Box::new(DatabaseStore {
+            database: database.clone(),
+        }),
+        "filesystem" => Box::new(FilesystemStore {
+            dir: std::env::var("BLOB_DIR")
+                .unwrap_or_else(|_| "blobs".to_string())
+                .into(),
+        }),
+        "s3" => Box::new(S3Store::from_env()?),
+        _ => return Err(format!("unknown blob storage `{name}`").into()),
+    };
+    Ok(store)
+}
+
+/// Moves all blobs from one store to another, blobs that were already moved
+/// are skipped so that an interrupted migration can be run again.
+pub(crate) async fn migrate(
+    database: &PgPool,
+    from: &dyn BlobStore,
+    to: &dyn BlobStore,
+) -> Result<usize, BoxError> {
+    const QUERY: &str = r#"
+SELECT id, NULL FROM image
+UNION ALL
+SELECT image_id, name FROM rendition
+ORDER BY 1, 2 NULLS FIRST;
+"#;
+    let keys: Vec<(i64, Option<String>)> = sqlx::query_as(QUERY).fetch_all(database).await?;
+    let keys = keys
+        .into_iter()
+        .map(|(id, name)| match name {
+            Some(name) => BlobKey::Rendition(id, name),
+            None => BlobKey::Original(id),
+        })
+        .collect::<Vec<_>>();
+
+    move_blobs(&keys, from, to).await
+}
+
+async fn move_blobs(
+    keys: &[BlobKey],
+    from: &dyn BlobStore,
+    to: &dyn BlobStore,
+) -> Result<usize, BoxError> {
+    // Every blob would be deleted right after it's put.
+    if from.location() == to.location() {
+        return Err(format!("the blobs are already in {}", to.location()).into());
+    }
+
+    let mut moved = 0;
+    for key in keys {
+        let Some(data) = from.get(key).await? else {
+            continue;
+        };
+        to.put(key, &data).await?;
+        from.delete(key).await?;
+
+        tracing::info!("moved blob {key}");
+        moved += 1;
+    }
+
+    Ok(moved)
+}
+
+/// Keeps blobs in the `image_data` and `data` columns of the image and
+/// rendition tables, like images always were.
+pub(crate) struct DatabaseStore {
+    database: PgPool,
+}
+
+#[async_trait]
+impl BlobStore for DatabaseStore {
+    async fn put(&self, key: &BlobKey, data: &[u8]) -> Result<(), BoxError> {
+        let query = match key {
+            BlobKey::Original(id) => {
+                sqlx::query(r#"UPDATE image SET image_data = $2 WHERE id = $1;"#)
+                    .bind(id)
+                    .bind(data)
+            }
+            BlobKey::Rendition(id, name) => {
+                sqlx::query(r#"UPDATE rendition SET data = $3 WHERE image_id = $1 AND name = $2;"#)
+                    .bind(id)
+                    .bind(name)
+                    .bind(data)
+            }
+        };
+
+        let res = query.execute(&self.database).await?;
+        if res.rows_affected() == 0 {
+            return Err(format!("no row for blob {key}").into());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &BlobKey) -> Result<Option<Vec<u8>>, BoxError> {
+        let query = match key {
+            BlobKey::Original(id) => {
+                sqlx::query_scalar(r#"SELECT image_data FROM image WHERE id = $1;"#).bind(id)
+            }
+            BlobKey::Rendition(id, name) => sqlx::query_scalar(
+                r#"SELECT data FROM rendition WHERE image_id = $1 AND name = $2;"#,
+            )
+            .bind(id)
+            .bind(name),
+        };
+
+        let data: Option<Option<Vec<u8>>> = query.fetch_optional(&self.database).await?;
+        Ok(data.flatten())
+    }
+
+    async fn delete(&self, key: &BlobKey) -> Result<(), BoxError> {
+        let query = match key {
+            BlobKey::Original(id) => {
+                sqlx::query(r#"UPDATE image SET image_data = NULL WHERE id = $1;"#).bind(id)
+            }
+            BlobKey::Rendition(id, name) => sqlx::query(
+                r#"UPDATE rendition SET data = NULL WHERE image_id = $1 AND name = $2;"#,
+            )
+            .bind(id)
+            .bind(name),
+        };
+
+        query.execute(&self.database).await?;
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        "database".to_string()
+    }
+}
+
+/// Keeps blobs as files in a directory, one directory per image.
+pub(crate) struct FilesystemStore {
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl BlobStore for FilesystemStore {
+    async fn put(&self, key: &BlobKey, data: &[u8]) -> Result<(), BoxError> {
+        let path = self.dir.join(key.path());
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Write to a temporary file first so that readers never see half a blob.
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &BlobKey) -> Result<Option<Vec<u8>>, BoxError> {
+        match tokio::fs::read(self.dir.join(key.path())).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&self, key: &BlobKey) -> Result<(), BoxError> {
+        match tokio::fs::remove_file(self.dir.join(key.path())).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn location(&self) -> String {
+        let dir = std::path::absolute(&self.dir).unwrap_or_else(|_| self.dir.clone());
+        format!("filesystem {}", dir.display())
+    }
+}
+
+/// Keeps blobs in a bucket of an S3 compatible API, e.g. MinIO.
+pub(crate) struct S3Store {
+    bucket: Bucket,
+}
+
+impl S3Store {
+    fn from_env() -> Result<Self, BoxError> {
+        let var = |name: &str| std::env::var(name).map_err(|_| format!("{name} should be set"));
+
+        let region = Region::Custom {
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            endpoint: var("S3_ENDPOINT")?,
+        };
+        let credentials = Credentials::new(
+            Some(&var("S3_ACCESS_KEY")?),
+            Some(&var("S3_SECRET_KEY")?),
+            None,
+            None,
+            None,
+        )?;
+
+        // Path style urls work with every S3 compatible API.
+        let bucket = Bucket::new(&var("S3_BUCKET")?, region, credentials)?.with_path_style();
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn put(&self, key: &BlobKey, data: &[u8]) -> Result<(), BoxError> {
+        let res = self.bucket.put_object(key.path(), data).await?;
+        match res.status_code() {
+            200..=299 => Ok(()),
+            status => Err(format!("could not put blob {key}: {status}").into()),
+        }
+    }
+
+    async fn get(&self, key: &BlobKey) -> Result<Option<Vec<u8>>, BoxError> {
+        let res = self.bucket.get_object(key.path()).await?;
+        match res.status_code() {
+            200 => Ok(Some(res.to_vec())),
+            404 => Ok(None),
+            status => Err(format!("could not get blob {key}: {status}").into()),
+        }
+    }
+
+    async fn delete(&self, key: &BlobKey) -> Result<(), BoxError> {
+        let res = self.bucket.delete_object(key.path()).await?;
+        match res.status_code() {
+            200..=299 | 404 => Ok(()),
+            status => Err(format!("could not delete blob {key}: {status}").into()),
+        }
+    }
+
+    fn location(&self) -> String {
+        format!("s3 {}", self.bucket.url())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store in an empty directory of its own.
+    fn filesystem_store(name: &str) -> FilesystemStore {
+        let dir = std::env::temp_dir().join(format!("htmx_images-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        FilesystemStore { dir }
+    }
+
+    #[tokio::test]
+    async fn filesystem_round_trip() {
+        let store = filesystem_store("round-trip");
+        let key = BlobKey::Rendition(7, "thumbnail.webp".to_string());
+
+        assert_eq!(store.get(&key).await.unwrap(), None);
+        store.put(&key, b"first").await.unwrap();
+        store.put(&key, b"second").await.unwrap();
+        assert_eq!(store.get(&key).await.unwrap(), Some(b"second".to_vec()));
+        assert!(store.dir.join("7/thumbnail.webp").exists());
+
+        store.delete(&key).await.unwrap();
+        assert_eq!(store.get(&key).await.unwrap(), None);
+        // Missing blobs are deleted too.
+        store.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrate_moves_blobs_once() {
+        let from = filesystem_store("migrate-from");
+        let to = filesystem_store("migrate-to");
+        let keys = [
+            BlobKey::Original(1),
+            BlobKey::Rendition(1, "thumbnail.webp".to_string()),
+            // Not stored, e.g. still processing.
+            BlobKey::Original(2),
+        ];
+        from.put(&keys[0], b"original").await.unwrap();
+        from.put(&keys[1], b"thumbnail").await.unwrap();
+
+        assert_eq!(move_blobs(&keys, &from, &to).await.unwrap(), 2);
+        assert_eq!(from.get(&keys[0]).await.unwrap(), None);
+        assert_eq!(to.get(&keys[0]).await.unwrap(), Some(b"original".to_vec()));
+        assert_eq!(to.get(&keys[1]).await.unwrap(), Some(b"thumbnail".to_vec()));
+
+        // Running it again finds nothing left to move.
+        assert_eq!(move_blobs(&keys, &from, &to).await.unwrap(), 0);
+        assert_eq!(to.get(&keys[0]).await.unwrap(), Some(b"original".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn migrate_to_the_same_store() {
+        let from = filesystem_store("migrate-same");
+        let to = FilesystemStore {
+            dir: from.dir.join("."),
+        };
+        let keys = [BlobKey::Original(1)];
+        from.put(&keys[0], b"original").await.unwrap();
+
+        assert!(move_blobs(&keys, &from, &to).await.is_err());
+        assert_eq!(
+            from.get(&keys[0]).await.unwrap(),
+            Some(b"original".to_vec())
+        );
+    }
+}
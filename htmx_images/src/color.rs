@@ -0,0 +1,218 @@
+/// A color in CIE L*a*b*, where distances match how different colors look.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Lab {
+    pub(crate) l: f64,
+    pub(crate) a: f64,
+    pub(crate) b: f64,
+}
+
+impl Lab {
+    /// Converts a `0xRRGGBB` sRGB color, with the D65 white point.
+    pub(crate) fn from_rgb(rgb: i32) -> Lab {
+        // sRGB to linear light.
+        let linear = |channel: i32| {
+            let c = (channel & 0xFF) as f64 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let (r, g, b) = (linear(rgb >> 16), linear(rgb >> 8), linear(rgb));
+
+        // Linear sRGB to XYZ, relative to the white point.
+        let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+        let f = |t: f64| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    /// The CIEDE2000 color difference, about 1 is the smallest difference
+    /// people notice.
+    pub(crate) fn delta_e(&self, other: &Lab) -> f64 {
+        let (l1, a1, b1) = (self.l, self.a, self.b);
+        let (l2, a2, b2) = (other.l, other.a, other.b);
+
+        let c_mean = ((a1.hypot(b1)) + (a2.hypot(b2))) / 2.0;
+        let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt());
+        let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+
+        let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+        let hue = |b: f64, a: f64| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+        let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+        let delta_l = l2 - l1;
+        let delta_c = c2 - c1;
+        let delta_h = if c1 * c2 == 0.0 {
+            0.0
+        } else if (h2 - h1).abs() <= 180.0 {
+            h2 - h1
+        } else if h2 <= h1 {
+            h2 - h1 + 360.0
+        } else {
+            h2 - h1 - 360.0
+        };
+        let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+        let l_mean = (l1 + l2) / 2.0;
+        let c_mean = (c1 + c2) / 2.0;
+        let h_mean = if c1 * c2 == 0.0 {
+            h1 + h2
+        } else if (h1 - h2).abs() <= 180.0 {
+            (h1 + h2) / 2.0
+        } else if h1 + h2 < 360.0 {
+            (h1 + h2 + 360.0) / 2.0
+        } else {
+            (h1 + h2 - 360.0) / 2.0
+        };
+
+        let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+            + 0.24 * (2.0 * h_mean).to_radians().cos()
+            + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+            - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+        let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+        let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt();
+        let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+        let s_c = 1.0 + 0.045 * c_mean;
+        let s_h = 1.0 + 0.015 * c_mean * t;
+        let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+        let l = delta_l / s_l;
+        let c = delta_c / s_c;
+        let h = delta_big_h / s_h;
+        (l * l + c * c + h * h + r_t * c * h).sqrt()
+    }
+}
+
+/// The ids of the colors closest to `color`, closest first.
+pub(crate) fn nearest(color: i32, colors: &[(i64, i32)], limit: usize) -> Vec<i64> {
+    let target = Lab::from_rgb(color);
+    let mut distances = colors
+        .iter()
+        .map(|&(id, rgb)| (id, target.delta_e(&Lab::from_rgb(rgb))))
+        .collect::<Vec<_>>();
+    distances.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    distances
+        .into_iter()
+        .take(limit)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Parses `#RRGGBB` or `RRGGBB` into `0xRRGGBB`.
+pub(crate) fn parse_hex(hex: &str) -> Option<i32> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    i32::from_str_radix(hex, 16).ok()
+}
+
+/// Formats `0xRRGGBB` as `#RRGGBB`.
+pub(crate) fn hex(rgb: i32) -> String {
+    format!("#{:06X}", rgb & 0xFF_FFFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{actual} should be {expected}"
+        );
+    }
+
+    #[test]
+    fn rgb_to_lab() {
+        let white = Lab::from_rgb(0xFFFFFF);
+        assert_close(white.l, 100.0);
+        assert!(white.a.abs() < 0.01 && white.b.abs() < 0.01);
+
+        let black = Lab::from_rgb(0x000000);
+        assert_eq!((black.l, black.a, black.b), (0.0, 0.0, 0.0));
+
+        let red = Lab::from_rgb(0xFF0000);
+        assert!((red.l - 53.24).abs() < 0.05, "{red:?}");
+        assert!((red.a - 80.09).abs() < 0.05, "{red:?}");
+        assert!((red.b - 67.20).abs() < 0.05, "{red:?}");
+    }
+
+    /// Pairs from the CIEDE2000 test data by Sharma, Wu and Dalal.
+    #[test]
+    fn ciede2000() {
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, -1.3802, -84.2814), (50.0, 0.0, -82.7485), 1.0000),
+            ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+            ((50.0, 2.49, -0.001), (50.0, -2.49, 0.0011), 7.2195),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            (
+                (60.2574, -34.0099, 36.2677),
+                (60.4626, -34.1751, 39.4387),
+                1.2644,
+            ),
+            (
+                (90.8027, -2.0831, 1.4410),
+                (91.1528, -1.6435, 0.0447),
+                1.4441,
+            ),
+        ];
+        for ((l1, a1, b1), (l2, a2, b2), expected) in pairs {
+            let one = Lab {
+                l: l1,
+                a: a1,
+                b: b1,
+            };
+            let two = Lab {
+                l: l2,
+                a: a2,
+                b: b2,
+            };
+            assert!(
+                (one.delta_e(&two) - expected).abs() < 1e-4,
+                "{one:?} {two:?}"
+            );
+            assert!((two.delta_e(&one) - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn nearest_colors() {
+        let colors = [(1, 0x0000FF), (2, 0xFF0000), (3, 0xFFFFFF), (4, 0xE01010)];
+        assert_eq!(nearest(0xFF2000, &colors, 2), vec![2, 4]);
+        assert_eq!(nearest(0xF8F8F8, &colors, 1), vec![3]);
+        assert_eq!(nearest(0x2020C0, &colors, 10)[0], 1);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(parse_hex("#ff8000"), Some(0xFF8000));
+        assert_eq!(parse_hex("FF8000"), Some(0xFF8000));
+        assert_eq!(parse_hex("#fff"), None);
+        assert_eq!(parse_hex("#gg0000"), None);
+        assert_eq!(parse_hex("+fffff"), None);
+        assert_eq!(hex(0xFF8000), "#FF8000");
+    }
+}
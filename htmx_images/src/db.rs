@@ -1,47 +1,675 @@
-use std::error::Error;
+use sqlx::{FromRow, PgPool, Row};
 
-use crate::state::{AppState, Image};
+use crate::{
+    error::BoxError,
+    img::Rendition,
+    metadata::Metadata,
+    state::{Album, AppState, Cursor, Filter, Image, ImageStatus, RenditionMeta, User},
+};
 
-pub(crate) async fn get_image(state: &AppState, id: i64) -> Result<Image, Box<dyn Error>> {
-    const QUERY: &'static str = r#"SELECT * FROM image WHERE id = $1;"#;
+/// Every column but the image data, lists never load the blobs.
+const IMAGE_COLUMNS: &str = "id, file_name, mime_type, dominant_color, created_at, status, error, \
+camera, taken_at, width, height, focal_x, focal_y, renditions_version, palette, duplicate_of, owner_id";
+
+pub(crate) async fn get_image(state: &AppState, id: i64) -> Result<Image, BoxError> {
+    let query = format!("SELECT {IMAGE_COLUMNS} FROM image WHERE id = $1;");
     let database = &state.database;
-    let img = sqlx::query_as(QUERY).bind(id).fetch_one(database).await?;
+    let img = sqlx::query_as(&query).bind(id).fetch_one(database).await?;
     Ok(img)
 }
 
-pub(crate) async fn get_all_images(state: &AppState) -> Result<Vec<Image>, Box<dyn Error>> {
-    const QUERY: &'static str = r#"
-SELECT * FROM image
-ORDER BY created_at DESC
-LIMIT 100;
+/// A rendition of an image in every format, its name is `<spec>.<format>`.
+/// Images without it get their largest rendition instead, e.g. images
+/// uploaded before the rendition was added, so only names of specs should
+/// be asked for.
+pub(crate) async fn get_renditions(
+    state: &AppState,
+    id: i64,
+    name: &str,
+) -> Result<Vec<RenditionMeta>, BoxError> {
+    const QUERY: &str = r#"
+SELECT name, mime_type FROM rendition
+WHERE image_id = $1 AND split_part(name, '.', 1) = (
+    SELECT split_part(name, '.', 1) FROM rendition
+    WHERE image_id = $1
+    ORDER BY split_part(name, '.', 1) = $2 DESC, width DESC
+    LIMIT 1
+);
 "#;
     let database = &state.database;
-    let img = sqlx::query_as(QUERY).fetch_all(database).await?;
+    let renditions = sqlx::query_as(QUERY)
+        .bind(id)
+        .bind(name)
+        .fetch_all(database)
+        .await?;
+    Ok(renditions)
+}
+
+/// A page of the gallery, newest first, starting after the cursor. The first
+/// page has its own query so that the others can use the index on
+/// `(created_at, id)`.
+pub(crate) async fn get_images(
+    state: &AppState,
+    filter: &Filter,
+    before: Option<Cursor>,
+    limit: i64,
+) -> Result<Vec<Image>, BoxError> {
+    let after_cursor = match before {
+        Some(_) => "(created_at, id) < ($4, $5) AND",
+        None => "",
+    };
+    let query = format!(
+        r#"
+SELECT {IMAGE_COLUMNS} FROM image
+WHERE {after_cursor} ($1::bigint IS NULL OR id IN (SELECT image_id FROM album_image WHERE album_id = $1))
+AND ($2::text IS NULL OR id IN (
+    SELECT image_id FROM image_tag JOIN tag ON tag.id = image_tag.tag_id WHERE tag.name = $2
+))
+ORDER BY created_at DESC, id DESC
+LIMIT $3;
+"#
+    );
+    let database = &state.database;
+    let mut query = sqlx::query_as(&query)
+        .bind(filter.album)
+        .bind(&filter.tag)
+        .bind(limit);
+    if let Some(cursor) = before {
+        query = query.bind(cursor.created_at).bind(cursor.id);
+    }
+    let img = query.fetch_all(database).await?;
     Ok(img)
 }
 
-pub(crate) async fn insert_image(
+/// The dominant colors of the processed images in the gallery.
+pub(crate) async fn get_image_colors(
     state: &AppState,
-    file_name: String,
-    mime_type: String,
-    image_data: &[u8],
-    dominant_color: i32,
-) -> Result<Image, Box<dyn Error>> {
-    const QUERY: &'static str = r#"
-INSERT INTO image (file_name, mime_type, image_data, dominant_color)
-VALUES ($1, $2, $3, $4)
-RETURNING *;
-    "#;
+    filter: &Filter,
+) -> Result<Vec<(i64, i32)>, BoxError> {
+    const QUERY: &str = r#"
+SELECT id, dominant_color FROM image
+WHERE status = 'ready' AND dominant_color IS NOT NULL
+AND ($1::bigint IS NULL OR id IN (SELECT image_id FROM album_image WHERE album_id = $1))
+AND ($2::text IS NULL OR id IN (
+    SELECT image_id FROM image_tag JOIN tag ON tag.id = image_tag.tag_id WHERE tag.name = $2
+));
+"#;
+    let database = &state.database;
+    let colors = sqlx::query_as(QUERY)
+        .bind(filter.album)
+        .bind(&filter.tag)
+        .fetch_all(database)
+        .await?;
+    Ok(colors)
+}
 
+/// The images with the given ids, in the same order.
+pub(crate) async fn get_images_by_ids(
+    state: &AppState,
+    ids: &[i64],
+) -> Result<Vec<Image>, BoxError> {
+    let query = format!(
+        r#"
+SELECT {IMAGE_COLUMNS} FROM image
+WHERE id = ANY($1)
+ORDER BY array_position($1, id);
+"#
+    );
     let database = &state.database;
+    let img = sqlx::query_as(&query).bind(ids).fetch_all(database).await?;
+    Ok(img)
+}
 
-    let img = sqlx::query_as(QUERY)
-        .bind(&file_name)
-        .bind(&mime_type)
-        .bind(image_data)
-        .bind(dominant_color)
+/// The image that looks most like a perceptual hash, if at most
+/// `max_distance` bits of their hashes differ. Images that failed to
+/// process don't count, they can be uploaded again.
+pub(crate) async fn find_duplicate(
+    state: &AppState,
+    phash: i64,
+    max_distance: u32,
+) -> Result<Option<i64>, BoxError> {
+    const QUERY: &str = r#"
+SELECT id FROM image
+WHERE bit_count((phash # $1)::bit(64)) <= $2 AND status <> $3
+ORDER BY bit_count((phash # $1)::bit(64)), id
+LIMIT 1;
+"#;
+    let database = &state.database;
+    let id = sqlx::query_scalar(QUERY)
+        .bind(phash)
+        .bind(max_distance as i64)
+        .bind(ImageStatus::Failed)
+        .fetch_optional(database)
+        .await?;
+    Ok(id)
+}
+
+/// The images that look most like an image, most similar first.
+pub(crate) async fn get_similar_images(
+    state: &AppState,
+    id: i64,
+    max_distance: u32,
+    limit: i64,
+) -> Result<Vec<Image>, BoxError> {
+    let query = format!(
+        r#"
+WITH original AS (SELECT phash FROM image WHERE id = $1)
+SELECT {IMAGE_COLUMNS} FROM image
+WHERE id <> $1 AND status = 'ready'
+AND bit_count((phash # (SELECT phash FROM original))::bit(64)) <= $2
+ORDER BY bit_count((phash # (SELECT phash FROM original))::bit(64)), created_at DESC
+LIMIT $3;
+"#
+    );
+    let database = &state.database;
+    let img = sqlx::query_as(&query)
+        .bind(id)
+        .bind(max_distance as i64)
+        .bind(limit)
+        .fetch_all(database)
+        .await?;
+    Ok(img)
+}
+
+/// A new upload, see `insert_image`.
+pub(crate) struct NewImage<'a> {
+    pub(crate) file_name: String,
+    pub(crate) mime_type: String,
+    pub(crate) metadata: &'a Metadata,
+    /// The size of the upright image.
+    pub(crate) size: (u32, u32),
+    pub(crate) phash: Option<i64>,
+    pub(crate) duplicate_of: Option<i64>,
+    pub(crate) owner_id: i64,
+}
+
+/// Inserts a new upload, it's processed in the background.
+pub(crate) async fn insert_image(state: &AppState, image: NewImage<'_>) -> Result<Image, BoxError> {
+    let query = format!(
+        r#"
+INSERT INTO image (
+    file_name, mime_type, status, camera, taken_at, width, height, phash, duplicate_of, owner_id
+)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+RETURNING {IMAGE_COLUMNS};
+    "#
+    );
+
+    let database = &state.database;
+
+    let (width, height) = image.size;
+    let img = sqlx::query_as(&query)
+        .bind(&image.file_name)
+        .bind(&image.mime_type)
+        .bind(ImageStatus::Processing)
+        .bind(&image.metadata.camera)
+        .bind(image.metadata.taken_at)
+        .bind(width as i32)
+        .bind(height as i32)
+        .bind(image.phash)
+        .bind(image.duplicate_of)
+        .bind(image.owner_id)
         .fetch_one(database)
         .await?;
 
     Ok(img)
 }
+
+pub(crate) async fn rename_image(
+    state: &AppState,
+    id: i64,
+    file_name: &str,
+) -> Result<Image, BoxError> {
+    let query = format!("UPDATE image SET file_name = $2 WHERE id = $1 RETURNING {IMAGE_COLUMNS};");
+    let database = &state.database;
+    let img = sqlx::query_as(&query)
+        .bind(id)
+        .bind(file_name)
+        .fetch_one(database)
+        .await?;
+    Ok(img)
+}
+
+/// Centers the thumbnail on a new focal point, the renditions are generated
+/// again. Images that are being processed aren't changed, `None` is returned
+/// so that two renders of the same image never run at once.
+pub(crate) async fn set_focal_point(
+    state: &AppState,
+    id: i64,
+    (focal_x, focal_y): (f32, f32),
+) -> Result<Option<Image>, BoxError> {
+    let query = format!(
+        r#"
+UPDATE image
+SET focal_x = $2, focal_y = $3, status = $4, error = NULL,
+    renditions_version = renditions_version + 1
+WHERE id = $1 AND status <> $4
+RETURNING {IMAGE_COLUMNS};
+    "#
+    );
+    let database = &state.database;
+    let img = sqlx::query_as(&query)
+        .bind(id)
+        .bind(focal_x)
+        .bind(focal_y)
+        .bind(ImageStatus::Processing)
+        .fetch_optional(database)
+        .await?;
+    Ok(img)
+}
+
+/// Images that are in the given status.
+pub(crate) async fn get_images_by_status(
+    state: &AppState,
+    status: ImageStatus,
+) -> Result<Vec<Image>, BoxError> {
+    let query = format!("SELECT {IMAGE_COLUMNS} FROM image WHERE status = $1 ORDER BY id;");
+    let database = &state.database;
+    let images = sqlx::query_as(&query)
+        .bind(status)
+        .fetch_all(database)
+        .await?;
+    Ok(images)
+}
+
+/// Adds or updates the renditions of an image, their data goes to the blob
+/// store. Renditions that already exist keep their data until it's replaced.
+pub(crate) async fn insert_renditions(
+    state: &AppState,
+    id: i64,
+    palette: &[i32],
+    renditions: &[Rendition],
+) -> Result<(), BoxError> {
+    const RENDITION_QUERY: &str = r#"
+INSERT INTO rendition (image_id, name, mime_type, width, height)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (image_id, name) DO UPDATE
+SET mime_type = EXCLUDED.mime_type, width = EXCLUDED.width, height = EXCLUDED.height;
+    "#;
+    const COLOR_QUERY: &str =
+        r#"UPDATE image SET dominant_color = $2[1], palette = $2 WHERE id = $1;"#;
+
+    let mut transaction = state.database.begin().await?;
+
+    for rendition in renditions {
+        sqlx::query(RENDITION_QUERY)
+            .bind(id)
+            .bind(&rendition.name)
+            .bind(&rendition.mime_type)
+            .bind(rendition.width as i32)
+            .bind(rendition.height as i32)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    sqlx::query(COLOR_QUERY)
+        .bind(id)
+        .bind(palette)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+pub(crate) async fn set_status(
+    state: &AppState,
+    id: i64,
+    status: ImageStatus,
+    error: Option<&str>,
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"UPDATE image SET status = $2, error = $3 WHERE id = $1;"#;
+    let database = &state.database;
+    sqlx::query(QUERY)
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// The names of the renditions of an image, to find their blobs.
+pub(crate) async fn get_rendition_names(
+    state: &AppState,
+    id: i64,
+) -> Result<Vec<String>, BoxError> {
+    const QUERY: &str = r#"SELECT name FROM rendition WHERE image_id = $1;"#;
+    let database = &state.database;
+    let names = sqlx::query_scalar(QUERY)
+        .bind(id)
+        .fetch_all(database)
+        .await?;
+    Ok(names)
+}
+
+/// Deletes the renditions of an image that aren't in `names`, but not their
+/// blobs.
+pub(crate) async fn delete_other_renditions(
+    state: &AppState,
+    id: i64,
+    names: &[String],
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"DELETE FROM rendition WHERE image_id = $1 AND NOT name = ANY($2);"#;
+    let database = &state.database;
+    sqlx::query(QUERY)
+        .bind(id)
+        .bind(names)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// Deletes an image and its renditions, but not their blobs.
+pub(crate) async fn delete_image(state: &AppState, id: i64) -> Result<(), BoxError> {
+    const QUERY: &str = r#"DELETE FROM image WHERE id = $1;"#;
+    let database = &state.database;
+    sqlx::query(QUERY).bind(id).execute(database).await?;
+    Ok(())
+}
+
+/// All albums by name.
+pub(crate) async fn get_albums(state: &AppState) -> Result<Vec<Album>, BoxError> {
+    const QUERY: &str = r#"
+SELECT album.id, album.name, album.owner_id, COUNT(album_image.image_id) AS image_count
+FROM album
+LEFT JOIN album_image ON album_image.album_id = album.id
+GROUP BY album.id
+ORDER BY album.name, album.id;
+"#;
+    let database = &state.database;
+    let albums = sqlx::query_as(QUERY).fetch_all(database).await?;
+    Ok(albums)
+}
+
+pub(crate) async fn get_album(state: &AppState, id: i64) -> Result<Album, BoxError> {
+    const QUERY: &str = r#"
+SELECT album.id, album.name, album.owner_id, COUNT(album_image.image_id) AS image_count
+FROM album
+LEFT JOIN album_image ON album_image.album_id = album.id
+WHERE album.id = $1
+GROUP BY album.id;
+"#;
+    let database = &state.database;
+    let album = sqlx::query_as(QUERY).bind(id).fetch_one(database).await?;
+    Ok(album)
+}
+
+pub(crate) async fn insert_album(
+    state: &AppState,
+    name: &str,
+    owner_id: i64,
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"INSERT INTO album (name, owner_id) VALUES ($1, $2);"#;
+    let database = &state.database;
+    sqlx::query(QUERY)
+        .bind(name)
+        .bind(owner_id)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// Fails with `RowNotFound` for an unknown album.
+pub(crate) async fn rename_album(state: &AppState, id: i64, name: &str) -> Result<(), BoxError> {
+    const QUERY: &str = r#"UPDATE album SET name = $2 WHERE id = $1;"#;
+    let database = &state.database;
+    let res = sqlx::query(QUERY)
+        .bind(id)
+        .bind(name)
+        .execute(database)
+        .await?;
+    if res.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+    Ok(())
+}
+
+/// The ids of the albums an image is in.
+pub(crate) async fn get_image_album_ids(
+    state: &AppState,
+    image_id: i64,
+) -> Result<Vec<i64>, BoxError> {
+    const QUERY: &str = r#"SELECT album_id FROM album_image WHERE image_id = $1;"#;
+    let database = &state.database;
+    let ids = sqlx::query_scalar(QUERY)
+        .bind(image_id)
+        .fetch_all(database)
+        .await?;
+    Ok(ids)
+}
+
+/// Fails with `RowNotFound` when the album or the image doesn't exist.
+pub(crate) async fn add_album_image(
+    state: &AppState,
+    album_id: i64,
+    image_id: i64,
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"
+INSERT INTO album_image (album_id, image_id) VALUES ($1, $2)
+ON CONFLICT DO NOTHING;
+"#;
+    let database = &state.database;
+    let res = sqlx::query(QUERY)
+        .bind(album_id)
+        .bind(image_id)
+        .execute(database)
+        .await;
+    match res {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(err)) if err.is_foreign_key_violation() => {
+            Err(sqlx::Error::RowNotFound.into())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub(crate) async fn remove_album_image(
+    state: &AppState,
+    album_id: i64,
+    image_id: i64,
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"DELETE FROM album_image WHERE album_id = $1 AND image_id = $2;"#;
+    let database = &state.database;
+    sqlx::query(QUERY)
+        .bind(album_id)
+        .bind(image_id)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// The tags of an image by name.
+pub(crate) async fn get_image_tags(
+    state: &AppState,
+    image_id: i64,
+) -> Result<Vec<String>, BoxError> {
+    const QUERY: &str = r#"
+SELECT tag.name FROM tag
+JOIN image_tag ON image_tag.tag_id = tag.id
+WHERE image_tag.image_id = $1
+ORDER BY tag.name;
+"#;
+    let database = &state.database;
+    let tags = sqlx::query_scalar(QUERY)
+        .bind(image_id)
+        .fetch_all(database)
+        .await?;
+    Ok(tags)
+}
+
+/// Tags an image, the tag is created when it's new.
+pub(crate) async fn add_image_tag(
+    state: &AppState,
+    image_id: i64,
+    tag: &str,
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"
+WITH tag AS (
+    INSERT INTO tag (name) VALUES ($2)
+    ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+    RETURNING id
+)
+INSERT INTO image_tag (image_id, tag_id)
+SELECT $1, id FROM tag
+ON CONFLICT DO NOTHING;
+"#;
+    let database = &state.database;
+    sqlx::query(QUERY)
+        .bind(image_id)
+        .bind(tag)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// Removes a tag from an image, tags without images are deleted.
+pub(crate) async fn remove_image_tag(
+    state: &AppState,
+    image_id: i64,
+    tag: &str,
+) -> Result<(), BoxError> {
+    const UNTAG_QUERY: &str = r#"
+DELETE FROM image_tag
+USING tag
+WHERE image_tag.tag_id = tag.id AND image_tag.image_id = $1 AND tag.name = $2;
+"#;
+    const CLEANUP_QUERY: &str = r#"
+DELETE FROM tag
+WHERE name = $1 AND NOT EXISTS (SELECT 1 FROM image_tag WHERE tag_id = tag.id);
+"#;
+
+    let mut transaction = state.database.begin().await?;
+    sqlx::query(UNTAG_QUERY)
+        .bind(image_id)
+        .bind(tag)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query(CLEANUP_QUERY)
+        .bind(tag)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Tags starting with the prefix, for autocompletion.
+pub(crate) async fn search_tags(
+    state: &AppState,
+    prefix: &str,
+    limit: i64,
+) -> Result<Vec<String>, BoxError> {
+    const QUERY: &str = r#"
+SELECT name FROM tag
+WHERE left(name, length($1)) = $1
+ORDER BY name
+LIMIT $2;
+"#;
+    let database = &state.database;
+    let tags = sqlx::query_scalar(QUERY)
+        .bind(prefix)
+        .bind(limit)
+        .fetch_all(database)
+        .await?;
+    Ok(tags)
+}
+
+/// Creates an account, admins are made with the `make-admin` command.
+pub(crate) async fn insert_user(
+    state: &AppState,
+    name: &str,
+    password_hash: &str,
+) -> Result<User, BoxError> {
+    const QUERY: &str = r#"
+INSERT INTO account (name, password_hash)
+VALUES ($1, $2)
+RETURNING id, name, is_admin;
+"#;
+    let database = &state.database;
+    let user = sqlx::query_as(QUERY)
+        .bind(name)
+        .bind(password_hash)
+        .fetch_one(database)
+        .await?;
+    Ok(user)
+}
+
+/// Makes an account an admin, `false` if there is no account with the name.
+/// The command runs before the app, so it only has the database.
+pub(crate) async fn make_admin(database: &PgPool, name: &str) -> Result<bool, BoxError> {
+    const QUERY: &str = r#"UPDATE account SET is_admin = TRUE WHERE name = $1;"#;
+    let res = sqlx::query(QUERY).bind(name).execute(database).await?;
+    Ok(res.rows_affected() > 0)
+}
+
+pub(crate) async fn get_user(state: &AppState, id: i64) -> Result<Option<User>, BoxError> {
+    const QUERY: &str = r#"SELECT id, name, is_admin FROM account WHERE id = $1;"#;
+    let database = &state.database;
+    let user = sqlx::query_as(QUERY)
+        .bind(id)
+        .fetch_optional(database)
+        .await?;
+    Ok(user)
+}
+
+/// An account with its password hash, to log in.
+pub(crate) async fn get_user_by_name(
+    state: &AppState,
+    name: &str,
+) -> Result<Option<(User, String)>, BoxError> {
+    const QUERY: &str = r#"SELECT id, name, is_admin, password_hash FROM account WHERE name = $1;"#;
+    let database = &state.database;
+    let row = sqlx::query(QUERY)
+        .bind(name)
+        .fetch_optional(database)
+        .await?;
+    row.map(|row| Ok((User::from_row(&row)?, row.try_get("password_hash")?)))
+        .transpose()
+}
+
+pub(crate) async fn insert_session(
+    state: &AppState,
+    token: &str,
+    user_id: i64,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), BoxError> {
+    const QUERY: &str = r#"
+INSERT INTO session (token, account_id, expires_at) VALUES ($1, $2, $3);
+"#;
+    let database = &state.database;
+    sqlx::query(QUERY)
+        .bind(token)
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// The account of a session that hasn't expired.
+pub(crate) async fn get_session_user(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<User>, BoxError> {
+    const QUERY: &str = r#"
+SELECT account.id, account.name, account.is_admin
+FROM session
+JOIN account ON account.id = session.account_id
+WHERE session.token = $1 AND session.expires_at > now();
+"#;
+    let database = &state.database;
+    let user = sqlx::query_as(QUERY)
+        .bind(token)
+        .fetch_optional(database)
+        .await?;
+    Ok(user)
+}
+
+/// Ends a session, expired sessions are removed along the way.
+pub(crate) async fn delete_session(state: &AppState, token: &str) -> Result<(), BoxError> {
+    const QUERY: &str = r#"DELETE FROM session WHERE token = $1 OR expires_at <= now();"#;
+    let database = &state.database;
+    sqlx::query(QUERY).bind(token).execute(database).await?;
+    Ok(())
+}
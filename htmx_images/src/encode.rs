@@ -0,0 +1,325 @@
+use std::sync::Arc;
+
+use image::{codecs::jpeg, DynamicImage, GenericImageView};
+use mediatype::MediaType;
+use webp::{AnimEncoder, AnimFrame, WebPConfig};
+
+use crate::{error::BoxError, img::Animation, mime, riff};
+
+/// From 1 (slowest, smallest) to 10, AVIF is slow to encode.
+const AVIF_SPEED: u8 = 6;
+
+/// Encodes renditions in one format, every rendition is kept in all the
+/// formats that are configured.
+pub(crate) trait Encoder: Send + Sync {
+    fn mime_type(&self) -> MediaType<'static>;
+
+    /// The end of the names of the renditions, e.g. `thumbnail.webp`.
+    fn extension(&self) -> &'static str;
+
+    fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, BoxError>;
+
+    /// Formats without animations keep the first frame.
+    fn encode_animation(&self, animation: &Animation) -> Result<Vec<u8>, BoxError> {
+        self.encode(&animation.frames[0].image)
+    }
+}
+
+/// Selects encoders from a list like `avif:60,webp:70,jpeg`, the quality
+/// is from 1 to 100, or 0 to 100 for WebP. The first format is served to
+/// browsers that don't say which ones they accept.
+pub(crate) fn from_config(config: &str) -> Result<Vec<Arc<dyn Encoder>>, BoxError> {
+    config
+        .split(',')
+        .map(|format| parse(format.trim()))
+        .collect()
+}
+
+fn parse(format: &str) -> Result<Arc<dyn Encoder>, BoxError> {
+    let (name, quality) = match format.split_once(':') {
+        Some((name, quality)) => (name, Some(quality)),
+        None => (format, None),
+    };
+    let (lowest, default) = match name {
+        "webp" => (0.0, 70.0),
+        "avif" => (1.0, 60.0),
+        "jpeg" => (1.0, 80.0),
+        _ => return Err(format!("unknown format `{name}`, expected webp, avif or jpeg").into()),
+    };
+    let quality = match quality.map(str::parse::<f32>) {
+        Some(Ok(quality)) if (lowest..=100.0).contains(&quality) => quality,
+        Some(_) => {
+            return Err(format!("the quality in `{format}` should be from {lowest} to 100").into())
+        }
+        None => default,
+    };
+
+    let encoder: Arc<dyn Encoder> = match name {
+        "webp" => Arc::new(WebpEncoder { quality }),
+        "avif" => Arc::new(AvifEncoder { quality }),
+        _ => Arc::new(JpegEncoder {
+            quality: quality.round() as u8,
+        }),
+    };
+    Ok(encoder)
+}
+
+/// Lossy WebP, the only format that keeps animations.
+pub(crate) struct WebpEncoder {
+    pub(crate) quality: f32,
+}
+
+impl Encoder for WebpEncoder {
+    fn mime_type(&self) -> MediaType<'static> {
+        mime::IMAGE_WEBP
+    }
+
+    fn extension(&self) -> &'static str {
+        "webp"
+    }
+
+    fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, BoxError> {
+        let encoder = webp::Encoder::from_image(img)?;
+        Ok(encoder.encode(self.quality).to_vec())
+    }
+
+    fn encode_animation(&self, animation: &Animation) -> Result<Vec<u8>, BoxError> {
+        // Construct WebP animation encoder with lossy config.
+        let mut config = WebPConfig::new().map_err(|_| "could not create webp config")?;
+        config.lossless = 0;
+        config.alpha_compression = 0;
+        config.quality = self.quality;
+
+        let (width, height) = animation.frames[0].image.dimensions();
+        let mut anim_encoder = AnimEncoder::new(width, height, &config);
+        anim_encoder.set_loop_count(animation.loop_count as i32);
+
+        // Copy all frames into WebP, they start when the one before ends.
+        let mut timestamp = 0;
+        for frame in &animation.frames {
+            let anim = AnimFrame::from_image(&frame.image, timestamp as i32)?;
+            anim_encoder.add_frame(anim);
+            timestamp += frame.delay;
+        }
+
+        // Write bytes from encoder.
+        let mut data = anim_encoder
+            .try_encode()
+            .map_err(|err| format!("could not encode animation: {err:?}"))?
+            .to_vec();
+        set_duration(&mut data, timestamp);
+        Ok(data)
+    }
+}
+
+/// The encoder ends an animation at 0 ms, which libwebp ignores and shows
+/// the last frame for as long as the average frame instead. The duration of
+/// the last `ANMF` chunk is changed so that the animation lasts `duration`
+/// ms, frames the encoder merged included.
+fn set_duration(data: &mut [u8], duration: u32) {
+    // The 24 bit duration of a frame, after its position and size.
+    const DURATION_OFFSET: usize = 12;
+
+    let frames = riff::chunks(data)
+        .filter(|chunk| chunk.fourcc == b"ANMF" && chunk.data.len() >= DURATION_OFFSET + 3)
+        .map(|chunk| chunk.data.start + DURATION_OFFSET)
+        .collect::<Vec<_>>();
+
+    let read = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], 0]);
+    let Some((&last, others)) = frames.split_last() else {
+        return;
+    };
+    let others = others.iter().map(|&at| read(at)).sum::<u32>();
+    if let Some(last_duration) = duration.checked_sub(others).filter(|&d| d > 0) {
+        let bytes = last_duration.min(0xFF_FFFF).to_le_bytes();
+        data[last..last + 3].copy_from_slice(&bytes[..3]);
+    }
+}
+
+/// AVIF is smaller than WebP for the same quality, but slower to encode.
+pub(crate) struct AvifEncoder {
+    pub(crate) quality: f32,
+}
+
+impl Encoder for AvifEncoder {
+    fn mime_type(&self) -> MediaType<'static> {
+        mime::IMAGE_AVIF
+    }
+
+    fn extension(&self) -> &'static str {
+        "avif"
+    }
+
+    fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, BoxError> {
+        let rgba = img.to_rgba8();
+        let pixels = rgba
+            .pixels()
+            .map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3]))
+            .collect::<Vec<_>>();
+        let buffer = ravif::Img::new(
+            pixels.as_slice(),
+            rgba.width() as usize,
+            rgba.height() as usize,
+        );
+
+        let encoded = ravif::Encoder::new()
+            .with_quality(self.quality)
+            .with_alpha_quality(self.quality)
+            .with_speed(AVIF_SPEED)
+            .encode_rgba(buffer)?;
+        Ok(encoded.avif_file)
+    }
+}
+
+/// For browsers that support neither WebP nor AVIF, transparency is lost.
+pub(crate) struct JpegEncoder {
+    pub(crate) quality: u8,
+}
+
+impl Encoder for JpegEncoder {
+    fn mime_type(&self) -> MediaType<'static> {
+        mime::IMAGE_JPEG
+    }
+
+    fn extension(&self) -> &'static str {
+        "jpeg"
+    }
+
+    fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, BoxError> {
+        let mut data = vec![];
+        let encoder = jpeg::JpegEncoder::new_with_quality(&mut data, self.quality);
+        DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use webp::AnimDecoder;
+
+    use super::*;
+    use crate::img::Frame;
+
+    /// A gradient, so that lossy formats keep roughly the same colors.
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255])
+        }))
+    }
+
+    /// Frames that all look different, so that the encoder keeps them.
+    fn animation(delays: &[u32], loop_count: u32) -> Animation {
+        let img = gradient(32, 32);
+        let frames = [img.clone(), img.fliph(), img.flipv(), img.rotate180()];
+        Animation {
+            frames: delays
+                .iter()
+                .zip(frames.into_iter().cycle())
+                .map(|(&delay, image)| Frame { image, delay })
+                .collect(),
+            loop_count,
+        }
+    }
+
+    fn assert_round_trip(encoder: &dyn Encoder, format: ImageFormat) {
+        let img = gradient(64, 48);
+        let data = encoder.encode(&img).unwrap();
+        assert_eq!(image::guess_format(&data).unwrap(), format);
+
+        let decoded = image::load_from_memory_with_format(&data, format).unwrap();
+        assert_eq!(decoded.dimensions(), (64, 48));
+        let (original, decoded) = (img.get_pixel(40, 30), decoded.get_pixel(40, 30));
+        for channel in 0..3 {
+            let difference = original[channel].abs_diff(decoded[channel]);
+            assert!(difference < 16, "{original:?} became {decoded:?}");
+        }
+    }
+
+    #[test]
+    fn webp_round_trip() {
+        assert_round_trip(&WebpEncoder { quality: 70.0 }, ImageFormat::WebP);
+    }
+
+    #[test]
+    fn webp_animation_keeps_its_timing() {
+        let data = WebpEncoder { quality: 70.0 }
+            .encode_animation(&animation(&[50, 120, 300], 3))
+            .unwrap();
+
+        let decoded = AnimDecoder::new(&data).decode().unwrap();
+        assert_eq!(decoded.loop_count, 3);
+        // The timestamps are when the frames end.
+        let ends = decoded
+            .into_iter()
+            .map(|frame| frame.get_time_ms())
+            .collect::<Vec<_>>();
+        assert_eq!(ends, [50, 170, 470]);
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        assert_round_trip(&JpegEncoder { quality: 80 }, ImageFormat::Jpeg);
+    }
+
+    /// There is no AVIF decoder, so the size is read from the `ispe`
+    /// property of the container instead.
+    #[test]
+    fn avif_round_trip() {
+        let data = AvifEncoder { quality: 60.0 }
+            .encode(&gradient(64, 48))
+            .unwrap();
+        assert_eq!(&data[4..12], b"ftypavif");
+
+        let ispe = data
+            .windows(4)
+            .position(|window| window == b"ispe")
+            .expect("should have an image size");
+        let size = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+        assert_eq!((size(ispe + 8), size(ispe + 12)), (64, 48));
+    }
+
+    #[test]
+    fn still_formats_keep_the_first_frame() {
+        let data = JpegEncoder { quality: 80 }
+            .encode_animation(&animation(&[100, 100], 0))
+            .unwrap();
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!(decoded.dimensions(), (32, 32));
+        // The gradient goes from black to red, the flipped frame the other way.
+        assert!(decoded.get_pixel(2, 16)[0] < 64);
+    }
+
+    #[test]
+    fn formats_from_config() {
+        let encoders = from_config("avif:50, webp,jpeg:90").unwrap();
+        let extensions = encoders
+            .iter()
+            .map(|encoder| encoder.extension())
+            .collect::<Vec<_>>();
+        assert_eq!(extensions, ["avif", "webp", "jpeg"]);
+
+        assert!(from_config("png").is_err());
+        assert!(from_config("").is_err());
+    }
+
+    #[test]
+    fn invalid_qualities() {
+        for config in [
+            "avif:0",
+            "jpeg:0",
+            "webp:-1",
+            "webp:200",
+            "avif:best",
+            "jpeg:",
+        ] {
+            assert!(from_config(config).is_err(), "{config} should be invalid");
+        }
+
+        // The lowest qualities still encode.
+        for config in ["avif:1", "jpeg:1", "webp:0"] {
+            let encoders = from_config(config).unwrap();
+            assert!(encoders[0].encode(&gradient(16, 16)).is_ok());
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+
+use axum::{
+    extract::multipart::MultipartError,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::markup;
+
+/// Any error, it can be held across an `.await` and sent between tasks.
+pub(crate) type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Why an upload was rejected.
+#[derive(Debug)]
+pub(crate) enum UploadError {
+    /// The body isn't a multipart form or couldn't be read.
+    Multipart(MultipartError),
+    NoFiles,
+    TooLarge {
+        limit: usize,
+    },
+    /// The data isn't a JPEG, PNG, WebP or GIF image, whatever the client says.
+    UnsupportedFormat,
+    TooManyPixels {
+        width: u32,
+        height: u32,
+    },
+    /// One side is much longer than the other, see `MAX_ASPECT_RATIO`.
+    TooNarrow {
+        width: u32,
+        height: u32,
+    },
+    /// All frames of the animation together are too large.
+    TooManyFrames {
+        frames: u32,
+    },
+    /// The image looks like an earlier upload.
+    Duplicate {
+        id: i64,
+    },
+    /// Too many images are waiting to be processed.
+    QueueFull,
+    Internal(String),
+}
+
+impl UploadError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            UploadError::Multipart(err) => err.status(),
+            UploadError::NoFiles => StatusCode::BAD_REQUEST,
+            UploadError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            UploadError::UnsupportedFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            UploadError::TooManyPixels { .. }
+            | UploadError::TooNarrow { .. }
+            | UploadError::TooManyFrames { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            UploadError::Duplicate { .. } => StatusCode::CONFLICT,
+            UploadError::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            UploadError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Multipart(err) => {
+                write!(f, "the upload couldn't be read: {}", err.body_text())
+            }
+            UploadError::NoFiles => f.write_str("no files were uploaded"),
+            UploadError::TooLarge { limit } => {
+                write!(f, "files can't be larger than {} MB", limit / 1024 / 1024)
+            }
+            UploadError::UnsupportedFormat => {
+                f.write_str("only JPEG, PNG, WebP and GIF images can be uploaded")
+            }
+            UploadError::TooManyPixels { width, height } => {
+                write!(f, "the image is too large ({width}x{height})")
+            }
+            UploadError::TooNarrow { width, height } => {
+                write!(f, "the image is too narrow ({width}x{height})")
+            }
+            UploadError::TooManyFrames { frames } => {
+                write!(f, "the animation has too many frames ({frames})")
+            }
+            UploadError::Duplicate { id } => {
+                write!(f, "the image looks like image {id}, which is already there")
+            }
+            UploadError::QueueFull => {
+                f.write_str("too many images are being processed, try again later")
+            }
+            UploadError::Internal(err) => write!(f, "the upload failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// Shown above the grid, htmx swaps error responses of the upload form.
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        (
+            self.status(),
+            [
+                ("HX-Retarget", "#upload-alerts"),
+                ("HX-Reswap", "innerHTML"),
+            ],
+            markup::alert(&self.to_string()),
+        )
+            .into_response()
+    }
+}
@@ -1,15 +1,34 @@
 use axum::{
-    extract::{Multipart, Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
+    extract::{multipart::Field, Form, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
 };
+use maud::html;
+use serde::Deserialize;
 
-use crate::{db, img, markup, state::AppState};
+use crate::{
+    auth::{self, AuthError},
+    blob::BlobKey,
+    color,
+    db::{self, NewImage},
+    error::{BoxError, UploadError},
+    img, markup,
+    metadata::{self, Metadata},
+    mime,
+    state::{
+        normalize_tag, Album, AppState, Cursor, DuplicatePolicy, Duplicates, Filter, Image,
+        RenditionMeta, User,
+    },
+};
+
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
 
-pub(crate) async fn index() -> maud::Markup {
-    crate::markup::home()
+pub(crate) async fn index(user: Option<User>) -> maud::Markup {
+    crate::markup::home(user.as_ref())
 }
 
+/// Placeholders poll this until the image is processed, errors replace
+/// them so that they stop.
 pub(crate) async fn image(State(state): State<AppState>, Path(id): Path<i64>) -> Response {
     let res = db::get_image(&state, id).await;
     let img = match res {
@@ -17,86 +36,913 @@ pub(crate) async fn image(State(state): State<AppState>, Path(id): Path<i64>) ->
             tracing::info!("getting image {id}");
             img
         }
-        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Err(err) => {
+            let status = not_found_or_error(err).status();
+            let error = match status {
+                StatusCode::NOT_FOUND => "the image was deleted",
+                _ => "the image couldn't be loaded",
+            };
+            return (status, markup::image_error(id, error)).into_response();
+        }
     };
     (StatusCode::OK, markup::image(&img)).into_response()
 }
 
-pub(crate) async fn image_modal(State(state): State<AppState>, Path(id): Path<i64>) -> Response {
-    let res = db::get_image(&state, id).await;
-    let img = match res {
+/// The original upload.
+pub(crate) async fn image_raw(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    image_data(&state, id, None, &headers).await
+}
+
+/// A rendition of the image, e.g. the thumbnail.
+pub(crate) async fn image_rendition(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(i64, String)>,
+    headers: HeaderMap,
+) -> Response {
+    image_data(&state, id, Some(&name), &headers).await
+}
+
+/// Images never change so their data is cached for a year. Renditions are
+/// kept in several formats, the one the browser prefers is sent.
+async fn image_data(
+    state: &AppState,
+    id: i64,
+    rendition: Option<&str>,
+    headers: &HeaderMap,
+) -> Response {
+    let img = match db::get_image(state, id).await {
+        Ok(img) => img,
+        Err(err) => return not_found_or_error(err),
+    };
+
+    let (key, mime_type) = match rendition {
+        Some(name) if !state.renditions.iter().any(|spec| spec.name == name) => {
+            return (StatusCode::NOT_FOUND, "unknown rendition").into_response()
+        }
+        Some(name) => match db::get_renditions(state, id, name).await {
+            Ok(renditions) if renditions.is_empty() => {
+                return (StatusCode::NOT_FOUND, "image not found").into_response()
+            }
+            Ok(mut renditions) => {
+                let rendition =
+                    renditions.swap_remove(negotiate_rendition(state, &renditions, headers));
+                (BlobKey::Rendition(id, rendition.name), rendition.mime_type)
+            }
+            Err(err) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        },
+        None => (BlobKey::Original(id), img.mime_type.clone()),
+    };
+
+    let etag = match &key {
+        BlobKey::Rendition(_, name) => img.etag(Some(name)),
+        BlobKey::Original(_) => img.etag(None),
+    };
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, CACHE_CONTROL.to_string()),
+        (header::VARY, header::ACCEPT.to_string()),
+    ];
+
+    // The browser already has it, don't load the data.
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    let data = match state.blobs.get(&key).await {
+        Ok(Some(data)) => data,
+        Ok(None) => return (StatusCode::NOT_FOUND, "image not found").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, mime_type)],
+        cache_headers,
+        data,
+    )
+        .into_response()
+}
+
+/// The rendition in the format the browser prefers, the configured order
+/// decides between formats it likes as much.
+fn negotiate_rendition(
+    state: &AppState,
+    renditions: &[RenditionMeta],
+    headers: &HeaderMap,
+) -> usize {
+    let preference = |rendition: &RenditionMeta| {
+        state
+            .encoders
+            .iter()
+            .position(|encoder| encoder.mime_type().to_string() == rendition.mime_type)
+            .unwrap_or(state.encoders.len())
+    };
+    let mut order = (0..renditions.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| preference(&renditions[i]));
+
+    let types = order
+        .iter()
+        .map(|&i| renditions[i].mime_type.as_str())
+        .collect::<Vec<_>>();
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    order[mime::negotiate(accept, &types)]
+}
+
+/// How many of the 64 bits of the perceptual hashes of similar images may
+/// differ, more than for duplicates.
+const SIMILAR_DISTANCE: u32 = 12;
+const SIMILAR_IMAGES: i64 = 6;
+
+pub(crate) async fn image_modal(
+    State(state): State<AppState>,
+    user: Option<User>,
+    Path(id): Path<i64>,
+) -> Response {
+    let img = match db::get_image(&state, id).await {
         Ok(img) => {
             tracing::info!("getting image {id}");
             img
         }
+        Err(err) => return not_found_or_error(err),
+    };
+    let owner = match img.owner_id {
+        Some(owner_id) => match db::get_user(&state, owner_id).await {
+            Ok(owner) => owner,
+            Err(err) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            }
+        },
+        None => None,
+    };
+    let tags = match db::get_image_tags(&state, id).await {
+        Ok(tags) => tags,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let albums = match image_albums(&state, id).await {
+        Ok(albums) => albums,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let similar = match db::get_similar_images(&state, id, SIMILAR_DISTANCE, SIMILAR_IMAGES).await {
+        Ok(similar) => similar,
         Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     };
-    (StatusCode::OK, markup::image_modal(&img)).into_response()
+    (
+        StatusCode::OK,
+        markup::image_modal(
+            &img,
+            owner.as_ref(),
+            user.as_ref().filter(|user| user.can_edit(&img)),
+            &tags,
+            albums,
+            &similar,
+        ),
+    )
+        .into_response()
 }
 
-pub(crate) async fn images(State(state): State<AppState>) -> Response {
-    let res = db::get_all_images(&state).await;
-    let images = match res {
+/// Images per page of the gallery.
+const PAGE_SIZE: usize = 30;
+/// Images found by color are ranked, not paged.
+const COLOR_RESULTS: usize = 60;
+
+#[derive(Deserialize)]
+pub(crate) struct ImagesQuery {
+    /// Only images older than this, the next page.
+    before: Option<Cursor>,
+    album: Option<i64>,
+    tag: Option<String>,
+    /// Orders the images by how close their dominant color is, `#RRGGBB`.
+    color: Option<String>,
+}
+
+pub(crate) async fn images(
+    State(state): State<AppState>,
+    Query(query): Query<ImagesQuery>,
+) -> Response {
+    // The tag ends up in the urls of the next pages.
+    let tag = match query.tag.as_deref().map(normalize_tag) {
+        Some(None) => return (StatusCode::BAD_REQUEST, "invalid tag").into_response(),
+        tag => tag.flatten(),
+    };
+    let filter = Filter {
+        album: query.album,
+        tag,
+    };
+
+    if let Some(color) = query.color.filter(|color| !color.is_empty()) {
+        let Some(color) = color::parse_hex(&color) else {
+            return (StatusCode::BAD_REQUEST, format!("invalid color `{color}`")).into_response();
+        };
+        return images_by_color(&state, &filter, color).await;
+    }
+
+    // One more image tells if there is a next page.
+    let res = db::get_images(&state, &filter, query.before, PAGE_SIZE as i64 + 1).await;
+    let mut images = match res {
         Ok(images) => {
             tracing::info!("getting all images");
             images
         }
         Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     };
-    (StatusCode::OK, markup::images(&images)).into_response()
+
+    let next = if images.len() > PAGE_SIZE {
+        images.truncate(PAGE_SIZE);
+        images
+            .last()
+            .map(|image| filter.images_url(Some(image.cursor())))
+    } else {
+        None
+    };
+    (StatusCode::OK, markup::images(&images, next)).into_response()
 }
 
-pub(crate) async fn upload_image(
+/// The images closest to a color, compared as people see colors.
+async fn images_by_color(state: &AppState, filter: &Filter, color: i32) -> Response {
+    let colors = match db::get_image_colors(state, filter).await {
+        Ok(colors) => colors,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let ids = color::nearest(color, &colors, COLOR_RESULTS);
+
+    match db::get_images_by_ids(state, &ids).await {
+        Ok(images) => {
+            tracing::info!("getting images by color {}", color::hex(color));
+            (StatusCode::OK, markup::images(&images, None)).into_response()
+        }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AlbumForm {
+    name: String,
+}
+
+impl AlbumForm {
+    fn name(&self) -> Result<&str, String> {
+        const MAX_LEN: usize = 100;
+
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_LEN {
+            return Err(format!("album names should have 1 to {MAX_LEN} characters"));
+        }
+        Ok(name)
+    }
+}
+
+/// The albums above the gallery.
+pub(crate) async fn albums(State(state): State<AppState>) -> Response {
+    match db::get_albums(&state).await {
+        Ok(albums) => markup::albums(&albums).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Albums belong to the account that created them, see `User::can_edit_album`.
+pub(crate) async fn create_album(
     State(state): State<AppState>,
-    mut multipart: Multipart,
+    user: User,
+    Form(form): Form<AlbumForm>,
+) -> Response {
+    let name = match form.name() {
+        Ok(name) => name,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    if let Err(err) = db::insert_album(&state, name, user.id).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    tracing::info!("created album {name}");
+    albums(State(state)).await
+}
+
+/// The gallery of an album.
+pub(crate) async fn album(
+    State(state): State<AppState>,
+    user: Option<User>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db::get_album(&state, id).await {
+        Ok(album) => {
+            let editable = user.is_some_and(|user| user.can_edit_album(&album));
+            markup::album_page(&album, editable).into_response()
+        }
+        Err(err) => not_found_or_error(err),
+    }
+}
+
+pub(crate) async fn rename_album(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+    Form(form): Form<AlbumForm>,
+) -> Response {
+    let name = match form.name() {
+        Ok(name) => name,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    if let Some(res) = check_can_edit_album(&state, &user, id).await {
+        return res;
+    }
+    if let Err(err) = db::rename_album(&state, id, name).await {
+        return not_found_or_error(err);
+    }
+    match db::get_album(&state, id).await {
+        Ok(album) => markup::album_header(&album, true).into_response(),
+        Err(err) => not_found_or_error(err),
+    }
+}
+
+pub(crate) async fn add_album_image(
+    State(state): State<AppState>,
+    user: User,
+    Path((album_id, image_id)): Path<(i64, i64)>,
+) -> Response {
+    if let Some(res) = check_can_edit(&state, &user, image_id).await {
+        return res;
+    }
+    if let Some(res) = check_can_edit_album(&state, &user, album_id).await {
+        return res;
+    }
+    if let Err(err) = db::add_album_image(&state, album_id, image_id).await {
+        return not_found_or_error(err);
+    }
+    image_albums_section(&state, &user, image_id).await
+}
+
+pub(crate) async fn remove_album_image(
+    State(state): State<AppState>,
+    user: User,
+    Path((album_id, image_id)): Path<(i64, i64)>,
+) -> Response {
+    if let Some(res) = check_can_edit(&state, &user, image_id).await {
+        return res;
+    }
+    if let Some(res) = check_can_edit_album(&state, &user, album_id).await {
+        return res;
+    }
+    if let Err(err) = db::remove_album_image(&state, album_id, image_id).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    image_albums_section(&state, &user, image_id).await
+}
+
+/// All albums and whether the image is in them.
+async fn image_albums(state: &AppState, image_id: i64) -> Result<Vec<(Album, bool)>, BoxError> {
+    let albums = db::get_albums(state).await?;
+    let ids = db::get_image_album_ids(state, image_id).await?;
+    Ok(albums
+        .into_iter()
+        .map(|album| {
+            let contains = ids.contains(&album.id);
+            (album, contains)
+        })
+        .collect())
+}
+
+async fn image_albums_section(state: &AppState, user: &User, image_id: i64) -> Response {
+    match image_albums(state, image_id).await {
+        Ok(albums) => markup::image_albums(image_id, albums, Some(user)).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TagForm {
+    tag: String,
+}
+
+/// Tags starting with what was typed so far.
+pub(crate) async fn tags(State(state): State<AppState>, Query(form): Query<TagForm>) -> Response {
+    const LIMIT: i64 = 10;
+
+    let Some(prefix) = normalize_tag(&form.tag) else {
+        return markup::tag_options(&[]).into_response();
+    };
+    match db::search_tags(&state, &prefix, LIMIT).await {
+        Ok(tags) => markup::tag_options(&tags).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// The gallery of a tag.
+pub(crate) async fn tag(Path(tag): Path<String>) -> Response {
+    match normalize_tag(&tag) {
+        Some(normalized) if normalized == tag => markup::tag_page(&tag).into_response(),
+        _ => (StatusCode::NOT_FOUND, "tag not found").into_response(),
+    }
+}
+
+pub(crate) async fn add_image_tag(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+    Form(form): Form<TagForm>,
 ) -> Response {
-    tracing::info!("uploading image...");
+    if let Some(res) = check_can_edit(&state, &user, id).await {
+        return res;
+    }
+    let Some(tag) = normalize_tag(&form.tag) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "tags should have letters or digits",
+        )
+            .into_response();
+    };
+    if let Err(err) = db::add_image_tag(&state, id, &tag).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    image_tags_section(&state, id).await
+}
+
+pub(crate) async fn remove_image_tag(
+    State(state): State<AppState>,
+    user: User,
+    Path((id, tag)): Path<(i64, String)>,
+) -> Response {
+    if let Some(res) = check_can_edit(&state, &user, id).await {
+        return res;
+    }
+    if let Err(err) = db::remove_image_tag(&state, id, &tag).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    image_tags_section(&state, id).await
+}
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        let file_name = field.file_name().unwrap().to_string();
-        let content_type = field.content_type().unwrap().to_string();
-        let data = field.bytes().await.unwrap();
+async fn image_tags_section(state: &AppState, image_id: i64) -> Response {
+    match db::get_image_tags(state, image_id).await {
+        Ok(tags) => markup::image_tags(image_id, &tags, true).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
 
-        let x = format!(
-            "Length of `{name}` (`{file_name}`: `{content_type}`) is {} bytes",
-            data.len()
-        );
+/// Stops users from changing the images of others, see `User::can_edit`.
+async fn check_can_edit(state: &AppState, user: &User, id: i64) -> Option<Response> {
+    match db::get_image(state, id).await {
+        Ok(image) if user.can_edit(&image) => None,
+        Ok(_) => Some(AuthError::Forbidden.into_response()),
+        Err(err) => Some(not_found_or_error(err)),
+    }
+}
 
-        tracing::info!("{}", &x);
+/// Stops users from changing the albums of others, see `User::can_edit_album`.
+async fn check_can_edit_album(state: &AppState, user: &User, id: i64) -> Option<Response> {
+    match db::get_album(state, id).await {
+        Ok(album) if user.can_edit_album(&album) => None,
+        Ok(_) => Some(AuthError::Forbidden.into_response()),
+        Err(err) => Some(not_found_or_error(err)),
+    }
+}
 
-        if !content_type.contains(mediatype::names::IMAGE.as_str()) {
-            return (StatusCode::BAD_REQUEST, "only images can be uploaded").into_response();
+#[derive(Deserialize)]
+pub(crate) struct RenameForm {
+    file_name: String,
+}
+
+pub(crate) async fn rename_image(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+    Form(form): Form<RenameForm>,
+) -> Response {
+    const MAX_LEN: usize = 255;
+
+    if let Some(res) = check_can_edit(&state, &user, id).await {
+        return res;
+    }
+
+    let file_name = form.file_name.trim();
+    if file_name.is_empty() || file_name.chars().count() > MAX_LEN {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("file names should have 1 to {MAX_LEN} characters"),
+        )
+            .into_response();
+    }
+
+    match db::rename_image(&state, id, file_name).await {
+        Ok(image) => {
+            tracing::info!("renamed image {id} to {file_name}");
+            markup::image_name(&image, true).into_response()
         }
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Deletes an image with its original and renditions, the grid item is
+/// removed and upload.js closes the modal.
+pub(crate) async fn delete_image(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+) -> Response {
+    if let Some(res) = check_can_edit(&state, &user, id).await {
+        return res;
+    }
 
-        let (bytes, mime_type, dominant_color) = match img::thumbnail_for_mime(&data, &content_type)
-        {
-            Ok(bytes) => bytes,
+    let names = match db::get_rendition_names(&state, id).await {
+        Ok(names) => names,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    if let Err(err) = db::delete_image(&state, id).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+    }
+    tracing::info!("deleted image {id}");
+
+    // The image is gone either way, blobs left behind are only logged.
+    let keys = std::iter::once(BlobKey::Original(id))
+        .chain(names.into_iter().map(|name| BlobKey::Rendition(id, name)));
+    for key in keys {
+        if let Err(err) = state.blobs.delete(&key).await {
+            tracing::error!("could not delete blob {key}: {err}");
+        }
+    }
+
+    (StatusCode::OK, [("HX-Trigger", "image-deleted")]).into_response()
+}
+
+fn not_found_or_error(err: BoxError) -> Response {
+    let status = match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string()).into_response()
+}
+
+/// Uploads every file of the form, a file that can't be uploaded is shown
+/// as an error in the grid and doesn't stop the others.
+pub(crate) async fn upload_image(
+    State(state): State<AppState>,
+    user: User,
+    mut multipart: Multipart,
+) -> Response {
+    tracing::info!("uploading images...");
+
+    let mut uploads = vec![];
+    let mut status = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            // Nothing was uploaded, the whole request is rejected.
+            Err(err) if uploads.is_empty() => return UploadError::Multipart(err).into_response(),
+            // The rest of the body can't be read anymore.
             Err(err) => {
-                return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+                let err = UploadError::Multipart(err);
+                uploads.push(markup::upload_failed("upload", &err.to_string()));
+                break;
             }
         };
 
-        let res =
-            db::insert_image(&state, file_name.clone(), mime_type, &bytes, dominant_color).await;
-
-        let image = match res {
+        // Only files are uploaded, other form fields are ignored.
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+        let upload = match upload_file(&state, &user, field).await {
             Ok(image) => {
-                tracing::info!("inserted image {file_name} into database");
-                image
+                status = Some(StatusCode::OK);
+                markup::image(&image)
             }
+            Err(err) => {
+                tracing::error!("could not upload {file_name}: {err}");
+                status.get_or_insert(err.status());
+                markup::upload_failed(&file_name, &err.to_string())
+            }
+        };
+        uploads.push(upload);
+    }
+
+    // Succeeds if any file was uploaded, the errors are in the grid either way.
+    let Some(status) = status else {
+        return UploadError::NoFiles.into_response();
+    };
+
+    let markup = html! {
+        @for upload in uploads {
+            (upload)
+        }
+    };
+    (status, markup).into_response()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FocalPoint {
+    x: f32,
+    y: f32,
+}
+
+/// Centers the thumbnail of an image on a point, as fractions of the width
+/// and height, and generates the renditions again.
+pub(crate) async fn image_focal_point(
+    State(state): State<AppState>,
+    user: User,
+    Path(id): Path<i64>,
+    Form(focal_point): Form<FocalPoint>,
+) -> Response {
+    if let Some(res) = check_can_edit(&state, &user, id).await {
+        return res;
+    }
+
+    let FocalPoint { x, y } = focal_point;
+    if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "the focal point should be in the image",
+        )
+            .into_response();
+    }
+
+    let Ok(queued) = state.queue.try_reserve() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many images are being processed, try again later",
+        )
+            .into_response();
+    };
+
+    let image = match db::set_focal_point(&state, id, (x, y)).await {
+        Ok(Some(image)) => image,
+        Ok(None) => {
+            return (
+                StatusCode::CONFLICT,
+                "the image is still being processed, try again",
+            )
+                .into_response()
+        }
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+
+    queued.send(image.id);
+    markup::image(&image).into_response()
+}
+
+/// Maximum size of a single file, the whole request can be larger.
+const MAX_FILE_SIZE: usize = 25 * 1024 * 1024;
+
+/// Stores one uploaded file.
+async fn upload_file(
+    state: &AppState,
+    owner: &User,
+    mut field: Field<'_>,
+) -> Result<Image, UploadError> {
+    let file_name = field.file_name().unwrap_or("upload").to_string();
+
+    // The upload is refused before anything is done when the queue is full.
+    let queued = state
+        .queue
+        .try_reserve()
+        .map_err(|_| UploadError::QueueFull)?;
+
+    // Stop reading as soon as the file is too large.
+    let mut data = vec![];
+    while let Some(chunk) = field.chunk().await.map_err(UploadError::Multipart)? {
+        if data.len() + chunk.len() > MAX_FILE_SIZE {
+            return Err(UploadError::TooLarge {
+                limit: MAX_FILE_SIZE,
+            });
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    // The content type sent by the client isn't trusted.
+    let sniffed = img::sniff(&data)?;
+    tracing::info!(
+        "Length of `{file_name}` (`{}`) is {} bytes",
+        sniffed.mime_type,
+        data.len()
+    );
+
+    // The location of photos isn't shared with everyone who sees them.
+    let metadata = Metadata::read(&data);
+    let data = match metadata::strip_gps(&data) {
+        Ok(Some(stripped)) => stripped,
+        Ok(None) => data,
+        Err(err) => return Err(UploadError::Internal(err.to_string())),
+    };
+
+    // Decoding is CPU bound, keep it off the async runtime and share the
+    // workers with processing. Broken images fail later while they are
+    // processed.
+    let mime_type = sniffed.mime_type;
+    let permit = state
+        .workers
+        .acquire()
+        .await
+        .map_err(|err| UploadError::Internal(err.to_string()))?;
+    let (data, phash) = tokio::task::spawn_blocking(move || {
+        let phash = img::perceptual_hash(&data, mime_type);
+        (data, phash)
+    })
+    .await
+    .map_err(|err| UploadError::Internal(err.to_string()))?;
+    drop(permit);
+    let phash = phash
+        .inspect_err(|err| tracing::warn!("could not hash {file_name}: {err}"))
+        .ok()
+        .flatten();
+    let duplicate_of = check_duplicate(state, phash).await?;
+
+    let size = metadata.upright_size(sniffed.width, sniffed.height);
+    let new_image = NewImage {
+        file_name: file_name.clone(),
+        mime_type: sniffed.mime_type.to_string(),
+        metadata: &metadata,
+        size,
+        phash,
+        duplicate_of,
+        owner_id: owner.id,
+    };
+    let image = db::insert_image(state, new_image)
+        .await
+        .map_err(|err| UploadError::Internal(err.to_string()))?;
+    tracing::info!("inserted image {file_name} into database");
+
+    // The original is kept as it was uploaded.
+    store_original(state, &image, &data)
+        .await
+        .map_err(|err| UploadError::Internal(err.to_string()))?;
+
+    // The renditions follow, until then the grid shows a placeholder.
+    queued.send(image.id);
+
+    Ok(image)
+}
+
+/// Rejects or marks uploads that look like an earlier one, see `Duplicates`.
+async fn check_duplicate(state: &AppState, phash: Option<i64>) -> Result<Option<i64>, UploadError> {
+    let Duplicates {
+        policy,
+        max_distance,
+    } = state.duplicates;
+    let Some(phash) = phash.filter(|_| policy != DuplicatePolicy::Allow) else {
+        return Ok(None);
+    };
+
+    let duplicate = db::find_duplicate(state, phash, max_distance)
+        .await
+        .map_err(|err| UploadError::Internal(err.to_string()))?;
+    match (policy, duplicate) {
+        (DuplicatePolicy::Reject, Some(id)) => Err(UploadError::Duplicate { id }),
+        _ => Ok(duplicate),
+    }
+}
+
+/// Stores the original of a new image, on failure the image is deleted
+/// again so that there are no images without data.
+async fn store_original(state: &AppState, image: &Image, original: &[u8]) -> Result<(), BoxError> {
+    if let Err(err) = state
+        .blobs
+        .put(&BlobKey::Original(image.id), original)
+        .await
+    {
+        db::delete_image(state, image.id).await?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AccountForm {
+    name: String,
+    password: String,
+}
+
+pub(crate) async fn login_page() -> maud::Markup {
+    markup::login_page(None)
+}
+
+pub(crate) async fn login(
+    State(state): State<AppState>,
+    Form(form): Form<AccountForm>,
+) -> Response {
+    let wrong = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            markup::login_page(Some("wrong name or password")),
+        )
+            .into_response()
+    };
+
+    let name = form.name.trim().to_string();
+    let now = std::time::Instant::now();
+    if state.login_throttle.is_locked(&name, now) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            markup::login_page(Some("too many wrong passwords, try again later")),
+        )
+            .into_response();
+    }
+
+    let found = match db::get_user_by_name(&state, &name).await {
+        Ok(found) => found,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let (user, password_hash) = found.unzip();
+
+    // Hashing is CPU bound, keep it off the async runtime.
+    let verified = tokio::task::spawn_blocking(move || {
+        let hash = match &password_hash {
+            Some(hash) => hash,
+            None => auth::unknown_user_hash(),
+        };
+        auth::verify_password(&form.password, hash)
+    })
+    .await;
+    match (verified, user) {
+        (Ok(true), Some(user)) => {
+            state.login_throttle.succeeded(&name);
+            start_session(&state, &user).await
+        }
+        (Ok(_), _) => {
+            state.login_throttle.failed(&name, now);
+            wrong()
+        }
+        (Err(err), _) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+pub(crate) async fn register_page() -> maud::Markup {
+    markup::register_page(None)
+}
+
+/// Anyone can create an account, admins are made with `make-admin`.
+pub(crate) async fn register(
+    State(state): State<AppState>,
+    Form(form): Form<AccountForm>,
+) -> Response {
+    const NAME_LEN: std::ops::RangeInclusive<usize> = 3..=32;
+    const PASSWORD_LEN: std::ops::RangeInclusive<usize> = 8..=128;
+
+    let invalid =
+        |error: &str| (StatusCode::BAD_REQUEST, markup::register_page(Some(error))).into_response();
+
+    let name = form.name.trim().to_string();
+    let valid_name = NAME_LEN.contains(&name.chars().count())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !valid_name {
+        return invalid("names have 3 to 32 letters, digits, `-` or `_`");
+    }
+    if !PASSWORD_LEN.contains(&form.password.chars().count()) {
+        return invalid("passwords have 8 to 128 characters");
+    }
+
+    let password_hash =
+        match tokio::task::spawn_blocking(move || auth::hash_password(&form.password)).await {
+            Ok(Ok(hash)) => hash,
+            Ok(Err(err)) => return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
             Err(err) => {
                 return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
             }
         };
 
-        return markup::image(&image).into_response();
+    let user = match db::insert_user(&state, &name, &password_hash).await {
+        Ok(user) => user,
+        Err(err) => {
+            let taken = matches!(
+                err.downcast_ref::<sqlx::Error>(),
+                Some(sqlx::Error::Database(err)) if err.is_unique_violation()
+            );
+            return if taken {
+                invalid("the name is taken")
+            } else {
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+            };
+        }
+    };
+    tracing::info!("registered {name}");
+    start_session(&state, &user).await
+}
+
+/// Logs the user in and goes back to the gallery.
+async fn start_session(state: &AppState, user: &User) -> Response {
+    match auth::login(state, user).await {
+        Ok(cookie) => ([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
     }
+}
 
-    // Something went wrong.
+pub(crate) async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = auth::session_token(&headers) {
+        if let Err(err) = db::delete_session(&state, token).await {
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+        }
+    }
     (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        "could not upload the file".to_string(),
+        [(header::SET_COOKIE, auth::logout_cookie())],
+        Redirect::to("/"),
     )
         .into_response()
 }
@@ -1,184 +1,1123 @@
-use std::error::Error;
+use std::{io::Cursor, sync::Arc};
 
 use image::{
-    codecs::gif::GifDecoder, imageops::FilterType, AnimationDecoder, DynamicImage, Frame,
-    GenericImageView, ImageDecoder, RgbaImage,
+    codecs::gif::GifDecoder, imageops::FilterType, io::Reader, AnimationDecoder, DynamicImage,
+    GenericImageView, ImageFormat, RgbaImage,
 };
-use webp::{AnimDecoder, AnimEncoder, AnimFrame, Encoder, WebPConfig};
+use mediatype::MediaType;
+use webp::{AnimDecoder, AnimFrame};
 
-use crate::mime;
+use crate::{
+    encode::Encoder,
+    error::{BoxError, UploadError},
+    metadata::{self, Metadata},
+    mime, riff,
+};
 
-const TARGET_PX: u32 = 150;
-const QUALITY: f32 = 70.0;
 const MAX_FRAMES: usize = 24;
-const FRAME_RATE: i32 = 100;
+const DOMINANT_PX: u32 = 150;
+/// The most colors kept for an image, the dominant one included.
+const PALETTE_SIZE: usize = 6;
+/// The largest image that is decoded, e.g. 10000x5000.
+const MAX_PIXELS: u64 = 50_000_000;
+/// The most pixels decoded for all frames of an animation together, as many
+/// as for the largest image so that every worker needs at most as much
+/// memory for an animation.
+const MAX_ANIMATION_PIXELS: u64 = MAX_PIXELS;
+/// How much longer one side of an image may be than the other, e.g. a
+/// panorama of 5000x100.
+const MAX_ASPECT_RATIO: u32 = 50;
+/// The longest side of a rendition, WebP can't be larger.
+const MAX_SIZE: u32 = 16383;
+/// The longest side saliency is measured on, longer images are squeezed.
+const SALIENCY_PX: u32 = 2048;
 
-pub(crate) fn thumbnail_for_mime(
-    data: &[u8],
-    mime_type: &String,
-) -> Result<(Vec<u8>, String, i32), Box<dyn Error>> {
-    match mime_type.as_str() {
-        "image/webp" => webp_to_webp(&data),
-        "image/gif" => gif_to_webp(&data),
-        _ => any_to_webp(&data),
+/// A version of every upload, e.g. a small square for the grid.
+#[derive(Clone, Debug)]
+pub(crate) struct RenditionSpec {
+    pub(crate) name: &'static str,
+    /// The longest side in pixels, `None` keeps the original size up to
+    /// `MAX_SIZE`.
+    pub(crate) size: Option<u32>,
+    /// Crops a square instead of fitting the whole image.
+    pub(crate) crop: Option<Crop>,
+}
+
+/// Which square of an image a cropped rendition shows, a focal point chosen
+/// for the image wins over both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Crop {
+    Center,
+    /// The square with the most detail, measured as the edges in it.
+    Saliency,
+}
+
+impl RenditionSpec {
+    pub(crate) const THUMBNAIL: &'static str = "thumbnail";
+    pub(crate) const LARGE: &'static str = "large";
+
+    /// A thumbnail for the grid, a medium version and the full-size image.
+    pub(crate) fn defaults() -> Vec<RenditionSpec> {
+        vec![
+            RenditionSpec {
+                name: Self::THUMBNAIL,
+                size: Some(150),
+                crop: Some(Crop::Saliency),
+            },
+            RenditionSpec {
+                name: "medium",
+                size: Some(600),
+                crop: None,
+            },
+            RenditionSpec {
+                name: Self::LARGE,
+                size: None,
+                crop: None,
+            },
+        ]
+    }
+
+    /// Changes the sizes of renditions from a list like
+    /// `thumbnail:200,medium:800`, `original` keeps the original size.
+    pub(crate) fn with_sizes(
+        mut specs: Vec<RenditionSpec>,
+        config: &str,
+    ) -> Result<Vec<RenditionSpec>, String> {
+        for size in config.split(',') {
+            let invalid = || format!("invalid rendition size `{size}`");
+            let (name, size) = size.trim().split_once(':').ok_or_else(invalid)?;
+            let spec = specs
+                .iter_mut()
+                .find(|spec| spec.name == name)
+                .ok_or_else(|| format!("unknown rendition `{name}`"))?;
+            spec.size = match size {
+                "original" if spec.crop.is_none() => None,
+                _ => Some(
+                    size.parse()
+                        .ok()
+                        .filter(|size| (1..=MAX_SIZE).contains(size))
+                        .ok_or_else(invalid)?,
+                ),
+            };
+        }
+        Ok(specs)
     }
 }
 
-pub(crate) fn any_to_webp(bytes: &[u8]) -> Result<(Vec<u8>, String, i32), Box<dyn Error>> {
-    let img = image::load_from_memory(bytes)?;
-    let small_img = resize_and_crop(img);
-    let dominant = dominant(&small_img.to_rgb8());
-    let encoder = Encoder::from_image(&small_img)?;
-    let webp_memory = encoder.encode(QUALITY);
-    Ok((webp_memory.to_vec(), mime::IMAGE_WEBP.to_string(), dominant))
+/// An encoded rendition, named after its spec and format, e.g.
+/// `thumbnail.webp`.
+pub(crate) struct Rendition {
+    pub(crate) name: String,
+    pub(crate) mime_type: String,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) data: Vec<u8>,
 }
 
-pub(crate) fn gif_to_webp(gif_bytes: &[u8]) -> Result<(Vec<u8>, String, i32), Box<dyn Error>> {
-    let gif_decoder = GifDecoder::new(gif_bytes)?;
+/// The frames of an image, still images have a single frame.
+pub(crate) struct Animation {
+    pub(crate) frames: Vec<Frame>,
+    /// How many times the animation plays, 0 is forever.
+    pub(crate) loop_count: u32,
+}
 
-    let (original_width, original_height) = gif_decoder.dimensions();
+pub(crate) struct Frame {
+    pub(crate) image: DynamicImage,
+    /// How long the frame is shown, in milliseconds.
+    pub(crate) delay: u32,
+}
 
-    let gif_frames: Vec<Frame> = gif_decoder.into_frames().collect::<Result<_, _>>()?;
+impl Animation {
+    fn still(image: DynamicImage) -> Self {
+        Animation {
+            frames: vec![Frame { image, delay: 0 }],
+            loop_count: 0,
+        }
+    }
+
+    fn first_frame(&self) -> &DynamicImage {
+        &self.frames[0].image
+    }
+
+    pub(crate) fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+}
 
-    // Keep only 24 evenly distributed frames.
-    let frames_count = gif_frames.len();
-    let frame_step = frames_count / std::cmp::min(MAX_FRAMES, frames_count);
+/// The real format and size of an upload.
+pub(crate) struct Sniffed {
+    pub(crate) mime_type: &'static str,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// Checks the real format of an upload from its data and its size from its
+/// header, before anything is decoded.
+pub(crate) fn sniff(data: &[u8]) -> Result<Sniffed, UploadError> {
+    let format = image::guess_format(data).map_err(|_| UploadError::UnsupportedFormat)?;
+    if !matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Gif
+    ) {
+        return Err(UploadError::UnsupportedFormat);
+    }
 
-    // Temporary store all scaled and resized frames here,
-    // so that in the next step we can construct a webp.
-    let mut processed_frames = vec![];
+    check_animation(data, format).map_err(|frames| UploadError::TooManyFrames { frames })?;
 
-    // Scale and resize all gif frames.
-    for gif_frame in gif_frames.into_iter().step_by(frame_step) {
-        let gif_frame_bytes: Vec<u8> = gif_frame.into_buffer().into_vec();
-        let img = RgbaImage::from_vec(original_width, original_height, gif_frame_bytes).unwrap();
-        processed_frames.push(resize_and_crop(img.into()));
+    let (width, height) = Reader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|_| UploadError::UnsupportedFormat)?;
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(UploadError::TooManyPixels { width, height });
+    }
+    // Renditions scale the short side, which would make very long images huge.
+    if width.max(height) > width.min(height).saturating_mul(MAX_ASPECT_RATIO) {
+        return Err(UploadError::TooNarrow { width, height });
     }
 
-    // Construct WebP animation encoder with lossy config.
-    let mut config = WebPConfig::new().map_err(|_| "could not create webp config")?;
-    config.lossless = 0;
-    config.alpha_compression = 0;
-    config.quality = QUALITY;
+    Ok(Sniffed {
+        mime_type: format.to_mime_type(),
+        width,
+        height,
+    })
+}
 
-    let mut anim_encoder = AnimEncoder::new(TARGET_PX, TARGET_PX, &config);
-    anim_encoder.set_loop_count(0);
+/// Generates all renditions of an upload in every format and finds its
+/// palette, the dominant color first.
+///
+/// The focal point is where the cropped renditions are centered, as a
+/// fraction of the width and height.
+pub(crate) fn renditions(
+    data: &[u8],
+    mime_type: &str,
+    specs: &[RenditionSpec],
+    encoders: &[Arc<dyn Encoder>],
+    focal_point: Option<(f32, f32)>,
+) -> Result<(Vec<Rendition>, Vec<i32>), BoxError> {
+    let animation = decode(data, mime_type)?;
 
-    // Copy all frames into WebP.
-    let mut timestamp = 0;
-    for frame in &processed_frames {
-        let anim = AnimFrame::from_image(frame, timestamp)?;
-        anim_encoder.add_frame(anim);
-        timestamp += FRAME_RATE;
+    // Take the colors from the thumbnail of the first frame.
+    let first_frame = animation.first_frame();
+    let center = CropWindow::new(first_frame, DOMINANT_PX, Crop::Center, None);
+    let palette = palette(&center.apply(first_frame).to_rgb8())?;
+
+    let mut renditions = vec![];
+    for spec in specs {
+        renditions.extend(render(&animation, spec, encoders, focal_point)?);
+    }
+
+    Ok((renditions, palette))
+}
+
+/// A difference hash of how the first frame looks, images that look alike
+/// have hashes with few different bits, see `hash_distance`. Flat images
+/// have none, they would all look alike.
+pub(crate) fn perceptual_hash(data: &[u8], mime_type: &str) -> Result<Option<i64>, BoxError> {
+    Ok(dhash(&decode_first_frame(data, mime_type)?))
+}
+
+/// Every bit tells if a pixel of a tiny grey version is brighter than its
+/// right neighbour, so the hash survives resizing and recompression.
+fn dhash(img: &DynamicImage) -> Option<i64> {
+    const MIN_CONTRAST: u8 = 8;
+
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let darkest = small.pixels().map(|pixel| pixel[0]).min()?;
+    let brightest = small.pixels().map(|pixel| pixel[0]).max()?;
+    if brightest - darkest < MIN_CONTRAST {
+        return None;
+    }
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | brighter as u64;
+        }
     }
+    Some(hash as i64)
+}
 
-    // Take the dominant color from the first processed frame.
-    let dominant = {
-        let first_frame = &processed_frames[0];
-        dominant(&first_frame.to_rgb8())
+/// How many bits of two perceptual hashes differ, 0 to 64.
+#[cfg(test)]
+fn hash_distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Decodes the frames of an image with their timing.
+fn decode(data: &[u8], mime_type: &str) -> Result<Animation, BoxError> {
+    let animation = match MediaType::parse(mime_type) {
+        Ok(mime_type) if mime_type == mime::IMAGE_WEBP => decode_webp(data)?,
+        Ok(mime_type) if mime_type == mime::IMAGE_GIF => decode_gif(data)?,
+        _ => Animation::still(decode_still(data)?),
     };
 
-    // Write bytes from encoder.
-    let webp_encoder = anim_encoder.encode();
-    Ok((
-        webp_encoder.to_vec(),
-        mime::IMAGE_WEBP.to_string(),
-        dominant,
-    ))
+    if animation.frames.is_empty() {
+        return Err("image has no frames".into());
+    }
+
+    // The encoders work with RGBA.
+    Ok(Animation {
+        frames: animation
+            .frames
+            .into_iter()
+            .map(|frame| Frame {
+                image: frame.image.to_rgba8().into(),
+                delay: frame.delay,
+            })
+            .collect(),
+        loop_count: animation.loop_count,
+    })
 }
 
-pub(crate) fn webp_to_webp(bytes: &[u8]) -> Result<(Vec<u8>, String, i32), Box<dyn Error>> {
-    let anim_image = AnimDecoder::new(&bytes).decode()?;
+/// Photos are often stored sideways with their orientation in EXIF.
+fn decode_still(data: &[u8]) -> Result<DynamicImage, BoxError> {
+    let orientation = Metadata::read(data).orientation;
+    Ok(metadata::orient(
+        image::load_from_memory(data)?,
+        orientation,
+    ))
+}
 
-    if anim_image.has_animation() {
-        let mut processed_frames = vec![];
+/// Decodes only the first frame of an animation, without the others.
+fn decode_first_frame(data: &[u8], mime_type: &str) -> Result<DynamicImage, BoxError> {
+    match MediaType::parse(mime_type) {
+        Ok(mime_type) if mime_type == mime::IMAGE_GIF => {
+            let frame = GifDecoder::new(data)?
+                .into_frames()
+                .next()
+                .ok_or("image has no frames")??;
+            Ok(frame.into_buffer().into())
+        }
+        Ok(mime_type) if mime_type == mime::IMAGE_WEBP => match riff::first_webp_frame(data) {
+            Some(still) => {
+                let frame = webp::Decoder::new(&still)
+                    .decode()
+                    .ok_or("invalid webp frame")?;
+                Ok(frame.to_image())
+            }
+            None => Ok(image::load_from_memory(data)?),
+        },
+        _ => decode_still(data),
+    }
+}
 
-        let frames: Vec<AnimFrame> = anim_image.into_iter().collect();
+fn decode_gif(data: &[u8]) -> Result<Animation, BoxError> {
+    // Browsers play a GIF that repeats n times n + 1 times, and once without
+    // a repeat count.
+    let loop_count = match gif::DecodeOptions::new().read_info(data)?.repeat() {
+        gif::Repeat::Infinite => 0,
+        gif::Repeat::Finite(repeat) => repeat as u32 + 1,
+    };
 
-        let frames_count = frames.len();
-        let frame_step = frames_count / std::cmp::min(MAX_FRAMES, frames_count);
+    check_animation(data, ImageFormat::Gif).map_err(|_| "the animation has too many frames")?;
+    let frames = GifDecoder::new(data)?.into_frames().map(|frame| {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        // Browsers show frames of 10 ms or less for 100 ms.
+        let delay = match numer / denom.max(1) {
+            delay if delay <= 10 => 100,
+            delay => delay,
+        };
+        Ok(Frame {
+            image: frame.into_buffer().into(),
+            delay,
+        })
+    });
+    collect_frames(frames, loop_count)
+}
 
-        for frame in frames.into_iter().step_by(frame_step) {
-            let img =
-                RgbaImage::from_vec(frame.width(), frame.height(), frame.get_image().to_vec())
-                    .unwrap();
-            processed_frames.push(resize_and_crop(img.into()))
+/// Every frame of an animated WebP or GIF is decoded to the whole canvas, so
+/// the frames are counted from their headers before decoding. Returns the
+/// number of frames if there are too many.
+fn check_animation(data: &[u8], format: ImageFormat) -> Result<(), u32> {
+    let animation = match format {
+        ImageFormat::WebP => riff::webp_animation(data),
+        ImageFormat::Gif => gif_animation(data),
+        _ => None,
+    };
+    match animation {
+        Some((frames, width, height))
+            if frames as u64 * width as u64 * height as u64 > MAX_ANIMATION_PIXELS =>
+        {
+            Err(frames)
         }
+        _ => Ok(()),
+    }
+}
+
+/// How many frames a GIF has and the size of its canvas, without decoding
+/// the frames.
+fn gif_animation(data: &[u8]) -> Option<(u32, u32, u32)> {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(data).ok()?;
+    let (width, height) = (decoder.width() as u32, decoder.height() as u32);
+
+    let mut frames = 0;
+    while let Ok(Some(_)) = decoder.next_frame_info() {
+        frames += 1;
+    }
+    Some((frames, width, height))
+}
+
+fn decode_webp(data: &[u8]) -> Result<Animation, BoxError> {
+    check_animation(data, ImageFormat::WebP).map_err(|_| "the animation has too many frames")?;
+    let anim_image = AnimDecoder::new(data).decode()?;
+    let loop_count = anim_image.loop_count;
+
+    if !anim_image.has_animation() {
+        return Ok(Animation::still(image::load_from_memory(data)?));
+    }
+
+    // The timestamps are when the frames end.
+    let mut start = 0;
+    let frames = anim_image.into_iter().map(|frame: AnimFrame| {
+        let end = frame.get_time_ms();
+        let delay = (end - start).max(0) as u32;
+        start = end;
+        RgbaImage::from_vec(frame.width(), frame.height(), frame.get_image().to_vec())
+            .map(|image| Frame {
+                image: image.into(),
+                delay,
+            })
+            .ok_or_else(|| "invalid webp frame".into())
+    });
+    collect_frames(frames, loop_count)
+}
 
-        // Construct WebP animation encoder with lossy config.
-        let mut config = WebPConfig::new().map_err(|_| "could not create webp config")?;
-        config.lossless = 0;
-        config.alpha_compression = 0;
-        config.quality = QUALITY;
-
-        let mut anim_encoder = AnimEncoder::new(TARGET_PX, TARGET_PX, &config);
-        anim_encoder.set_loop_count(0); // Set loop count to 0 for infinite loop, change if needed
-
-        // Copy all frames into WebP.
-        let mut timestamp = 0;
-        for frame in &processed_frames {
-            let anim = AnimFrame::from_image(frame, timestamp)?;
-            anim_encoder.add_frame(anim);
-            timestamp += FRAME_RATE;
+/// Keeps only `MAX_FRAMES` evenly distributed frames of an animation, every
+/// frame that is kept is shown as long as the frames dropped after it so
+/// that the animation plays as fast as before.
+fn collect_frames(
+    frames: impl Iterator<Item = Result<Frame, BoxError>>,
+    loop_count: u32,
+) -> Result<Animation, BoxError> {
+    // Frames are small but there can be very many.
+    let mut pixels = 0;
+    let mut all_frames = vec![];
+    for frame in frames {
+        let frame = frame?;
+        pixels += frame.image.width() as u64 * frame.image.height() as u64;
+        if pixels > MAX_ANIMATION_PIXELS {
+            return Err("the animation has too many frames".into());
         }
+        all_frames.push(frame);
+    }
+
+    let step = all_frames.len().div_ceil(MAX_FRAMES).max(1);
+    let mut frames = vec![];
+    let mut all_frames = all_frames.into_iter();
+    while let Some(mut frame) = all_frames.next() {
+        frame.delay += all_frames
+            .by_ref()
+            .take(step - 1)
+            .map(|dropped| dropped.delay)
+            .sum::<u32>();
+        frames.push(frame);
+    }
+
+    Ok(Animation { frames, loop_count })
+}
+
+fn render(
+    animation: &Animation,
+    spec: &RenditionSpec,
+    encoders: &[Arc<dyn Encoder>],
+    focal_point: Option<(f32, f32)>,
+) -> Result<Vec<Rendition>, BoxError> {
+    // Every frame of an animation is cropped to the same square.
+    let window = match (spec.size, spec.crop) {
+        (Some(size), Some(crop)) => Some(CropWindow::new(
+            animation.first_frame(),
+            size,
+            crop,
+            focal_point,
+        )),
+        _ => None,
+    };
+
+    let processed = Animation {
+        frames: animation
+            .frames
+            .iter()
+            .map(|frame| Frame {
+                image: match &window {
+                    Some(window) => window.apply(&frame.image),
+                    None => resize(&frame.image, spec),
+                },
+                delay: frame.delay,
+            })
+            .collect(),
+        loop_count: animation.loop_count,
+    };
+
+    let (width, height) = processed.first_frame().dimensions();
+
+    encoders
+        .iter()
+        .map(|encoder| {
+            let data = if processed.is_animated() {
+                encoder.encode_animation(&processed)?
+            } else {
+                encoder.encode(processed.first_frame())?
+            };
+            Ok(Rendition {
+                name: format!("{}.{}", spec.name, encoder.extension()),
+                mime_type: encoder.mime_type().to_string(),
+                width,
+                height,
+                data,
+            })
+        })
+        .collect()
+}
 
-        // Take the dominant color from the first processed frame.
-        let dominant = {
-            let first_frame = &processed_frames[0];
-            dominant(&first_frame.to_rgb8())
+fn resize(img: &DynamicImage, spec: &RenditionSpec) -> DynamicImage {
+    // Images are only ever made smaller.
+    let size = spec.size.unwrap_or(MAX_SIZE);
+    if img.width() > size || img.height() > size {
+        img.resize(size, size, FilterType::Lanczos3)
+    } else {
+        img.clone()
+    }
+}
+
+/// A square of an image scaled so that its short side fits the square.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CropWindow {
+    /// The size of the scaled image.
+    width: u32,
+    height: u32,
+    /// The top left corner of the square in the scaled image.
+    x: u32,
+    y: u32,
+    size: u32,
+}
+
+impl CropWindow {
+    fn new(img: &DynamicImage, size: u32, crop: Crop, focal_point: Option<(f32, f32)>) -> Self {
+        let (width, height) = window(img.width(), img.height(), size);
+        let center = |len: u32| (len - size) / 2;
+
+        let (x, y) = match (focal_point, crop) {
+            (Some((focal_x, focal_y)), _) => {
+                (focus(width, size, focal_x), focus(height, size, focal_y))
+            }
+            (None, Crop::Center) => (center(width), center(height)),
+            (None, Crop::Saliency) => salient(img, width, height, size),
         };
 
-        // Write bytes from encoder.
-        let webp_encoder = anim_encoder.encode();
-        return Ok((
-            webp_encoder.to_vec(),
-            mime::IMAGE_WEBP.to_string(),
-            dominant,
-        ));
+        CropWindow {
+            width,
+            height,
+            x,
+            y,
+            size,
+        }
+    }
+
+    /// The square is cut from the image before it's scaled, scaling a very
+    /// long image first could take gigabytes.
+    fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let side = width.min(height);
+        let start = |start: u32, len: u32, scaled: u32| {
+            ((start as u64 * len as u64 / scaled as u64) as u32).min(len - side)
+        };
+        img.crop_imm(
+            start(self.x, width, self.width),
+            start(self.y, height, self.height),
+            side,
+            side,
+        )
+        .resize_exact(self.size, self.size, FilterType::Lanczos3)
     }
+}
 
-    any_to_webp(bytes)
+/// The start of the square along one side, with the focal point as close to
+/// its middle as the image allows.
+fn focus(len: u32, size: u32, focal: f32) -> u32 {
+    let start = (focal.clamp(0.0, 1.0) * len as f32 - size as f32 / 2.0).round();
+    (start.max(0.0) as u32).min(len - size)
 }
 
-fn resize_and_crop(img: DynamicImage) -> DynamicImage {
-    let (window_width, window_height) = window(img.width(), img.height());
+/// The start of the square with the most edges in it, in the image scaled to
+/// `width` and `height`. Sides longer than `SALIENCY_PX` are measured
+/// squeezed to it and the start is scaled back.
+fn salient(img: &DynamicImage, width: u32, height: u32, size: u32) -> (u32, u32) {
+    let (scaled_width, scaled_height) = (width, height);
+    let luma = img
+        .resize_exact(
+            width.min(SALIENCY_PX),
+            height.min(SALIENCY_PX),
+            FilterType::Triangle,
+        )
+        .to_luma8();
+    let (width, height) = luma.dimensions();
+
+    // The energy of a pixel is how much it differs from its neighbours.
+    let mut columns = vec![0u64; width as usize];
+    let mut rows = vec![0u64; height as usize];
+    for (x, y, pixel) in luma.enumerate_pixels() {
+        let value = pixel.0[0] as i32;
+        let right = luma.get_pixel((x + 1).min(width - 1), y).0[0] as i32;
+        let below = luma.get_pixel(x, (y + 1).min(height - 1)).0[0] as i32;
+        let energy = ((value - right).abs() + (value - below).abs()) as u64;
+        columns[x as usize] += energy;
+        rows[y as usize] += energy;
+    }
 
-    let dyn_img: DynamicImage = img.to_rgba8().into();
-    let resized_dyn_img = dyn_img.resize(window_width, window_height, FilterType::Lanczos3);
+    let start = |energy: &[u64], len: u32| {
+        let measured = energy.len() as u64;
+        let measured_size = (size as u64 * measured / len as u64).max(1) as u32;
+        let start = best_start(energy, measured_size) as u64;
+        ((start * len as u64 / measured) as u32).min(len - size)
+    };
+    (start(&columns, scaled_width), start(&rows, scaled_height))
+}
 
-    // get the half offset by doing: 190 - 150 = 40 -> 40 / 2 = 20
-    let center_x = (window_width - TARGET_PX) / 2;
-    let center_y = (window_height - TARGET_PX) / 2;
+/// The start of the run of `size` values with the largest sum, ties go to the
+/// run closest to the middle.
+fn best_start(values: &[u64], size: u32) -> u32 {
+    let size = size as usize;
+    let center = (values.len() - size) / 2;
 
-    // Crop image at the center
-    resized_dyn_img
-        .view(center_x, center_y, TARGET_PX, TARGET_PX)
-        .to_image()
-        .into()
+    let mut sum: u64 = values[..size].iter().sum();
+    let mut best = (sum, 0usize);
+    for start in 1..=values.len() - size {
+        sum = sum + values[start + size - 1] - values[start - 1];
+        let closer = start.abs_diff(center) < best.1.abs_diff(center);
+        if sum > best.0 || (sum == best.0 && closer) {
+            best = (sum, start);
+        }
+    }
+    best.1 as u32
 }
 
 // Calculate the new dimensions while maintaining the aspect ratio
-fn window(width: u32, height: u32) -> (u32, u32) {
+fn window(width: u32, height: u32, target_px: u32) -> (u32, u32) {
     if width > height {
         let aspect = width as f32 / height as f32;
-        ((TARGET_PX as f32 * aspect).round() as u32, TARGET_PX)
+        ((target_px as f32 * aspect).round() as u32, target_px)
     } else {
         let aspect = height as f32 / width as f32;
-        (TARGET_PX, (TARGET_PX as f32 * aspect).round() as u32)
+        (target_px, (target_px as f32 * aspect).round() as u32)
     }
 }
 
-/// Find the dominant color from the given image as bytes
+/// Find the colors of the given image as bytes, the dominant color first
 /// # Important
 /// Bytes must be in RGB format!
-fn dominant(bytes: &[u8]) -> i32 {
-    let palette = color_thief::get_palette(&bytes, color_thief::ColorFormat::Rgb, 10, 3).unwrap();
+fn palette(bytes: &[u8]) -> Result<Vec<i32>, BoxError> {
+    let to_rgb = |color: &color_thief::Color| {
+        let r = (color.r as u32) << 16;
+        let g = (color.g as u32) << 8;
+        let b = color.b as u32;
+        (r | g | b) as i32
+    };
+
+    // The dominant color stays what it was before palettes were kept.
+    let dominant = color_thief::get_palette(bytes, color_thief::ColorFormat::Rgb, 10, 3)?;
+    let dominant = dominant.first().ok_or("image has no colors")?;
+    let mut palette = vec![to_rgb(dominant)];
+
+    let colors =
+        color_thief::get_palette(bytes, color_thief::ColorFormat::Rgb, 10, PALETTE_SIZE as u8)?;
+    for color in colors.iter().map(to_rgb) {
+        if palette.len() < PALETTE_SIZE && !palette.contains(&color) {
+            palette.push(color);
+        }
+    }
+
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{
+        codecs::gif::{GifEncoder, Repeat},
+        Delay, Rgba, RgbaImage,
+    };
+
+    use super::*;
+
+    /// A GIF of frames shown for the given delays in milliseconds.
+    fn gif(delays: &[u32], repeat: Repeat) -> Vec<u8> {
+        let mut data = vec![];
+        let mut encoder = GifEncoder::new(&mut data);
+        encoder.set_repeat(repeat).unwrap();
+        for (i, &delay) in delays.iter().enumerate() {
+            let image = RgbaImage::from_pixel(8, 8, Rgba([i as u8, 0, 0, 255]));
+            let delay = Delay::from_numer_denom_ms(delay, 1);
+            encoder
+                .encode_frame(image::Frame::from_parts(image, 0, 0, delay))
+                .unwrap();
+        }
+        drop(encoder);
+        data
+    }
+
+    /// A flat grey image with a checkerboard in the square at `(x, y)`.
+    fn detail_at(width: u32, height: u32, x: u32, y: u32, size: u32) -> DynamicImage {
+        RgbaImage::from_fn(width, height, |px, py| {
+            let inside = (x..x + size).contains(&px) && (y..y + size).contains(&py);
+            if inside && (px / 4 + py / 4) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else if inside {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        })
+        .into()
+    }
+
+    /// Smooth waves, like the large shapes of a photo.
+    fn waves(width: u32, height: u32, upside_down: bool) -> DynamicImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let y = if upside_down { height - 1 - y } else { y };
+            let fx = x as f32 / width as f32 * std::f32::consts::TAU * 1.5;
+            let fy = y as f32 / height as f32 * std::f32::consts::PI;
+            let v = (128.0 + 100.0 * fx.sin() * fy.cos()) as u8;
+            Rgba([v, v / 2, 255 - v, 255])
+        })
+        .into()
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = vec![];
+        RgbaImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    /// A PNG without any pixels, enough to read its size.
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut ihdr = b"IHDR".to_vec();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend_from_slice(&13u32.to_be_bytes());
+        data.extend_from_slice(&ihdr);
+        data.extend_from_slice(&crc32fast::hash(&ihdr).to_be_bytes());
+        // The size is known once the image data starts.
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(b"IDAT");
+        data.extend_from_slice(&crc32fast::hash(b"IDAT").to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn sniff_the_real_format() {
+        let sniffed = sniff(&png(3, 2)).unwrap();
+        assert_eq!(
+            (sniffed.mime_type, sniffed.width, sniffed.height),
+            ("image/png", 3, 2)
+        );
+
+        let sniffed = sniff(&gif(&[100, 100], Repeat::Infinite)).unwrap();
+        assert_eq!(
+            (sniffed.mime_type, sniffed.width, sniffed.height),
+            ("image/gif", 8, 8)
+        );
+    }
+
+    #[test]
+    fn sniff_rejects_other_formats() {
+        // Named and sent as a PNG, but it's text.
+        assert!(matches!(
+            sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            Err(UploadError::UnsupportedFormat)
+        ));
+
+        let mut bmp = vec![];
+        RgbaImage::new(2, 2)
+            .write_to(&mut Cursor::new(&mut bmp), ImageFormat::Bmp)
+            .unwrap();
+        assert!(matches!(sniff(&bmp), Err(UploadError::UnsupportedFormat)));
+
+        // The PNG signature, but no header.
+        assert!(matches!(
+            sniff(&png(2, 2)[..12]),
+            Err(UploadError::UnsupportedFormat)
+        ));
+    }
+
+    #[test]
+    fn sniff_rejects_large_images() {
+        assert!(sniff(&png_header(7000, 7000)).is_ok());
+        assert!(matches!(
+            sniff(&png_header(10_000, 6_000)),
+            Err(UploadError::TooManyPixels {
+                width: 10_000,
+                height: 6_000
+            })
+        ));
+    }
+
+    #[test]
+    fn large_animations_are_not_decoded() {
+        use crate::riff::tests::animated_webp;
+
+        // 13 frames of 2000x2000 are 52 megapixels.
+        let data = animated_webp(2000, 2000, 13);
+        assert!(matches!(
+            sniff(&data),
+            Err(UploadError::TooManyFrames { frames: 13 })
+        ));
+        let err = decode(&data, "image/webp").err().unwrap();
+        assert_eq!(err.to_string(), "the animation has too many frames");
+
+        let data = animated_webp(2000, 2000, 12);
+        assert!(check_animation(&data, ImageFormat::WebP).is_ok());
+    }
+
+    /// A GIF with a large canvas and tiny frames, it's small but every frame
+    /// is decoded to the whole canvas.
+    fn gif_canvas(width: u16, height: u16, frames: usize) -> Vec<u8> {
+        let mut data = vec![];
+        let mut encoder =
+            gif::Encoder::new(&mut data, width, height, &[0, 0, 0, 255, 255, 255]).unwrap();
+        for _ in 0..frames {
+            encoder
+                .write_frame(&gif::Frame {
+                    width: 1,
+                    height: 1,
+                    buffer: [0][..].into(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        drop(encoder);
+        data
+    }
+
+    #[test]
+    fn large_gifs_are_not_decoded() {
+        let data = gif_canvas(2000, 2000, 13);
+        assert_eq!(gif_animation(&data), Some((13, 2000, 2000)));
+        assert!(matches!(
+            sniff(&data),
+            Err(UploadError::TooManyFrames { frames: 13 })
+        ));
+        let err = decode(&data, "image/gif").err().unwrap();
+        assert_eq!(err.to_string(), "the animation has too many frames");
+
+        assert!(sniff(&gif_canvas(2000, 2000, 12)).is_ok());
+    }
+
+    #[test]
+    fn sniff_rejects_narrow_images() {
+        assert!(sniff(&png_header(5000, 100)).is_ok());
+        assert!(matches!(
+            sniff(&png_header(1, 100_000)),
+            Err(UploadError::TooNarrow {
+                width: 1,
+                height: 100_000
+            })
+        ));
+        assert!(sniff(&png_header(5001, 100)).is_err());
+    }
+
+    #[test]
+    fn gif_keeps_its_timing() {
+        let data = gif(&[40, 500, 0], Repeat::Finite(2));
+        let animation = decode(&data, "image/gif").unwrap();
+        let delays = animation
+            .frames
+            .iter()
+            .map(|frame| frame.delay)
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [40, 500, 100]);
+        assert_eq!(animation.loop_count, 3);
+
+        let data = gif(&[40, 40], Repeat::Infinite);
+        assert_eq!(decode(&data, "image/gif").unwrap().loop_count, 0);
+    }
+
+    #[test]
+    fn dropped_frames_keep_the_duration() {
+        let data = gif(&[30; 50], Repeat::Infinite);
+        let animation = decode(&data, "image/gif").unwrap();
+        assert!(animation.frames.len() <= MAX_FRAMES);
+        let duration = animation
+            .frames
+            .iter()
+            .map(|frame| frame.delay)
+            .sum::<u32>();
+        assert_eq!(duration, 50 * 30);
+    }
+
+    #[test]
+    fn perceptual_hash_survives_resizing() {
+        let img = waves(640, 480, false);
+        let small = img.resize(200, 150, FilterType::Lanczos3);
+        let hash = dhash(&img).unwrap();
+        assert!(hash_distance(hash, dhash(&small).unwrap()) <= 2);
+
+        let other = waves(640, 480, true);
+        assert!(hash_distance(hash, dhash(&other).unwrap()) > 20);
+
+        let flat = detail_at(640, 480, 0, 0, 0);
+        assert_eq!(dhash(&flat), None);
+    }
 
-    let dominant = palette[0];
+    #[test]
+    fn perceptual_hash_of_the_first_frame() {
+        use crate::encode::WebpEncoder;
+
+        let first = waves(64, 48, false);
+        let frames = [first.clone(), waves(64, 48, true)];
+
+        let mut data = vec![];
+        let mut encoder = GifEncoder::new(&mut data);
+        for frame in &frames {
+            let frame = image::Frame::new(frame.to_rgba8());
+            encoder.encode_frame(frame).unwrap();
+        }
+        drop(encoder);
+        let animation = decode(&data, "image/gif").unwrap();
+        let hash = perceptual_hash(&data, "image/gif").unwrap();
+        assert_eq!(hash, dhash(animation.first_frame()));
+
+        // Transparent frames are decoded too.
+        for alpha in [255, 200] {
+            let animation = Animation {
+                frames: frames
+                    .iter()
+                    .map(|image| {
+                        let mut image = image.to_rgba8();
+                        image.pixels_mut().for_each(|pixel| pixel[3] = alpha);
+                        Frame {
+                            image: image.into(),
+                            delay: 100,
+                        }
+                    })
+                    .collect(),
+                loop_count: 0,
+            };
+            let data = WebpEncoder { quality: 90.0 }
+                .encode_animation(&animation)
+                .unwrap();
+            let still = riff::first_webp_frame(&data).unwrap();
+            assert_eq!(&still[12..16] == b"VP8X", alpha < 255);
+            let hash = perceptual_hash(&data, "image/webp").unwrap().unwrap();
+            assert!(hash_distance(hash, dhash(&first).unwrap()) <= 2);
+        }
+    }
+
+    #[test]
+    fn saliency_finds_detail_on_the_right() {
+        let img = detail_at(600, 200, 480, 40, 120);
+        let window = CropWindow::new(&img, 150, Crop::Saliency, None);
+        assert_eq!((window.width, window.height), (450, 150));
+        assert_eq!((window.x, window.y), (300, 0));
+    }
+
+    #[test]
+    fn saliency_finds_detail_at_the_top() {
+        let img = detail_at(200, 600, 40, 0, 120);
+        let window = CropWindow::new(&img, 150, Crop::Saliency, None);
+        assert_eq!((window.width, window.height), (150, 450));
+        assert_eq!((window.x, window.y), (0, 0));
+    }
+
+    #[test]
+    fn saliency_of_detail_in_the_middle() {
+        let img = detail_at(600, 200, 240, 40, 120);
+        let window = CropWindow::new(&img, 150, Crop::Saliency, None);
+        // The detail is 90px wide after scaling, any window around it is fine.
+        assert!((60..=180).contains(&window.x), "{window:?}");
+        let detail = 180..270;
+        assert!(window.x <= detail.start && window.x + 150 >= detail.end);
+    }
+
+    #[test]
+    fn saliency_of_a_flat_image_is_centered() {
+        let img = detail_at(600, 200, 0, 0, 0);
+        let saliency = CropWindow::new(&img, 150, Crop::Saliency, None);
+        let center = CropWindow::new(&img, 150, Crop::Center, None);
+        assert_eq!(saliency, center);
+        assert_eq!((center.x, center.y), (150, 0));
+    }
+
+    #[test]
+    fn extreme_aspect_ratios_stay_small() {
+        // Scaled so that its short side fits, it would be 150x15000000.
+        let img = waves(1, 100_000, false);
+        for crop in [Crop::Center, Crop::Saliency] {
+            let window = CropWindow::new(&img, 150, crop, None);
+            assert_eq!((window.width, window.height), (150, 15_000_000));
+            assert_eq!(window.apply(&img).dimensions(), (150, 150));
+        }
+
+        let mut data = vec![];
+        img.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        let encoders = crate::encode::from_config("webp").unwrap();
+        let (renditions, _) = renditions(
+            &data,
+            "image/png",
+            &RenditionSpec::defaults(),
+            &encoders,
+            None,
+        )
+        .unwrap();
+        let sizes = renditions
+            .iter()
+            .map(|r| (r.width, r.height))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(150, 150), (1, 600), (1, MAX_SIZE)]);
+    }
 
-    let r = (dominant.r as u32) << 16;
-    let g = (dominant.g as u32) << 8;
-    let b = dominant.b as u32;
+    #[test]
+    fn focal_point_wins() {
+        let img = detail_at(600, 200, 480, 40, 120);
+        let window = CropWindow::new(&img, 150, Crop::Saliency, Some((0.0, 0.5)));
+        assert_eq!((window.x, window.y), (0, 0));
 
-    (r | g | b) as i32
+        let window = CropWindow::new(&img, 150, Crop::Center, Some((0.5, 0.5)));
+        assert_eq!((window.x, window.y), (150, 0));
+
+        // The square stays inside the image.
+        let window = CropWindow::new(&img, 150, Crop::Center, Some((0.9, 1.0)));
+        assert_eq!((window.x, window.y), (300, 0));
+    }
+
+    #[test]
+    fn thumbnail_shows_the_detail() {
+        let img = detail_at(600, 200, 480, 40, 120);
+        let spec = RenditionSpec {
+            name: "thumbnail",
+            size: Some(150),
+            crop: Some(Crop::Saliency),
+        };
+        let window = CropWindow::new(&img, 150, Crop::Saliency, None);
+        let thumbnail = window.apply(&img);
+        assert_eq!(thumbnail.dimensions(), (150, 150));
+
+        // The checkerboard is near the right edge of the thumbnail.
+        let column = |x| {
+            (0..150)
+                .map(|y| thumbnail.get_pixel(x, y).0[0])
+                .collect::<Vec<_>>()
+        };
+        assert!(column(5).iter().all(|value| (120..=136).contains(value)));
+        let detail = column(120);
+        assert!(detail.iter().any(|&value| value > 200) && detail.iter().any(|&value| value < 50));
+
+        let encoders = crate::encode::from_config("webp,jpeg").unwrap();
+        let renditions = render(&Animation::still(img), &spec, &encoders, None).unwrap();
+        let names = renditions
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["thumbnail.webp", "thumbnail.jpeg"]);
+        assert!(renditions
+            .iter()
+            .all(|rendition| (rendition.width, rendition.height) == (150, 150)));
+    }
+
+    #[test]
+    fn resize_only_makes_images_smaller() {
+        let spec = |size| RenditionSpec {
+            name: "medium",
+            size,
+            crop: None,
+        };
+        let img = waves(800, 400, false);
+        assert_eq!(resize(&img, &spec(Some(600))).dimensions(), (600, 300));
+        assert_eq!(resize(&img, &spec(Some(1000))).dimensions(), (800, 400));
+        assert_eq!(resize(&img, &spec(None)).dimensions(), (800, 400));
+
+        // The original size is only kept up to what WebP can hold.
+        let wide = DynamicImage::ImageRgba8(RgbaImage::new(MAX_SIZE + 100, 2));
+        assert_eq!(resize(&wide, &spec(None)).dimensions(), (MAX_SIZE, 2));
+    }
+
+    #[test]
+    fn render_keeps_animations() {
+        let spec = RenditionSpec {
+            name: "medium",
+            size: Some(16),
+            crop: None,
+        };
+        let frames = [waves(32, 32, false), waves(32, 32, true)];
+        let animation = Animation {
+            frames: frames
+                .into_iter()
+                .map(|image| Frame { image, delay: 100 })
+                .collect(),
+            loop_count: 0,
+        };
+
+        let encoders = crate::encode::from_config("webp,jpeg").unwrap();
+        let renditions = render(&animation, &spec, &encoders, None).unwrap();
+        assert_eq!((renditions[0].width, renditions[0].height), (16, 16));
+        let decoded = webp::AnimDecoder::new(&renditions[0].data)
+            .decode()
+            .unwrap();
+        assert_eq!(decoded.len(), 2);
+        // JPEG only has the first frame.
+        let first = image::load_from_memory(&renditions[1].data).unwrap();
+        assert_eq!(first.dimensions(), (16, 16));
+    }
+
+    #[test]
+    fn renditions_of_every_spec() {
+        let mut data = vec![];
+        waves(800, 400, false)
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        let encoders = crate::encode::from_config("webp").unwrap();
+        let (renditions, palette) = renditions(
+            &data,
+            "image/png",
+            &RenditionSpec::defaults(),
+            &encoders,
+            None,
+        )
+        .unwrap();
+
+        let found = renditions
+            .iter()
+            .map(|r| (r.name.as_str(), r.width, r.height))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("thumbnail.webp", 150, 150),
+                ("medium.webp", 600, 300),
+                ("large.webp", 800, 400),
+            ]
+        );
+        assert!(renditions
+            .iter()
+            .all(|rendition| image::guess_format(&rendition.data).unwrap() == ImageFormat::WebP));
+        assert!(!palette.is_empty());
+    }
+
+    #[test]
+    fn rendition_sizes_from_config() {
+        let specs =
+            RenditionSpec::with_sizes(RenditionSpec::defaults(), "thumbnail:200, medium:original")
+                .unwrap();
+        let sizes = specs.iter().map(|spec| spec.size).collect::<Vec<_>>();
+        assert_eq!(sizes, [Some(200), None, None]);
+
+        for config in [
+            "medium",
+            "medium:0",
+            "medium:16384",
+            "thumbnail:original",
+            "original:100",
+        ] {
+            let specs = RenditionSpec::with_sizes(RenditionSpec::defaults(), config);
+            assert!(specs.is_err(), "{config} should be invalid");
+        }
+    }
 }
@@ -1,22 +1,32 @@
-use std::{error::Error, path::PathBuf};
+use std::path::PathBuf;
 
 use axum::{
     extract::DefaultBodyLimit,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
+use error::BoxError;
+use img::RenditionSpec;
 use sqlx::postgres::PgPoolOptions;
 use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer, services::ServeDir};
 
+mod auth;
+mod blob;
+mod color;
 mod db;
+mod encode;
+mod error;
 mod handler;
 mod img;
 mod markup;
+mod metadata;
 mod mime;
+mod queue;
+mod riff;
 mod state;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<(), BoxError> {
     let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("should have CARGO_MANIFEST_DIR");
     let manifest_path = PathBuf::from(manifest_dir);
 
@@ -39,23 +49,114 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // migrations
     sqlx::migrate!().run(&pg_pool).await?;
 
+    // `htmx_images migrate-blobs <from> <to>` moves image data between stores.
+    let args = std::env::args().collect::<Vec<_>>();
+    if let [_, command, from, to] = args.as_slice() {
+        if command == "migrate-blobs" {
+            let from = blob::from_config(from, &pg_pool)?;
+            let to = blob::from_config(to, &pg_pool)?;
+            let moved = blob::migrate(&pg_pool, from.as_ref(), to.as_ref()).await?;
+            println!("moved {moved} blobs");
+            return Ok(());
+        }
+    }
+    // `htmx_images make-admin <name>` lets an account change every image.
+    if let [_, command, name] = args.as_slice() {
+        if command == "make-admin" {
+            if !db::make_admin(&pg_pool, name).await? {
+                return Err(format!("there is no account `{name}`").into());
+            }
+            println!("{name} is an admin");
+            return Ok(());
+        }
+    }
+
+    // blob storage
+    let blob_storage = std::env::var("BLOB_STORAGE").unwrap_or_else(|_| "database".to_string());
+    let blobs = blob::from_config(&blob_storage, &pg_pool)?;
+
     let mut assets_path = manifest_path.clone();
     assets_path.push("assets");
 
     let serve_dir_service = ServeDir::new(assets_path);
 
+    // one worker per core processes uploads
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    // near duplicates of earlier uploads are marked by default
+    let mut duplicates = state::Duplicates::default();
+    if let Ok(policy) = std::env::var("DUPLICATES") {
+        duplicates.policy = policy.parse()?;
+    }
+    if let Ok(max_distance) = std::env::var("DUPLICATE_DISTANCE") {
+        duplicates.max_distance = max_distance.parse()?;
+    }
+
+    // renditions are kept as WebP by default, e.g. `avif:60,webp:70,jpeg:80`
+    let formats = std::env::var("RENDITION_FORMATS").unwrap_or_else(|_| "webp:70".to_string());
+    let encoders = encode::from_config(&formats)?;
+    let mut renditions = RenditionSpec::defaults();
+    if let Ok(sizes) = std::env::var("RENDITION_SIZES") {
+        renditions = RenditionSpec::with_sizes(renditions, &sizes)?;
+    }
+
+    let (queue, queued) = queue::channel();
+    let state = state::State::new(
+        pg_pool, blobs, renditions, encoders, queue, workers, duplicates,
+    );
+    tokio::spawn(queue::run(state.clone(), queued));
+
+    // The queue may be too small for all of them, so the server starts
+    // while they are queued.
+    let resuming = state.clone();
+    tokio::spawn(async move {
+        match queue::resume(&resuming).await {
+            Ok(0) => {}
+            Ok(resumed) => tracing::info!("resumed processing {resumed} images"),
+            Err(err) => tracing::error!("could not resume processing: {err}"),
+        }
+    });
+
     let app = Router::new()
         .route("/", get(handler::index))
         .route("/images", get(handler::images))
         .route("/images/:id", get(handler::image))
+        .route("/images/:id", put(handler::rename_image))
+        .route("/images/:id", delete(handler::delete_image))
+        .route("/images/:id/raw", get(handler::image_raw))
+        .route(
+            "/images/:id/renditions/:name",
+            get(handler::image_rendition),
+        )
         .route("/images/:id/modal", get(handler::image_modal))
+        .route("/images/:id/focal", post(handler::image_focal_point))
         .route("/images", post(handler::upload_image))
+        .route("/images/:id/tags", post(handler::add_image_tag))
+        .route("/images/:id/tags/:tag", delete(handler::remove_image_tag))
+        .route("/albums", get(handler::albums))
+        .route("/albums", post(handler::create_album))
+        .route("/albums/:id", get(handler::album))
+        .route("/albums/:id", put(handler::rename_album))
+        .route(
+            "/albums/:id/images/:image_id",
+            post(handler::add_album_image),
+        )
+        .route(
+            "/albums/:id/images/:image_id",
+            delete(handler::remove_album_image),
+        )
+        .route("/tags", get(handler::tags))
+        .route("/tags/:tag", get(handler::tag))
+        .route("/login", get(handler::login_page))
+        .route("/login", post(handler::login))
+        .route("/register", get(handler::register_page))
+        .route("/register", post(handler::register))
+        .route("/logout", post(handler::logout))
         .layer(DefaultBodyLimit::disable())
         .layer(RequestBodyLimitLayer::new(100 * 1024 * 1024)) // 100 MB
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .layer(CorsLayer::permissive())
         .nest_service("/assets", serve_dir_service)
-        .with_state(state::State::new(pg_pool));
+        .with_state(state);
 
     let address = "0.0.0.0:4205";
     let listener = tokio::net::TcpListener::bind(address).await.unwrap();
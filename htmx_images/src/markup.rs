@@ -1,6 +1,10 @@
 use maud::{html, Markup, DOCTYPE};
 
-use crate::state::Image;
+use crate::{
+    color,
+    img::RenditionSpec,
+    state::{Album, Filter, Image, ImageStatus, User},
+};
 
 pub(crate) fn base(content: Markup) -> Markup {
     html! {
@@ -32,23 +36,205 @@ pub(crate) fn base(content: Markup) -> Markup {
     }
 }
 
-pub(crate) fn home() -> Markup {
+pub(crate) fn home(user: Option<&User>) -> Markup {
     let content = html! {
         div ."my-3 mx-1 mx-sm-2 mx-lg-3" {
-            (form())
+            div ."d-flex align-items-center gap-2" {
+                h1 ."me-auto" { i ."bi bi-file-image" {} " Images" }
+                (account(user))
+            }
+            hr;
+            // only logged in users upload
+            @if user.is_some() {
+                (form())
+            }
 
-            // images appear here
-            div
-            #all-images
-            .grid-container
-            hx-trigger="load"
-            hx-get="/images"
+            // albums appear here
+            div hx-get="/albums" hx-trigger="load" hx-swap="outerHTML" {}
+
+            (gallery(&Filter::default()))
+        }
+    };
+    base(content)
+}
+
+/// The images of an album, they are added from the image modal.
+pub(crate) fn album_page(album: &Album, editable: bool) -> Markup {
+    let filter = Filter {
+        album: Some(album.id),
+        tag: None,
+    };
+    let content = html! {
+        div ."my-3 mx-1 mx-sm-2 mx-lg-3" {
+            a href="/" ."link-secondary" { i ."bi bi-arrow-left" {} " All images" }
+            (album_header(album, editable))
+            hr;
+            (gallery(&filter))
+        }
+    };
+    base(content)
+}
+
+/// The album name, its owner can rename it.
+pub(crate) fn album_header(album: &Album, editable: bool) -> Markup {
+    html! {
+        @if editable {
+            form
+            ."d-flex gap-2 align-items-center mt-2"
+            hx-put={"/albums/"(album.id)}
+            hx-swap="outerHTML"
+            {
+                h1 ."m-0" { i ."bi bi-collection" {} }
+                input ."form-control form-control-lg" name="name" value=(album.name) aria-label="Album name" required maxlength="100";
+                button ."btn btn-outline-secondary" { "Rename" }
+                span ."text-secondary text-nowrap" { (album.image_count) " images" }
+            }
+        } @else {
+            div ."d-flex gap-2 align-items-center mt-2" {
+                h1 ."m-0 text-truncate" { i ."bi bi-collection" {} " " (album.name) }
+                span ."text-secondary text-nowrap" { (album.image_count) " images" }
+            }
+        }
+    }
+}
+
+pub(crate) fn tag_page(tag: &str) -> Markup {
+    let filter = Filter {
+        album: None,
+        tag: Some(tag.to_string()),
+    };
+    let content = html! {
+        div ."my-3 mx-1 mx-sm-2 mx-lg-3" {
+            a href="/" ."link-secondary" { i ."bi bi-arrow-left" {} " All images" }
+            h1 ."mt-2" { i ."bi bi-tag" {} " " (tag) }
+            hr;
+            (gallery(&filter))
+        }
+    };
+    base(content)
+}
+
+/// The grid of images, loaded page by page.
+fn gallery(filter: &Filter) -> Markup {
+    html! {
+        (color_search(filter))
+
+        // images appear here
+        div
+        #all-images
+        .grid-container
+        hx-trigger="load"
+        hx-get=(filter.images_url(None))
+        hx-indicator="#disk"
+        {}
+
+        { h1 #disk ."htmx-indicator d-flex justify-content-center" { "💿" } }
+
+        (modal_base())
+    }
+}
+
+/// Orders the gallery by color, clearing it goes back to the newest images.
+fn color_search(filter: &Filter) -> Markup {
+    html! {
+        form
+        ."d-flex gap-2 align-items-center mb-3"
+        hx-get="/images"
+        hx-trigger="change"
+        hx-target="#all-images"
+        hx-indicator="#disk"
+        {
+            @if let Some(album) = filter.album {
+                input type="hidden" name="album" value=(album);
+            }
+            @if let Some(tag) = &filter.tag {
+                input type="hidden" name="tag" value=(tag);
+            }
+            label for="color-search" .text-secondary { i ."bi bi-palette" {} " Search by color" }
+            input #color-search type="color" name="color" ."form-control form-control-sm form-control-color";
+            button
+            type="reset"
+            ."btn btn-sm btn-outline-secondary"
+            hx-get=(filter.images_url(None))
+            hx-target="#all-images"
             hx-indicator="#disk"
-            {}
+            { "Clear" }
+        }
+    }
+}
 
-            { h1 #disk ."htmx-indicator d-flex justify-content-center" { "💿" } }
+/// Links to the albums and a form to create one.
+pub(crate) fn albums(albums: &[Album]) -> Markup {
+    html! {
+        div #albums ."d-flex flex-wrap gap-2 align-items-center mb-3" {
+            @for album in albums {
+                a href={"/albums/"(album.id)} ."btn btn-sm btn-outline-primary" {
+                    i ."bi bi-collection" {} " " (album.name) " "
+                    span ."badge text-bg-secondary" { (album.image_count) }
+                }
+            }
+            form ."d-flex gap-1" hx-post="/albums" hx-target="#albums" hx-swap="outerHTML" {
+                input ."form-control form-control-sm" name="name" placeholder="New album" aria-label="New album" required maxlength="100";
+                button ."btn btn-sm btn-primary" aria-label="Create album" { i ."bi bi-plus-lg" {} }
+            }
+        }
+    }
+}
 
-            (modal_base())
+/// Who is logged in, or links to log in.
+fn account(user: Option<&User>) -> Markup {
+    html! {
+        @if let Some(user) = user {
+            span .text-secondary {
+                i ."bi bi-person" {} " " (user.name)
+                @if user.is_admin { " (admin)" }
+            }
+            form method="post" action="/logout" {
+                button type="submit" ."btn btn-sm btn-outline-secondary" { "Log out" }
+            }
+        } @else {
+            a href="/login" ."btn btn-sm btn-primary" { "Log in" }
+            a href="/register" ."btn btn-sm btn-outline-secondary" { "Register" }
+        }
+    }
+}
+
+pub(crate) fn login_page(error: Option<&str>) -> Markup {
+    account_page(
+        "Log in",
+        "/login",
+        error,
+        html! {
+            "No account yet? " a href="/register" { "Register" }
+        },
+    )
+}
+
+pub(crate) fn register_page(error: Option<&str>) -> Markup {
+    account_page(
+        "Register",
+        "/register",
+        error,
+        html! {
+            "Already registered? " a href="/login" { "Log in" }
+        },
+    )
+}
+
+fn account_page(title: &str, action: &str, error: Option<&str>, other: Markup) -> Markup {
+    let content = html! {
+        div ."my-3 mx-auto px-2" style="max-width: 400px;" {
+            h1 { i ."bi bi-person" {} " " (title) }
+            form method="post" action=(action) {
+                @if let Some(error) = error {
+                    div ."alert alert-danger" role="alert" { (error) }
+                }
+                input ."form-control mb-2" name="name" placeholder="Name" aria-label="Name" autocomplete="username" required autofocus;
+                input ."form-control mb-2" type="password" name="password" placeholder="Password" aria-label="Password" required;
+                button type="submit" ."btn btn-primary w-100" { (title) }
+            }
+            p ."mt-3 text-secondary" { (other) }
+            a href="/" ."link-secondary" { i ."bi bi-arrow-left" {} " All images" }
         }
     };
     base(content)
@@ -57,8 +243,6 @@ pub(crate) fn home() -> Markup {
 pub(crate) fn form() -> Markup {
     html! {
         div {
-            h1 { i ."bi bi-file-image" {} " Images" }
-            hr;
             div .my-2 {
 
                 form
@@ -73,7 +257,7 @@ pub(crate) fn form() -> Markup {
                 // _="on htmx:xhr:progress(loaded, total) set *width of #progress to (((loaded/total)*100) + '%')"
                 {
                     div ."col-sm-8 mb-2 mb-sm-0" {
-                        input id="form-file" ."form-control form-control-lg" type="file" name="file" accept="image/jpeg, image/png, image/webp, image/gif";
+                        input id="form-file" ."form-control form-control-lg" type="file" name="file" multiple accept="image/jpeg, image/png, image/webp, image/gif";
                     }
                     div ."col-sm-4 pe-sm-2" {
                         button #sub-btn ."btn btn-primary btn-lg w-100" { i ."bi bi-file-earmark-arrow-up-fill" {} " Upload" }
@@ -82,6 +266,11 @@ pub(crate) fn form() -> Markup {
                 div ."progress mt-3" role="progressbar" {
                     div id="progress" class="progress-bar progress-bar-striped progress-bar-animated" style="width:0%;" {}
                 }
+                div #upload-alerts ."mt-3" {}
+                // dropped files are uploaded one by one, see upload.js
+                div #drop-zone ."drop-zone mt-3 text-secondary" {
+                    i ."bi bi-images" {} " Drop images here"
+                }
             }
             hr;
         }
@@ -89,8 +278,62 @@ pub(crate) fn form() -> Markup {
 }
 
 pub(crate) fn image(img: &Image) -> Markup {
+    match img.status {
+        ImageStatus::Processing => image_processing(img),
+        ImageStatus::Failed => image_failed(img),
+        ImageStatus::Ready => image_ready(img),
+    }
+}
+
+/// Polls until the image is processed and replaces itself with it.
+fn image_processing(img: &Image) -> Markup {
+    html! {
+        div
+        #{"image-"(img.id)}
+        ."grid-item grid-placeholder"
+        hx-get={"/images/"(img.id)}
+        hx-trigger="every 1s"
+        hx-swap="outerHTML"
+        title=(img.file_name)
+        {
+            div ."spinner-border text-secondary" role="status" {
+                span .visually-hidden { "Processing..." }
+            }
+        }
+    }
+}
+
+fn image_failed(img: &Image) -> Markup {
+    let error = img.error.as_deref().unwrap_or("processing failed");
+    failed(Some(img.id), &img.file_name, error)
+}
+
+/// An image that couldn't be loaded, e.g. because it was deleted.
+pub(crate) fn image_error(id: i64, error: &str) -> Markup {
+    failed(Some(id), &format!("image {id}"), error)
+}
+
+/// A file that couldn't be uploaded or processed, the error is its tooltip.
+pub(crate) fn upload_failed(file_name: &str, error: &str) -> Markup {
+    failed(None, file_name, error)
+}
+
+fn failed(id: Option<i64>, file_name: &str, error: &str) -> Markup {
+    html! {
+        div
+        id=[id.map(|id| format!("image-{id}"))]
+        ."grid-item grid-placeholder text-danger"
+        title={(file_name)": "(error)}
+        {
+            i ."bi bi-exclamation-triangle fs-3" {}
+        }
+    }
+}
+
+fn image_ready(img: &Image) -> Markup {
     html! {
         a
+        #{"image-"(img.id)}
         ."grid-item"
         hx-get={"/images/"(img.id)"/modal"}
         hx-target="#modals-here"
@@ -98,16 +341,42 @@ pub(crate) fn image(img: &Image) -> Markup {
         data-bs-toggle="modal"
         data-bs-target="#modals-here"
         {
-            img src=(img.src());
+            img src=(img.rendition_src(RenditionSpec::THUMBNAIL)) loading="lazy" alt=(img.file_name);
+            @if let Some(duplicate_of) = img.duplicate_of {
+                span ."badge text-bg-warning grid-badge" title={"Looks like image "(duplicate_of)} {
+                    i ."bi bi-files" {}
+                }
+            }
         }
     }
 }
 
-pub(crate) fn images(images: &Vec<Image>) -> Markup {
+/// An error above the grid that can be dismissed.
+pub(crate) fn alert(message: &str) -> Markup {
+    html! {
+        div ."alert alert-danger alert-dismissible fade show" role="alert" {
+            i ."bi bi-exclamation-triangle" {} " " (message)
+            button type="button" ."btn-close" data-bs-dismiss="alert" aria-label="Close" {}
+        }
+    }
+}
+
+/// A page of the gallery, the next page is loaded when the end of this one
+/// is scrolled into view.
+pub(crate) fn images(images: &[Image], next_url: Option<String>) -> Markup {
     html! {
         @for image in images {
             (self::image(image))
         }
+        @if let Some(next_url) = next_url {
+            div
+            ."grid-sentinel"
+            hx-get=(next_url)
+            hx-trigger="revealed"
+            hx-swap="outerHTML"
+            hx-indicator="#disk"
+            {}
+        }
     }
 }
 
@@ -130,16 +399,236 @@ pub(crate) fn modal_base() -> Markup {
     }
 }
 
-pub(crate) fn image_modal(img: &Image) -> Markup {
+/// Images that look alike, e.g. the same photo uploaded twice. Clicking one
+/// opens its modal instead.
+fn similar_images(img: &Image, similar: &[Image]) -> Markup {
+    html! {
+        @if let Some(duplicate_of) = img.duplicate_of {
+            div ."alert alert-warning py-1 px-2 mt-3 mb-0 small" {
+                i ."bi bi-files" {} " This looks like image " (duplicate_of) ", which was uploaded before."
+            }
+        }
+        @if !similar.is_empty() {
+            div .mt-3 {
+                small .text-secondary { "Similar images" }
+                div ."d-flex flex-wrap gap-1 mt-1" {
+                    @for image in similar {
+                        a
+                        hx-get={"/images/"(image.id)"/modal"}
+                        hx-target="#modals-here"
+                        title=(image.file_name)
+                        {
+                            img
+                            src=(image.rendition_src(RenditionSpec::THUMBNAIL))
+                            alt=(image.file_name)
+                            loading="lazy"
+                            ."rounded similar-image";
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The main colors of an image, the dominant color first.
+fn palette(colors: &[i32]) -> Markup {
+    html! {
+        div ."d-flex rounded overflow-hidden mt-2 palette" {
+            @for color in colors {
+                @let hex = color::hex(*color);
+                span style={"background-color: "(hex)";"} title=(hex) {}
+            }
+        }
+    }
+}
+
+/// What the EXIF data says about the photo, if anything.
+fn image_metadata(img: &Image) -> Markup {
+    html! {
+        small ."d-flex flex-wrap gap-3 text-secondary mt-1" {
+            @if let Some(camera) = &img.camera {
+                span { i ."bi bi-camera" {} " " (camera) }
+            }
+            @if let Some(taken_at) = img.taken_at {
+                span { i ."bi bi-calendar" {} " " (taken_at.format("%Y-%m-%d %H:%M")) }
+            }
+            @if let (Some(width), Some(height)) = (img.width, img.height) {
+                span { i ."bi bi-aspect-ratio" {} " " (width) "×" (height) }
+            }
+        }
+    }
+}
+
+/// The tags of an image in its modal, with a form to add more.
+pub(crate) fn image_tags(image_id: i64, tags: &[String], editable: bool) -> Markup {
+    html! {
+        div #image-tags ."mt-3" {
+            div ."d-flex flex-wrap gap-1 mb-2" {
+                @for tag in tags {
+                    span ."badge text-bg-secondary d-flex align-items-center gap-1" {
+                        a href={"/tags/"(tag)} ."link-light text-decoration-none" { "#" (tag) }
+                        @if editable {
+                            button
+                            type="button"
+                            ."btn-close btn-close-white"
+                            style="font-size: .5rem;"
+                            aria-label={"Remove " (tag)}
+                            hx-delete={"/images/"(image_id)"/tags/"(tag)}
+                            hx-target="#image-tags"
+                            hx-swap="outerHTML"
+                            {}
+                        }
+                    }
+                }
+            }
+            @if editable {
+                form
+                ."d-flex gap-1"
+                hx-post={"/images/"(image_id)"/tags"}
+                hx-target="#image-tags"
+                hx-swap="outerHTML"
+                {
+                    // suggestions are loaded while typing
+                    input
+                    ."form-control form-control-sm"
+                    name="tag"
+                    placeholder="Add a tag"
+                    aria-label="Add a tag"
+                    list="tag-options"
+                    autocomplete="off"
+                    required
+                    hx-get="/tags"
+                    hx-trigger="input changed delay:300ms"
+                    hx-target="#tag-options";
+                    datalist #tag-options {}
+                    button ."btn btn-sm btn-outline-primary" aria-label="Add tag" { i ."bi bi-tag" {} }
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn tag_options(tags: &[String]) -> Markup {
+    html! {
+        @for tag in tags {
+            option value=(tag) {}
+        }
+    }
+}
+
+/// Every album as a button that adds the image to it or removes it, others
+/// only see the albums of the image.
+/// The albums of an image, `editor` can change the image and file it into
+/// the albums they can change.
+pub(crate) fn image_albums(
+    image_id: i64,
+    albums: Vec<(Album, bool)>,
+    editor: Option<&User>,
+) -> Markup {
+    html! {
+        div #image-albums ."d-flex flex-wrap gap-1 mt-2" {
+            @for (album, contains) in albums {
+                @let url = format!("/albums/{}/images/{image_id}", album.id);
+                @if !editor.is_some_and(|user| user.can_edit_album(&album)) {
+                    @if contains {
+                        a href={"/albums/"(album.id)} ."btn btn-sm btn-outline-primary" {
+                            i ."bi bi-collection" {} " " (album.name)
+                        }
+                    }
+                } @else if contains {
+                    button ."btn btn-sm btn-primary" hx-delete=(url) hx-target="#image-albums" hx-swap="outerHTML" {
+                        i ."bi bi-check-lg" {} " " (album.name)
+                    }
+                } @else {
+                    button ."btn btn-sm btn-outline-primary" hx-post=(url) hx-target="#image-albums" hx-swap="outerHTML" {
+                        i ."bi bi-plus-lg" {} " " (album.name)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The file name in the modal header, the owner can rename the image.
+pub(crate) fn image_name(img: &Image, editable: bool) -> Markup {
+    html! {
+        @if editable {
+            form
+            #image-name
+            ."d-flex gap-1 flex-grow-1"
+            hx-put={"/images/"(img.id)}
+            hx-swap="outerHTML"
+            {
+                input ."form-control form-control-sm" name="file_name" value=(img.file_name) aria-label="File name" required maxlength="255";
+                button ."btn btn-sm btn-outline-secondary" aria-label="Rename" { i ."bi bi-pencil" {} }
+            }
+        } @else {
+            h5 #image-name ."modal-title text-truncate" { (img.file_name) }
+        }
+    }
+}
+
+pub(crate) fn image_modal(
+    img: &Image,
+    owner: Option<&User>,
+    editor: Option<&User>,
+    tags: &[String],
+    albums: Vec<(Album, bool)>,
+    similar: &[Image],
+) -> Markup {
+    let editable = editor.is_some();
     html! {
         div ."modal-dialog modal-dialog-centered" {
             div ."modal-content" {
-                div ."modal-header" {
-                    h5 ."modal-title text-truncate" { (img.file_name) }
+                div ."modal-header gap-2" {
+                    (image_name(img, editable))
+                    @if editable {
+                        // upload.js closes the modal once the image is deleted
+                        button
+                        ."btn btn-sm btn-outline-danger"
+                        aria-label="Delete"
+                        hx-delete={"/images/"(img.id)}
+                        hx-confirm={"Delete " (img.file_name) "?"}
+                        hx-target={"#image-"(img.id)}
+                        hx-swap="outerHTML"
+                        { i ."bi bi-trash" {} }
+                    }
                 }
                 div ."modal-body" {
-                    img style={"border: 2px solid "(img.dominant_hex(1.0))";"} src=(img.src()) ."rounded w-100";
-                    small .text-wrap { (img.id) ": " (img.short_date()) }
+                    @if editable {
+                        // clicking the image centers the thumbnail there, see upload.js
+                        img
+                        style={"border: 2px solid "(img.dominant_hex(1.0))";"}
+                        src=(img.rendition_src(RenditionSpec::LARGE))
+                        alt=(img.file_name)
+                        title="Click to center the thumbnail here"
+                        data-focal-url={"/images/"(img.id)"/focal"}
+                        data-image-id=(img.id)
+                        ."rounded w-100 focal-picker";
+                    } @else {
+                        img
+                        style={"border: 2px solid "(img.dominant_hex(1.0))";"}
+                        src=(img.rendition_src(RenditionSpec::LARGE))
+                        alt=(img.file_name)
+                        ."rounded w-100";
+                    }
+                    div ."d-flex align-items-center justify-content-between mt-2" {
+                        small .text-wrap {
+                            (img.id) ": " (img.short_date())
+                            @if let Some(owner) = owner {
+                                " by " (owner.name)
+                            }
+                        }
+                        a href=(img.src()) download=(img.file_name) ."btn btn-sm btn-outline-secondary" {
+                            i ."bi bi-download" {} " Original"
+                        }
+                    }
+                    (palette(&img.palette))
+                    (image_metadata(img))
+                    (image_tags(img.id, tags, editable))
+                    (image_albums(img.id, albums, editor))
+                    (similar_images(img, similar))
                 }
             }
         }
@@ -0,0 +1,651 @@
+use std::{io::Cursor, ops::Range};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{experimental::Writer, Context, Exif, Field, In, Tag, Value};
+use image::DynamicImage;
+
+use crate::{error::BoxError, riff};
+
+const EXIF_ID: &[u8] = b"Exif\0\0";
+const XMP_ID: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const EXTENDED_XMP_ID: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+/// The EXIF fields of an upload that are kept, most images have none.
+#[derive(Clone, Debug)]
+pub(crate) struct Metadata {
+    /// How the image has to be turned to be upright, 1 to 8.
+    pub(crate) orientation: u32,
+    pub(crate) camera: Option<String>,
+    /// Local time of the camera, EXIF has no time zone.
+    pub(crate) taken_at: Option<NaiveDateTime>,
+}
+
+/// Upright, without a camera or date.
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata {
+            orientation: 1,
+            camera: None,
+            taken_at: None,
+        }
+    }
+}
+
+impl Metadata {
+    /// Reads the metadata of a JPEG, PNG or WebP image, missing or broken
+    /// EXIF data is ignored.
+    pub(crate) fn read(data: &[u8]) -> Metadata {
+        let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) else {
+            return Metadata::default();
+        };
+
+        let orientation = exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .unwrap_or(1);
+
+        let make = ascii(&exif, Tag::Make);
+        let camera = match (make, ascii(&exif, Tag::Model)) {
+            // Most models already start with the make, e.g. `Canon EOS 5D`.
+            (Some(make), Some(model)) if !model.starts_with(&make) => {
+                Some(format!("{make} {model}"))
+            }
+            (make, model) => model.or(make),
+        };
+
+        let taken_at =
+            date_time(&exif, Tag::DateTimeOriginal).or_else(|| date_time(&exif, Tag::DateTime));
+
+        Metadata {
+            orientation,
+            camera,
+            taken_at,
+        }
+    }
+
+    /// The size of the upright image, `width` and `height` are stored.
+    pub(crate) fn upright_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if (5..=8).contains(&self.orientation) {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let value = String::from_utf8_lossy(values.first()?).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+fn date_time(exif: &Exif, tag: Tag) -> Option<NaiveDateTime> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let date_time = exif::DateTime::from_ascii(values.first()?).ok()?;
+    NaiveDate::from_ymd_opt(
+        date_time.year.into(),
+        date_time.month.into(),
+        date_time.day.into(),
+    )?
+    .and_hms_opt(
+        date_time.hour.into(),
+        date_time.minute.into(),
+        date_time.second.into(),
+    )
+}
+
+/// Turns a decoded image upright.
+pub(crate) fn orient(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Removes the location from the EXIF and XMP data of an upload, the other
+/// EXIF fields are kept. XMP packets with a location and EXIF data that
+/// can't be read are dropped. Returns `None` if there is no location.
+pub(crate) fn strip_gps(data: &[u8]) -> Result<Option<Vec<u8>>, BoxError> {
+    let Some((format, segments)) = find_segments(data) else {
+        return Ok(None);
+    };
+
+    // The new EXIF data of the segments that change, `None` drops them.
+    let mut changed = vec![];
+    for segment in segments {
+        let payload = &data[segment.payload.clone()];
+        let replacement = match segment.kind {
+            Kind::Exif => match exif::parse_exif(payload) {
+                Ok((fields, little_endian)) => {
+                    if !fields
+                        .iter()
+                        .any(|field| field.tag.context() == Context::Gps)
+                    {
+                        continue;
+                    }
+                    Some(strip_exif(&fields, little_endian)?)
+                }
+                // What can't be read can't be checked for a location.
+                Err(_) => None,
+            },
+            Kind::Xmp if !xmp_has_gps(payload) => continue,
+            Kind::Xmp | Kind::CompressedXmp => None,
+        };
+        changed.push((segment, replacement));
+    }
+
+    if changed.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format.replace(data, &changed)?))
+}
+
+/// The EXIF data without the location.
+fn strip_exif(fields: &[Field], little_endian: bool) -> Result<Vec<u8>, BoxError> {
+    // The thumbnail and the maker note point into the old data, they are
+    // dropped with the location.
+    let mut writer = Writer::new();
+    let kept = fields.iter().filter(|field| keep(field));
+    for field in kept {
+        writer.push_field(field);
+    }
+    let mut stripped = Cursor::new(vec![]);
+    writer.write(&mut stripped, little_endian)?;
+    Ok(stripped.into_inner())
+}
+
+fn keep(field: &Field) -> bool {
+    field.ifd_num == In::PRIMARY
+        && field.tag.context() != Context::Gps
+        && field.tag != Tag::MakerNote
+}
+
+/// The location is in `exif:GPSLatitude` and the like, whatever the
+/// prefix of the namespace is.
+fn xmp_has_gps(xmp: &[u8]) -> bool {
+    xmp.windows(4).any(|window| window == b":GPS")
+}
+
+/// A segment or chunk with metadata.
+struct Segment {
+    kind: Kind,
+    /// The whole segment or chunk, header included.
+    chunk: Range<usize>,
+    /// The TIFF structure with the EXIF fields, or the XMP packet.
+    payload: Range<usize>,
+}
+
+enum Kind {
+    Exif,
+    Xmp,
+    /// Compressed XMP in PNG can't be searched for a location.
+    CompressedXmp,
+}
+
+enum Format {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+fn find_segments(data: &[u8]) -> Option<(Format, Vec<Segment>)> {
+    if data.starts_with(&[0xff, 0xd8]) {
+        Some((Format::Jpeg, find_jpeg_segments(data)))
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some((Format::Png, find_png_segments(data)))
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        Some((Format::WebP, find_webp_segments(data)))
+    } else {
+        None
+    }
+}
+
+/// The EXIF data and the XMP packet are in APP1 segments before the image
+/// data, long XMP packets continue in more segments.
+fn find_jpeg_segments(data: &[u8]) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut pos = 2;
+    while data.get(pos) == Some(&0xff) {
+        let Some(&marker) = data.get(pos + 1) else {
+            break;
+        };
+        // The image data starts, there are no more metadata segments.
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+        let Some(len) = data.get(pos + 2..pos + 4) else {
+            break;
+        };
+        let end = pos + 2 + u16::from_be_bytes([len[0], len[1]]) as usize;
+        let Some(segment) = data.get(pos + 4..end) else {
+            break;
+        };
+        if marker == 0xe1 {
+            let found = [
+                (Kind::Exif, EXIF_ID),
+                (Kind::Xmp, XMP_ID),
+                (Kind::Xmp, EXTENDED_XMP_ID),
+            ]
+            .into_iter()
+            .find(|(_, id)| segment.starts_with(id));
+            if let Some((kind, id)) = found {
+                segments.push(Segment {
+                    kind,
+                    chunk: pos..end,
+                    payload: pos + 4 + id.len()..end,
+                });
+            }
+        }
+        pos = end;
+    }
+    segments
+}
+
+/// The EXIF data is an `eXIf` chunk, the XMP packet an `iTXt` chunk.
+fn find_png_segments(data: &[u8]) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut pos = 8;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = pos + 12 + len;
+        if end > data.len() {
+            break;
+        }
+        let payload = pos + 8..pos + 8 + len;
+        match &header[4..] {
+            b"eXIf" => segments.push(Segment {
+                kind: Kind::Exif,
+                chunk: pos..end,
+                payload,
+            }),
+            b"iTXt" if data[payload.clone()].starts_with(PNG_XMP_KEYWORD) => {
+                // The keyword is followed by whether the text is compressed,
+                // the compression method, a language and a translated keyword.
+                let after_keyword = payload.start + PNG_XMP_KEYWORD.len();
+                let compressed = data.get(after_keyword) != Some(&0);
+                let text = data[after_keyword..payload.end]
+                    .iter()
+                    .enumerate()
+                    .skip(2)
+                    .filter(|(_, &byte)| byte == 0)
+                    .nth(1)
+                    .map_or(payload.end, |(i, _)| after_keyword + i + 1);
+                segments.push(Segment {
+                    kind: if compressed {
+                        Kind::CompressedXmp
+                    } else {
+                        Kind::Xmp
+                    },
+                    chunk: pos..end,
+                    payload: text..payload.end,
+                });
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+    segments
+}
+
+/// The EXIF data is an `EXIF` chunk of the RIFF container, the XMP packet
+/// an `XMP ` chunk.
+fn find_webp_segments(data: &[u8]) -> Vec<Segment> {
+    riff::chunks(data)
+        .filter_map(|chunk| {
+            let kind = match chunk.fourcc {
+                b"EXIF" => Kind::Exif,
+                b"XMP " => Kind::Xmp,
+                _ => return None,
+            };
+            // Some writers keep the JPEG identifier.
+            let skip = if data[chunk.data.clone()].starts_with(EXIF_ID) {
+                EXIF_ID.len()
+            } else {
+                0
+            };
+            let padded_end = (chunk.data.end + chunk.data.len() % 2).min(data.len());
+            Some(Segment {
+                kind,
+                chunk: chunk.data.start - 8..padded_end,
+                payload: chunk.data.start + skip..chunk.data.end,
+            })
+        })
+        .collect()
+}
+
+impl Format {
+    /// The image with the changed segments replaced by new EXIF data, or
+    /// dropped.
+    fn replace(
+        &self,
+        data: &[u8],
+        changed: &[(Segment, Option<Vec<u8>>)],
+    ) -> Result<Vec<u8>, BoxError> {
+        let mut image = vec![];
+        let mut pos = 0;
+        for (segment, tiff) in changed {
+            image.extend_from_slice(&data[pos..segment.chunk.start]);
+            if let Some(tiff) = tiff {
+                image.extend_from_slice(&self.exif_chunk(tiff)?);
+            }
+            pos = segment.chunk.end;
+        }
+        image.extend_from_slice(&data[pos..]);
+
+        if let Format::WebP = self {
+            // The RIFF header has the size of the whole file.
+            let riff_len = u32::try_from(image.len() - 8)?;
+            image[4..8].copy_from_slice(&riff_len.to_le_bytes());
+
+            // The extended header says which metadata chunks there are.
+            if image.get(12..16) == Some(b"VP8X") {
+                for (segment, tiff) in changed {
+                    match (&segment.kind, tiff) {
+                        (Kind::Exif, None) => image[20] &= !0x08,
+                        (Kind::Xmp, _) => image[20] &= !0x04,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// A segment or chunk with the EXIF data.
+    fn exif_chunk(&self, tiff: &[u8]) -> Result<Vec<u8>, BoxError> {
+        let mut chunk = vec![];
+        match self {
+            Format::Jpeg => {
+                let len = u16::try_from(2 + EXIF_ID.len() + tiff.len())?;
+                chunk.extend_from_slice(&[0xff, 0xe1]);
+                chunk.extend_from_slice(&len.to_be_bytes());
+                chunk.extend_from_slice(EXIF_ID);
+                chunk.extend_from_slice(tiff);
+            }
+            Format::Png => {
+                chunk.extend_from_slice(&u32::try_from(tiff.len())?.to_be_bytes());
+                chunk.extend_from_slice(b"eXIf");
+                chunk.extend_from_slice(tiff);
+                let crc = crc32fast::hash(&chunk[4..]);
+                chunk.extend_from_slice(&crc.to_be_bytes());
+            }
+            Format::WebP => {
+                chunk.extend_from_slice(b"EXIF");
+                chunk.extend_from_slice(&u32::try_from(tiff.len())?.to_le_bytes());
+                chunk.extend_from_slice(tiff);
+                if tiff.len() % 2 == 1 {
+                    chunk.push(0);
+                }
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use exif::Rational;
+    use image::{GenericImageView, ImageFormat, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::riff::tests::animated_webp;
+
+    const XMP_WITH_GPS: &[u8] = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:exif="http://ns.adobe.com/exif/1.0/" exif:GPSLatitude="52,30.0N"/></rdf:RDF></x:xmpmeta>"#;
+    const XMP_WITHOUT_GPS: &[u8] = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"/>"#;
+
+    fn ascii(tag: Tag, value: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+        }
+    }
+
+    /// The TIFF structure of a photo taken with a Canon, on its side.
+    fn exif_data(gps: bool) -> Vec<u8> {
+        let mut fields = vec![
+            ascii(Tag::Make, "Canon"),
+            ascii(Tag::Model, "Canon EOS 5D"),
+            ascii(Tag::DateTimeOriginal, "2024:05:01 12:30:00"),
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+        ];
+        if gps {
+            fields.push(ascii(Tag::GPSLatitudeRef, "N"));
+            fields.push(Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![
+                    Rational { num: 52, denom: 1 },
+                    Rational { num: 30, denom: 1 },
+                    Rational { num: 0, denom: 1 },
+                ]),
+            });
+        }
+
+        tiff(&fields)
+    }
+
+    fn tiff(fields: &[Field]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut data = Cursor::new(vec![]);
+        writer.write(&mut data, false).unwrap();
+        data.into_inner()
+    }
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let img = RgbaImage::from_pixel(4, 2, Rgba([200, 100, 50, 255]));
+        let mut data = Cursor::new(vec![]);
+        DynamicImage::ImageRgba8(img)
+            .to_rgb8()
+            .write_to(&mut data, format)
+            .unwrap();
+        data.into_inner()
+    }
+
+    /// A JPEG with APP1 segments, the identifiers included.
+    fn jpeg(segments: &[&[u8]]) -> Vec<u8> {
+        let image = encode(ImageFormat::Jpeg);
+        let mut data = image[..2].to_vec();
+        for segment in segments {
+            data.extend_from_slice(&[0xff, 0xe1]);
+            data.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+            data.extend_from_slice(segment);
+        }
+        data.extend_from_slice(&image[2..]);
+        data
+    }
+
+    /// A PNG with chunks after the header.
+    fn png(chunks: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let image = encode(ImageFormat::Png);
+        // The signature and the `IHDR` chunk.
+        let mut data = image[..33].to_vec();
+        for (name, content) in chunks {
+            let start = data.len();
+            data.extend_from_slice(&(content.len() as u32).to_be_bytes());
+            data.extend_from_slice(name);
+            data.extend_from_slice(content);
+            let crc = crc32fast::hash(&data[start + 4..]);
+            data.extend_from_slice(&crc.to_be_bytes());
+        }
+        data.extend_from_slice(&image[33..]);
+        data
+    }
+
+    fn png_xmp(compressed: bool, xmp: &[u8]) -> Vec<u8> {
+        let mut text = PNG_XMP_KEYWORD.to_vec();
+        text.extend_from_slice(&[compressed.into(), 0]);
+        text.extend_from_slice(b"en\0\0");
+        text.extend_from_slice(xmp);
+        text
+    }
+
+    fn with_id(id: &[u8], payload: &[u8]) -> Vec<u8> {
+        [id, payload].concat()
+    }
+
+    fn has_gps(data: &[u8]) -> bool {
+        let exif = exif::Reader::new()
+            .read_from_container(&mut Cursor::new(data))
+            .unwrap();
+        let has_gps = exif
+            .fields()
+            .any(|field| field.tag.context() == Context::Gps);
+        has_gps
+    }
+
+    fn assert_photo(metadata: &Metadata) {
+        assert_eq!(metadata.orientation, 6);
+        assert_eq!(metadata.camera.as_deref(), Some("Canon EOS 5D"));
+        let taken_at = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 30, 0);
+        assert_eq!(metadata.taken_at, taken_at);
+    }
+
+    #[test]
+    fn read_metadata() {
+        let data = jpeg(&[&with_id(EXIF_ID, &exif_data(false))]);
+        assert_photo(&Metadata::read(&data));
+
+        let exif = tiff(&[ascii(Tag::Make, "NIKON"), ascii(Tag::Model, "D750")]);
+        let metadata = Metadata::read(&jpeg(&[&with_id(EXIF_ID, &exif)]));
+        assert_eq!(metadata.camera.as_deref(), Some("NIKON D750"));
+        assert_eq!(metadata.taken_at, None);
+        assert_eq!(metadata.orientation, 1);
+
+        let metadata = Metadata::read(&jpeg(&[]));
+        assert_eq!((metadata.orientation, metadata.camera), (1, None));
+    }
+
+    #[test]
+    fn orientation() {
+        let mut metadata = Metadata::default();
+        assert_eq!(metadata.upright_size(300, 200), (300, 200));
+        metadata.orientation = 6;
+        assert_eq!(metadata.upright_size(300, 200), (200, 300));
+
+        // A red pixel at the top left of a wide image.
+        let mut img = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        img.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let img = DynamicImage::ImageRgba8(img);
+        let red_at = |orientation| {
+            let upright = orient(img.clone(), orientation);
+            let (width, height) = upright.dimensions();
+            let red = upright
+                .pixels()
+                .find(|(_, _, pixel)| pixel[0] == 255)
+                .unwrap();
+            (width, height, red.0, red.1)
+        };
+        assert_eq!(red_at(1), (2, 1, 0, 0));
+        assert_eq!(red_at(2), (2, 1, 1, 0));
+        assert_eq!(red_at(3), (2, 1, 1, 0));
+        assert_eq!(red_at(6), (1, 2, 0, 0));
+        assert_eq!(red_at(8), (1, 2, 0, 1));
+    }
+
+    #[test]
+    fn strip_gps_from_jpeg() {
+        let data = jpeg(&[
+            &with_id(EXIF_ID, &exif_data(true)),
+            &with_id(XMP_ID, XMP_WITH_GPS),
+            &with_id(EXTENDED_XMP_ID, XMP_WITH_GPS),
+        ]);
+        assert!(has_gps(&data));
+
+        let stripped = strip_gps(&data).unwrap().unwrap();
+        assert!(!has_gps(&stripped));
+        assert!(!xmp_has_gps(&stripped));
+        assert_photo(&Metadata::read(&stripped));
+        let img = image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg).unwrap();
+        assert_eq!(img.dimensions(), (4, 2));
+    }
+
+    #[test]
+    fn strip_gps_from_png() {
+        let data = png(&[
+            (b"eXIf", &exif_data(true)),
+            (b"iTXt", &png_xmp(false, XMP_WITH_GPS)),
+            // Compressed packets can't be checked.
+            (b"iTXt", &png_xmp(true, b"\x78\x9c")),
+        ]);
+        assert!(has_gps(&data));
+
+        let stripped = strip_gps(&data).unwrap().unwrap();
+        assert!(!has_gps(&stripped));
+        assert_eq!(find_png_segments(&stripped).len(), 1);
+        assert_photo(&Metadata::read(&stripped));
+        // The decoder checks the checksums.
+        let img = image::load_from_memory_with_format(&stripped, ImageFormat::Png).unwrap();
+        assert_eq!(img.dimensions(), (4, 2));
+    }
+
+    #[test]
+    fn strip_gps_from_webp() {
+        let mut data = animated_webp(4, 2, 0);
+        // The container has EXIF data and an XMP packet.
+        data[20] |= 0x08 | 0x04;
+        for (fourcc, content) in [(b"EXIF", exif_data(true)), (b"XMP ", XMP_WITH_GPS.to_vec())] {
+            data.extend_from_slice(fourcc);
+            data.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            data.extend_from_slice(&content);
+            if content.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+        let len = data.len() as u32 - 8;
+        data[4..8].copy_from_slice(&len.to_le_bytes());
+        assert!(has_gps(&data));
+
+        let stripped = strip_gps(&data).unwrap().unwrap();
+        assert!(!has_gps(&stripped));
+        assert!(!xmp_has_gps(&stripped));
+        assert_photo(&Metadata::read(&stripped));
+        assert_eq!(stripped[20] & (0x08 | 0x04), 0x08);
+        let len = u32::from_le_bytes(stripped[4..8].try_into().unwrap());
+        assert_eq!(len as usize, stripped.len() - 8);
+    }
+
+    #[test]
+    fn images_without_a_location_are_kept() {
+        let data = jpeg(&[
+            &with_id(EXIF_ID, &exif_data(false)),
+            &with_id(XMP_ID, XMP_WITHOUT_GPS),
+        ]);
+        assert!(strip_gps(&data).unwrap().is_none());
+        let data = png(&[(b"iTXt", &png_xmp(false, XMP_WITHOUT_GPS))]);
+        assert!(strip_gps(&data).unwrap().is_none());
+        assert!(strip_gps(b"GIF89a").unwrap().is_none());
+    }
+
+    #[test]
+    fn broken_exif_is_dropped() {
+        let data = jpeg(&[&with_id(EXIF_ID, b"MM\0*broken")]);
+        let stripped = strip_gps(&data).unwrap().unwrap();
+        assert!(find_jpeg_segments(&stripped).is_empty());
+        let img = image::load_from_memory_with_format(&stripped, ImageFormat::Jpeg).unwrap();
+        assert_eq!(img.dimensions(), (4, 2));
+    }
+}
@@ -1,7 +1,81 @@
 use mediatype::{
-    names::{GIF, IMAGE, WEBP},
+    names::{AVIF, GIF, IMAGE, JPEG, WEBP},
     MediaType,
 };
 
+pub(crate) const IMAGE_AVIF: MediaType = MediaType::new(IMAGE, AVIF);
 pub(crate) const IMAGE_GIF: MediaType = MediaType::new(IMAGE, GIF);
+pub(crate) const IMAGE_JPEG: MediaType = MediaType::new(IMAGE, JPEG);
 pub(crate) const IMAGE_WEBP: MediaType = MediaType::new(IMAGE, WEBP);
+
+/// Picks the type the client prefers from an `Accept` header, e.g.
+/// `image/avif,image/webp,*/*;q=0.8`. The types are in the order the server
+/// prefers them, which wins between types the client likes as much. The
+/// first type is the default, also when the client accepts none of them.
+pub(crate) fn negotiate(accept: Option<&str>, types: &[&str]) -> usize {
+    let Some(accept) = accept else {
+        return 0;
+    };
+
+    // The quality of every range, e.g. `("image/*", 0.8)`.
+    let ranges = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let range = params.next()?.trim().to_ascii_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((range, quality))
+        })
+        .collect::<Vec<_>>();
+
+    // The most specific range that matches a type decides its quality.
+    let quality = |mime_type: &str| {
+        let main = mime_type.split('/').next().unwrap_or_default();
+        let specificity = |range: &str| match range.split_once('/') {
+            _ if range == mime_type => Some(2),
+            Some((range_main, "*")) if range_main == main => Some(1),
+            Some(("*", "*")) => Some(0),
+            _ => None,
+        };
+        ranges
+            .iter()
+            .filter_map(|(range, quality)| Some((specificity(range)?, *quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, quality)| quality)
+    };
+
+    let mut best = (0, 0.0);
+    for (i, mime_type) in types.iter().enumerate() {
+        let quality = quality(mime_type);
+        if quality > best.1 {
+            best = (i, quality);
+        }
+    }
+    best.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [&str; 3] = ["image/webp", "image/avif", "image/jpeg"];
+
+    #[test]
+    fn negotiate_prefers_the_client() {
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert_eq!(negotiate(Some(chrome), &TYPES), 0);
+        assert_eq!(negotiate(Some("image/avif,image/*;q=0.5"), &TYPES), 1);
+        assert_eq!(negotiate(Some("image/jpeg"), &TYPES), 2);
+        assert_eq!(negotiate(Some("image/webp;q=0,*/*"), &TYPES), 1);
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_the_first_type() {
+        assert_eq!(negotiate(None, &TYPES), 0);
+        assert_eq!(negotiate(Some("text/html"), &TYPES), 0);
+        assert_eq!(negotiate(Some("image/png"), &TYPES[1..]), 0);
+    }
+}
@@ -0,0 +1,188 @@
+use std::{future::Future, sync::Arc};
+
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    Semaphore,
+};
+
+use crate::{
+    blob::BlobKey,
+    db,
+    error::BoxError,
+    img,
+    state::{AppState, Image, ImageStatus},
+};
+
+/// How many images can wait for a worker, uploads are refused when the
+/// queue is full. Only their ids wait, the originals are in the blob store.
+const QUEUE_SIZE: usize = 1000;
+
+/// The queue of images whose renditions are generated, see `run`.
+pub(crate) fn channel() -> (Sender<i64>, Receiver<i64>) {
+    mpsc::channel(QUEUE_SIZE)
+}
+
+/// Generates the renditions of queued images in the background.
+pub(crate) async fn run(state: AppState, queue: Receiver<i64>) {
+    let workers = state.workers.clone();
+    dispatch(queue, workers, move |id| {
+        let state = state.clone();
+        async move { process(&state, id).await }
+    })
+    .await;
+}
+
+/// At most one image per worker is processed at the same time, the others
+/// wait in the queue.
+async fn dispatch<F, Fut>(mut queue: Receiver<i64>, workers: Arc<Semaphore>, process: F)
+where
+    F: Fn(i64) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    while let Some(id) = queue.recv().await {
+        let Ok(permit) = workers.clone().acquire_owned().await else {
+            return;
+        };
+        let processing = process(id);
+        tokio::spawn(async move {
+            processing.await;
+            drop(permit);
+        });
+    }
+}
+
+async fn process(state: &AppState, id: i64) {
+    // Images deleted while they waited are skipped, the others stay queued
+    // until the server restarts.
+    let image = match db::get_image(state, id).await {
+        Ok(image) => image,
+        Err(err) => {
+            tracing::error!("could not load image {id}: {err}");
+            return;
+        }
+    };
+
+    let (status, error) = match render(state, &image).await {
+        Ok(()) => {
+            tracing::info!("processed image {id}");
+            (ImageStatus::Ready, None)
+        }
+        Err(err) => {
+            tracing::error!("could not process image {id}: {err}");
+            (ImageStatus::Failed, Some(err.to_string()))
+        }
+    };
+
+    if let Err(err) = db::set_status(state, id, status, error.as_deref()).await {
+        tracing::error!("could not update image {id}: {err}");
+    }
+}
+
+async fn render(state: &AppState, image: &Image) -> Result<(), BoxError> {
+    let id = image.id;
+    let original = state
+        .blobs
+        .get(&BlobKey::Original(id))
+        .await?
+        .ok_or("the original upload is missing")?;
+
+    let mime_type = image.mime_type.clone();
+    let focal_point = image.focal_point();
+    let specs = state.renditions.clone();
+    let encoders = state.encoders.clone();
+
+    // Decoding and encoding is CPU bound, keep it off the async runtime.
+    let (renditions, palette) = tokio::task::spawn_blocking(move || {
+        img::renditions(&original, &mime_type, &specs, &encoders, focal_point)
+    })
+    .await??;
+
+    // The rows are updated in place so that a re-render keeps serving the
+    // old renditions until their blobs are replaced, the database store
+    // needs the rows before the blobs.
+    let old_names = db::get_rendition_names(state, id).await?;
+    db::insert_renditions(state, id, &palette, &renditions).await?;
+
+    for rendition in &renditions {
+        let key = BlobKey::Rendition(id, rendition.name.clone());
+        state.blobs.put(&key, &rendition.data).await?;
+    }
+
+    // Renditions in formats that aren't configured anymore, or from before
+    // there were formats, blobs left behind are only logged.
+    let names: Vec<String> = renditions
+        .iter()
+        .map(|rendition| rendition.name.clone())
+        .collect();
+    let stale_names = old_names.into_iter().filter(|name| !names.contains(name));
+    for name in stale_names {
+        let key = BlobKey::Rendition(id, name);
+        if let Err(err) = state.blobs.delete(&key).await {
+            tracing::error!("could not delete blob {key}: {err}");
+        }
+    }
+    db::delete_other_renditions(state, id, &names).await?;
+
+    Ok(())
+}
+
+/// Queues the images that were still processing when the server stopped,
+/// waiting for room in the queue.
+pub(crate) async fn resume(state: &AppState) -> Result<usize, BoxError> {
+    let images = db::get_images_by_status(state, ImageStatus::Processing).await?;
+    for image in &images {
+        state.queue.send(image.id).await?;
+    }
+    Ok(images.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn one_image_per_worker() {
+        let (queue, queued) = channel();
+        let workers = Arc::new(Semaphore::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let processed = Arc::new(AtomicUsize::new(0));
+
+        for id in 0..6 {
+            queue.send(id).await.unwrap();
+        }
+        drop(queue);
+
+        let counters = (running.clone(), most_running.clone(), processed.clone());
+        dispatch(queued, workers.clone(), move |_| {
+            let (running, most_running, processed) = counters.clone();
+            async move {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                processed.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .await;
+
+        // The last images are done once their workers are free again.
+        let _all = workers.acquire_many(2).await.unwrap();
+        assert_eq!(processed.load(Ordering::SeqCst), 6);
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn full_queue() {
+        let (queue, _queued) = channel();
+        for id in 0..QUEUE_SIZE {
+            queue.try_send(id as i64).unwrap();
+        }
+        assert!(queue.try_reserve().is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use std::ops::Range;
+
+/// A chunk of a RIFF container, WebP images are one.
+pub(crate) struct Chunk<'a> {
+    pub(crate) fourcc: &'a [u8],
+    /// The data of the chunk.
+    pub(crate) data: Range<usize>,
+}
+
+/// The chunks after the `RIFF` header, a truncated chunk ends them.
+pub(crate) fn chunks(data: &[u8]) -> impl Iterator<Item = Chunk<'_>> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let start = pos + 8;
+        // Chunks are padded to an even size.
+        let end = start.checked_add(len)?.checked_add(len % 2)?;
+        if start + len > data.len() {
+            return None;
+        }
+
+        let chunk = Chunk {
+            fourcc: &header[..4],
+            data: start..start + len,
+        };
+        pos = end;
+        Some(chunk)
+    })
+}
+
+/// How many frames an animated WebP has and the size of its canvas, read
+/// from the container without decoding anything. Still images have none.
+pub(crate) fn webp_animation(data: &[u8]) -> Option<(u32, u32, u32)> {
+    let mut canvas = None;
+    let mut frames = 0;
+    for chunk in chunks(data) {
+        match chunk.fourcc {
+            // The canvas size minus one, in 24 bits each.
+            b"VP8X" => {
+                let header = data.get(chunk.data.start..chunk.data.start + 10)?;
+                let width = u32::from_le_bytes([header[4], header[5], header[6], 0]) + 1;
+                let height = u32::from_le_bytes([header[7], header[8], header[9], 0]) + 1;
+                canvas = Some((width, height));
+            }
+            b"ANMF" => frames += 1,
+            _ => {}
+        }
+    }
+
+    let (width, height) = canvas?;
+    (frames > 0).then_some((frames, width, height))
+}
+
+/// The first frame of an animated WebP as a still WebP, so that it can be
+/// decoded without the others. The canvas around the frame is left out.
+pub(crate) fn first_webp_frame(data: &[u8]) -> Option<Vec<u8>> {
+    let frame = chunks(data).find(|chunk| chunk.fourcc == b"ANMF")?;
+    // The position, size, duration and flags of the frame, then its chunks.
+    let header = data.get(frame.data.start..frame.data.start + 16)?;
+    let mut still = b"RIFF\0\0\0\0WEBP".to_vec();
+    still.extend_from_slice(&data[frame.data.start + 16..frame.data.end]);
+
+    // Transparent frames need the extended header.
+    if chunks(&still).any(|chunk| chunk.fourcc == b"ALPH") {
+        let mut vp8x = b"VP8X".to_vec();
+        vp8x.extend_from_slice(&10u32.to_le_bytes());
+        vp8x.extend_from_slice(&[0x10, 0, 0, 0]);
+        vp8x.extend_from_slice(&header[6..12]);
+        still.splice(12..12, vp8x);
+    }
+
+    let len = u32::try_from(still.len() - 8).ok()?;
+    still[4..8].copy_from_slice(&len.to_le_bytes());
+    Some(still)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A WebP container with a canvas and empty frames.
+    pub(crate) fn animated_webp(width: u32, height: u32, frames: usize) -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WEBP".to_vec();
+        data.extend_from_slice(b"VP8X");
+        data.extend_from_slice(&10u32.to_le_bytes());
+        data.extend_from_slice(&[0x02, 0, 0, 0]);
+        data.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        for _ in 0..frames {
+            data.extend_from_slice(b"ANMF");
+            data.extend_from_slice(&3u32.to_le_bytes());
+            data.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        let len = data.len() as u32 - 8;
+        data[4..8].copy_from_slice(&len.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn chunks_of_a_webp() {
+        let data = animated_webp(300, 200, 2);
+        let found = chunks(&data)
+            .map(|chunk| (chunk.fourcc.to_vec(), chunk.data))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                (b"VP8X".to_vec(), 20..30),
+                // Odd chunks are padded.
+                (b"ANMF".to_vec(), 38..41),
+                (b"ANMF".to_vec(), 50..53),
+            ]
+        );
+        assert_eq!(webp_animation(&data), Some((2, 300, 200)));
+
+        // A truncated chunk ends the container.
+        assert_eq!(chunks(&data[..40]).count(), 1);
+        assert_eq!(webp_animation(&animated_webp(300, 200, 0)), None);
+    }
+}
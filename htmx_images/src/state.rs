@@ -1,39 +1,167 @@
-use std::sync::Arc;
+use std::{fmt, str::FromStr, sync::Arc};
 
 use serde::Deserialize;
 use sqlx::{postgres::PgPool, prelude::FromRow};
+use tokio::sync::{mpsc::Sender, Semaphore};
+
+use crate::{auth::LoginThrottle, blob::BlobStore, encode::Encoder, img::RenditionSpec};
 
 pub(crate) type AppState = Arc<State>;
 
 pub(crate) struct State {
     pub(crate) database: PgPool,
+    /// Where the data of originals and renditions is kept.
+    pub(crate) blobs: Box<dyn BlobStore>,
+    /// The renditions generated for every upload.
+    pub(crate) renditions: Vec<RenditionSpec>,
+    /// The formats of the renditions, the preferred one first.
+    pub(crate) encoders: Vec<Arc<dyn Encoder>>,
+    /// Images waiting for their renditions, see `queue::run`.
+    pub(crate) queue: Sender<i64>,
+    /// One permit per upload that can be processed at the same time.
+    pub(crate) workers: Arc<Semaphore>,
+    pub(crate) duplicates: Duplicates,
+    pub(crate) login_throttle: LoginThrottle,
 }
 
 impl State {
-    pub(crate) fn new(database: PgPool) -> AppState {
-        Arc::new(Self { database })
+    pub(crate) fn new(
+        database: PgPool,
+        blobs: Box<dyn BlobStore>,
+        renditions: Vec<RenditionSpec>,
+        encoders: Vec<Arc<dyn Encoder>>,
+        queue: Sender<i64>,
+        workers: usize,
+        duplicates: Duplicates,
+    ) -> AppState {
+        Arc::new(Self {
+            database,
+            blobs,
+            renditions,
+            encoders,
+            queue,
+            workers: Arc::new(Semaphore::new(workers)),
+            duplicates,
+            login_throttle: LoginThrottle::default(),
+        })
+    }
+}
+
+/// What happens to uploads that look like an image that's already there.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Duplicates {
+    pub(crate) policy: DuplicatePolicy,
+    /// How many of the 64 bits of the perceptual hashes may differ.
+    pub(crate) max_distance: u32,
+}
+
+impl Default for Duplicates {
+    fn default() -> Self {
+        Self {
+            policy: DuplicatePolicy::Warn,
+            max_distance: 4,
+        }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DuplicatePolicy {
+    Allow,
+    /// The upload is kept and marked as a duplicate.
+    Warn,
+    Reject,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(DuplicatePolicy::Allow),
+            "warn" => Ok(DuplicatePolicy::Warn),
+            "reject" => Ok(DuplicatePolicy::Reject),
+            _ => Err(format!(
+                "unknown duplicate policy `{s}`, expected allow, warn or reject"
+            )),
+        }
+    }
+}
+
+/// Uploads are processed in the background, see `queue::process`.
+#[derive(Deserialize, sqlx::Type, Clone, Copy, PartialEq, Eq, Debug)]
+#[sqlx(type_name = "image_status", rename_all = "lowercase")]
+pub(crate) enum ImageStatus {
+    Processing,
+    Ready,
+    Failed,
+}
+
 #[derive(Deserialize, FromRow)]
 pub(crate) struct Image {
     pub(crate) id: i64,
     pub(crate) file_name: String,
     pub(crate) mime_type: String,
-    pub(crate) image_data: Vec<u8>,
     pub(crate) dominant_color: Option<i32>,
     pub(crate) created_at: chrono::DateTime<chrono::Utc>,
+    pub(crate) status: ImageStatus,
+    /// Why processing failed.
+    pub(crate) error: Option<String>,
+    pub(crate) camera: Option<String>,
+    pub(crate) taken_at: Option<chrono::NaiveDateTime>,
+    /// The size of the upright original.
+    pub(crate) width: Option<i32>,
+    pub(crate) height: Option<i32>,
+    /// Where the thumbnail is centered, as a fraction of the width and height.
+    pub(crate) focal_x: Option<f32>,
+    pub(crate) focal_y: Option<f32>,
+    pub(crate) renditions_version: i32,
+    /// The main colors, the dominant color first.
+    pub(crate) palette: Vec<i32>,
+    /// An older image that looked the same when this one was uploaded.
+    pub(crate) duplicate_of: Option<i64>,
+    /// The account that uploaded the image, older uploads have none.
+    pub(crate) owner_id: Option<i64>,
 }
 
 impl Image {
-    pub(crate) fn as_base64(&self) -> String {
-        use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+    /// The url of the original upload, see `handler::image_raw`.
+    pub(crate) fn src(&self) -> String {
+        format!("/images/{}/raw", self.id)
+    }
 
-        STANDARD_NO_PAD.encode(&self.image_data)
+    /// The url of a rendition, see `handler::image_rendition`.
+    /// Renditions are generated again when the focal point changes, the
+    /// version keeps browsers from showing the old ones.
+    pub(crate) fn rendition_src(&self, name: &str) -> String {
+        format!(
+            "/images/{}/renditions/{name}?v={}",
+            self.id, self.renditions_version
+        )
     }
 
-    pub(crate) fn src(&self) -> String {
-        format!("data:{};base64,{}", self.mime_type, self.as_base64())
+    /// Originals never change once uploaded, so the id, upload time and
+    /// rendition with its version identify their data.
+    pub(crate) fn etag(&self, rendition: Option<&str>) -> String {
+        let micros = self.created_at.timestamp_micros();
+        match rendition {
+            Some(name) => format!(
+                "\"{}-{micros}-{name}-{}\"",
+                self.id, self.renditions_version
+            ),
+            None => format!("\"{}-{micros}\"", self.id),
+        }
+    }
+
+    pub(crate) fn focal_point(&self) -> Option<(f32, f32)> {
+        self.focal_x.zip(self.focal_y)
+    }
+
+    /// The position of the image in the gallery.
+    pub(crate) fn cursor(&self) -> Cursor {
+        Cursor {
+            created_at: self.created_at,
+            id: self.id,
+        }
     }
 
     pub(crate) fn short_date(&self) -> String {
@@ -42,7 +170,7 @@ impl Image {
 
     pub(crate) fn dominant_hex(&self, alpha: f32) -> String {
         assert!(
-            alpha >= 0.0 && alpha <= 1.0,
+            (0.0..=1.0).contains(&alpha),
             "alpha should be between 0 and 1"
         );
 
@@ -56,3 +184,214 @@ impl Image {
         format!("#{:02X}{:02X}{:02X}{:02X}", red, green, blue, alpha)
     }
 }
+
+/// The position of an image in the gallery, newest first. It's written as
+/// `<upload time in micros>.<id>` in urls.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String")]
+pub(crate) struct Cursor {
+    pub(crate) created_at: chrono::DateTime<chrono::Utc>,
+    pub(crate) id: i64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.created_at.timestamp_micros(), self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cursor `{s}`");
+        let (micros, id) = s.split_once('.').ok_or_else(invalid)?;
+        let micros = micros.parse().map_err(|_| invalid())?;
+        Ok(Cursor {
+            created_at: chrono::DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Which images the gallery shows, all of them by default.
+#[derive(Deserialize, Clone, Default, Debug)]
+pub(crate) struct Filter {
+    pub(crate) album: Option<i64>,
+    /// Normalized, see `normalize_tag`.
+    pub(crate) tag: Option<String>,
+}
+
+impl Filter {
+    /// The url of a page of the filtered gallery, normalized tags are safe
+    /// in urls.
+    pub(crate) fn images_url(&self, before: Option<Cursor>) -> String {
+        let mut params = vec![];
+        if let Some(album) = self.album {
+            params.push(format!("album={album}"));
+        }
+        if let Some(tag) = &self.tag {
+            params.push(format!("tag={tag}"));
+        }
+        if let Some(before) = before {
+            params.push(format!("before={before}"));
+        }
+
+        if params.is_empty() {
+            "/images".to_string()
+        } else {
+            format!("/images?{}", params.join("&"))
+        }
+    }
+}
+
+#[derive(FromRow, Clone, Debug)]
+pub(crate) struct Album {
+    pub(crate) id: i64,
+    pub(crate) name: String,
+    pub(crate) owner_id: Option<i64>,
+    pub(crate) image_count: i64,
+}
+
+/// Tags are free-form but kept simple so that they can be used in urls:
+/// lowercase letters, digits, `-` and `_`, words are joined with `-`.
+pub(crate) fn normalize_tag(tag: &str) -> Option<String> {
+    const MAX_LEN: usize = 50;
+
+    let tag = tag
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_LEN)
+        .collect::<String>();
+    (!tag.is_empty()).then_some(tag)
+}
+
+/// A logged in account, see `auth`.
+#[derive(FromRow, Clone, Debug)]
+pub(crate) struct User {
+    pub(crate) id: i64,
+    pub(crate) name: String,
+    pub(crate) is_admin: bool,
+}
+
+impl User {
+    /// Only the owner of an image or an admin can change or delete it.
+    pub(crate) fn can_edit(&self, image: &Image) -> bool {
+        self.is_admin || image.owner_id == Some(self.id)
+    }
+
+    /// The same goes for renaming an album and filing images into it.
+    pub(crate) fn can_edit_album(&self, album: &Album) -> bool {
+        self.is_admin || album.owner_id == Some(self.id)
+    }
+}
+
+#[derive(FromRow)]
+pub(crate) struct RenditionMeta {
+    pub(crate) name: String,
+    pub(crate) mime_type: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_tags() {
+        assert_eq!(normalize_tag("Red  Car").as_deref(), Some("red-car"));
+        assert_eq!(normalize_tag(" été_2024 ").as_deref(), Some("été_2024"));
+        assert_eq!(normalize_tag("cats&album=1").as_deref(), Some("catsalbum1"));
+        assert_eq!(normalize_tag("a/b?c#d").as_deref(), Some("abcd"));
+        assert_eq!(
+            normalize_tag(&"x".repeat(80)).map(|tag| tag.len()),
+            Some(50)
+        );
+        assert_eq!(normalize_tag(" &?# "), None);
+        assert_eq!(normalize_tag(""), None);
+    }
+
+    #[test]
+    fn album_owners() {
+        let user = |id, is_admin| User {
+            id,
+            name: format!("user{id}"),
+            is_admin,
+        };
+        let album = |owner_id| Album {
+            id: 1,
+            name: "Holidays".to_string(),
+            owner_id,
+            image_count: 0,
+        };
+        assert!(user(1, false).can_edit_album(&album(Some(1))));
+        assert!(!user(2, false).can_edit_album(&album(Some(1))));
+        assert!(!user(2, false).can_edit_album(&album(None)));
+        assert!(user(3, true).can_edit_album(&album(Some(1))));
+        assert!(user(3, true).can_edit_album(&album(None)));
+    }
+
+    #[test]
+    fn images_urls() {
+        assert_eq!(Filter::default().images_url(None), "/images");
+
+        let before = Cursor {
+            created_at: chrono::DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            id: 42,
+        };
+        let filter = Filter {
+            album: Some(3),
+            tag: normalize_tag("Red Car"),
+        };
+        assert_eq!(
+            filter.images_url(Some(before)),
+            "/images?album=3&tag=red-car&before=1700000000123456.42"
+        );
+
+        let filter = Filter {
+            album: None,
+            tag: Some("cats".to_string()),
+        };
+        assert_eq!(filter.images_url(None), "/images?tag=cats");
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            created_at: chrono::DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            id: 42,
+        };
+        assert_eq!(cursor.to_string(), "1700000000123456.42");
+        assert_eq!(cursor.to_string().parse(), Ok(cursor));
+    }
+
+    #[test]
+    fn malformed_cursors() {
+        for cursor in [
+            "",
+            "1700000000123456",
+            "1700000000123456.",
+            ".42",
+            "yesterday.42",
+            "1700000000123456.x",
+            "1700000000123456.42.1",
+            "99999999999999999999.42",
+        ] {
+            assert!(
+                cursor.parse::<Cursor>().is_err(),
+                "{cursor} should be invalid"
+            );
+        }
+    }
+}
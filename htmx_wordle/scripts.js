@@ -28,4 +28,28 @@ document.addEventListener('alpine:init', () => {
 // https://github.com/alpinejs/alpine/discussions/2924
 document.addEventListener('htmx:beforeHistorySave', (evt) => {
     document.querySelectorAll('[x-from-template]').forEach((e) => e.remove());
-})
\ No newline at end of file
+})
+
+// Render client errors (e.g. rate limits) instead of ignoring them.
+document.addEventListener('htmx:beforeSwap', (evt) => {
+    const status = evt.detail.xhr.status;
+    if (status >= 400 && status < 500) {
+        evt.detail.shouldSwap = true;
+        evt.detail.isError = false;
+    }
+})
+
+
+// Colour-blind palette, it's remembered per player in a cookie.
+function applyPalette() {
+    const colorblind = document.cookie.split('; ').includes('palette=colorblind');
+    document.documentElement.classList.toggle('colorblind', colorblind);
+}
+
+function togglePalette() {
+    const colorblind = !document.documentElement.classList.contains('colorblind');
+    document.cookie = `palette=${colorblind ? 'colorblind' : 'default'}; path=/; max-age=31536000; samesite=lax`;
+    applyPalette();
+}
+
+applyPalette();
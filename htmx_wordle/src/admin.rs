@@ -0,0 +1,595 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post},
+    Form, Router,
+};
+use chrono::{NaiveDate, Utc};
+use maud::{html, Markup};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    base, cookie,
+    game::{short_id, Game, GameId, WordState},
+    rate_limit::{self, RateLimiter},
+    toast_markup, AppState,
+};
+
+const COOKIE_NAME: &str = "admin";
+/// How long an admin stays logged in.
+const SESSION_SECS: u64 = 24 * 60 * 60;
+
+/// How many times each client can try the token per `LOGIN_WINDOW`.
+const LOGIN_LIMIT: u32 = 5;
+const LOGIN_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many of the newest games are listed on the dashboard.
+const GAMES_LIMIT: usize = 50;
+
+/// The admin area, everything but logging in needs the admin token. Without a
+/// `WORDLE_ADMIN_TOKEN` there's no admin area at all.
+pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(dashboard))
+        .route("/words", post(add_word).delete(remove_word))
+        .route("/reload", post(reload))
+        .route("/schedule", post(schedule))
+        .route("/schedule/:date", delete(unschedule))
+        .route("/games/:id", get(inspect_game).delete(delete_game))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+}
+
+/// Logged in admins and how often logging in was tried. The cookie holds a
+/// random session id instead of the token, sessions are forgotten when the
+/// app restarts.
+pub struct Sessions {
+    login_limiter: RateLimiter<IpAddr>,
+    /// When each session ends.
+    sessions: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self {
+            login_limiter: RateLimiter::new(LOGIN_LIMIT, LOGIN_WINDOW),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Sessions {
+    fn start(&self, now: Instant) -> Uuid {
+        let id = Uuid::new_v4();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, end| *end > now);
+        sessions.insert(id, now + Duration::from_secs(SESSION_SECS));
+        id
+    }
+
+    fn is_valid(&self, id: &str, now: Instant) -> bool {
+        let Ok(id) = id.parse::<Uuid>() else {
+            return false;
+        };
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(&id).is_some_and(|end| *end > now)
+    }
+
+    fn end(&self, id: &str) {
+        if let Ok(id) = id.parse::<Uuid>() {
+            self.sessions.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+/// Compares tokens in constant time so that they can't be guessed from how
+/// long the comparison takes.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Admins have a session cookie, the token is only ever sent to log in so
+/// that every try of it is limited.
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    if state.admin_token.is_none() {
+        return false;
+    }
+
+    cookie::get(headers, COOKIE_NAME)
+        .is_some_and(|session| state.admin_sessions.is_valid(session, Instant::now()))
+}
+
+async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.admin_token.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if is_admin(&state, &headers) {
+        next.run(request).await
+    } else if headers.get("hx-request").is_some() {
+        (
+            StatusCode::UNAUTHORIZED,
+            toast_markup(html! { "log in again" }),
+        )
+            .into_response()
+    } else {
+        Redirect::to("/admin/login").into_response()
+    }
+}
+
+fn login_markup(error: Option<&str>) -> Markup {
+    base(html! {
+        div class="mx-auto" style="max-width:400px;" {
+            h1 { "🔐 Admin" }
+            form method="post" action="/admin/login" {
+                @if let Some(error) = error {
+                    div .alert .alert-danger role="alert" { (error) }
+                }
+                input
+                type="password"
+                name="token"
+                class="form-control mb-2"
+                aria-label="Admin token"
+                placeholder="Admin token"
+                autofocus;
+                button type="submit" class="btn btn-primary" { "Log in" }
+            }
+        }
+    })
+}
+
+async fn login_page(State(state): State<Arc<AppState>>) -> Response {
+    match state.admin_token {
+        Some(_) => login_markup(None).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    token: String,
+}
+
+async fn login(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    let Some(admin_token) = &state.admin_token else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Every client has its own limit, so that nobody can lock out the admin.
+    let now = Instant::now();
+    let sessions = &state.admin_sessions;
+    let client = rate_limit::client_address(&headers, connect_info);
+    if let Err(wait) = sessions.login_limiter.check(client, now) {
+        let error = format!("too many tries, wait {}s", wait.as_secs());
+        return (StatusCode::TOO_MANY_REQUESTS, login_markup(Some(&error))).into_response();
+    }
+
+    if !tokens_match(&form.token, admin_token) {
+        return (StatusCode::UNAUTHORIZED, login_markup(Some("wrong token"))).into_response();
+    }
+
+    // The cookie only goes to the admin area.
+    let session = sessions.start(now);
+    let cookie = format!(
+        "{COOKIE_NAME}={session}; Path=/admin; Max-Age={SESSION_SECS}; HttpOnly; Secure; SameSite=Strict"
+    );
+    ([(SET_COOKIE, cookie)], Redirect::to("/admin")).into_response()
+}
+
+async fn logout(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if let Some(session) = cookie::get(&headers, COOKIE_NAME) {
+        state.admin_sessions.end(session);
+    }
+    let cookie =
+        format!("{COOKIE_NAME}=; Path=/admin; Max-Age=0; HttpOnly; Secure; SameSite=Strict");
+    ([(SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+}
+
+async fn dashboard(State(state): State<Arc<AppState>>) -> Markup {
+    let today = Utc::now().date_naive();
+
+    base(html! {
+        div class="d-flex align-items-center mb-3" {
+            h1 class="me-auto" { "🔐 Admin" }
+            form method="post" action="/admin/logout" {
+                button type="submit" class="btn btn-outline-secondary btn-sm" { "Log out" }
+            }
+        }
+        (words_markup(state.dictionary.read().await.words.len(), None))
+        (schedule_markup(&state.dictionary.read().await.schedule, today, None))
+        (games_markup(&*state.games.read().await))
+        div #admin-game {}
+    })
+}
+
+/// The dictionary section, words are added and removed one at a time.
+fn words_markup(count: usize, toast: Option<Markup>) -> Markup {
+    html! {
+        section #admin-words class="mb-4" {
+            @if let Some(toast) = toast {
+                (toast)
+            }
+            h2 class="h4" { "Dictionary " small .text-secondary { (count) " words" } }
+            form
+            class="d-flex gap-2"
+            hx-target="#admin-words"
+            hx-swap="outerHTML"
+            {
+                input
+                type="text"
+                name="word"
+                class="form-control form-control-sm w-auto"
+                aria-label="Word"
+                placeholder="word"
+                maxlength="5"
+                required;
+                button hx-post="/admin/words" class="btn btn-sm btn-primary" { "Add" }
+                button hx-delete="/admin/words" class="btn btn-sm btn-outline-danger" { "Remove" }
+                button
+                hx-post="/admin/reload"
+                hx-confirm="Reload the dictionary from disk?"
+                class="btn btn-sm btn-outline-secondary"
+                { "Reload" }
+            }
+        }
+    }
+}
+
+/// Upcoming daily answers, past ones are no longer interesting.
+fn schedule_markup(
+    schedule: &BTreeMap<NaiveDate, String>,
+    today: NaiveDate,
+    toast: Option<Markup>,
+) -> Markup {
+    html! {
+        section #admin-schedule class="mb-4" {
+            @if let Some(toast) = toast {
+                (toast)
+            }
+            h2 class="h4" { "Daily answers" }
+            form
+            class="d-flex gap-2 mb-2"
+            hx-post="/admin/schedule"
+            hx-target="#admin-schedule"
+            hx-swap="outerHTML"
+            {
+                input
+                type="date"
+                name="date"
+                class="form-control form-control-sm w-auto"
+                aria-label="Date"
+                min=(today)
+                required;
+                input
+                type="text"
+                name="word"
+                class="form-control form-control-sm w-auto"
+                aria-label="Word"
+                placeholder="word"
+                maxlength="5"
+                required;
+                button type="submit" class="btn btn-sm btn-primary" { "Schedule" }
+            }
+            table class="table table-sm" {
+                tbody {
+                    @for (date, word) in schedule.range(today..) {
+                        tr {
+                            td { (date) }
+                            td .font-monospace { (word) }
+                            td {
+                                button
+                                hx-delete={"/admin/schedule/"(date)}
+                                hx-target="#admin-schedule"
+                                hx-swap="outerHTML"
+                                class="btn btn-sm btn-outline-danger"
+                                { "Unschedule" }
+                            }
+                        }
+                    }
+                    @if schedule.range(today..).next().is_none() {
+                        tr { td colspan="3" .text-center { "nothing scheduled, daily words are picked at random" } }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The newest games, admins can see the words.
+fn games_markup(games: &HashMap<GameId, Game>) -> Markup {
+    let mut newest = games.values().collect::<Vec<_>>();
+    newest.sort_by_key(|game| Reverse(game.created));
+    let now = Utc::now();
+
+    html! {
+        section #admin-games class="mb-4" {
+            h2 class="h4" { "Games " small .text-secondary { (games.len()) } }
+            table class="table table-sm" {
+                thead {
+                    tr {
+                        th scope="col" { "Game" }
+                        th scope="col" { "Word" }
+                        th scope="col" { "Result" }
+                        th scope="col" { "Date" }
+                        th scope="col" {}
+                    }
+                }
+                tbody {
+                    @for game in newest.iter().take(GAMES_LIMIT) {
+                        tr {
+                            td { (game.mode.emoji()) " " (short_id(game.id)) }
+                            td .font-monospace { (game.word) }
+                            td { (game.share_score(now)) }
+                            td { (game.created.map(|created| created.format("%y/%m/%d").to_string()).unwrap_or_default()) }
+                            td class="text-end" {
+                                button
+                                hx-get={"/admin/games/"(game.id)}
+                                hx-target="#admin-game"
+                                class="btn btn-sm btn-outline-primary me-1"
+                                { "Inspect" }
+                                button
+                                hx-delete={"/admin/games/"(game.id)}
+                                hx-target="closest tr"
+                                hx-swap="outerHTML"
+                                hx-confirm="Delete this game?"
+                                class="btn btn-sm btn-outline-danger"
+                                { "Delete" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WordForm {
+    #[serde(default)]
+    word: String,
+}
+
+async fn save_dictionary(state: &AppState) {
+    match state
+        .storage
+        .save_dictionary(&*state.dictionary.read().await)
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => panic!("{err}"),
+    }
+}
+
+async fn add_word(State(state): State<Arc<AppState>>, Form(form): Form<WordForm>) -> Response {
+    let result = state.dictionary.write().await.add(&form.word);
+    let count = state.dictionary.read().await.words.len();
+
+    match result {
+        Ok(word) => {
+            save_dictionary(&state).await;
+            let toast = html! { div .alert .alert-success role="status" { "added " b { (word) } } };
+            words_markup(count, Some(toast)).into_response()
+        }
+        Err(err) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            words_markup(count, Some(toast_markup(html! { (err) }))),
+        )
+            .into_response(),
+    }
+}
+
+/// htmx sends the form of deletes in the query string.
+async fn remove_word(State(state): State<Arc<AppState>>, Query(form): Query<WordForm>) -> Response {
+    let today = Utc::now().date_naive();
+    let result = state.dictionary.write().await.remove(&form.word, today);
+    let count = state.dictionary.read().await.words.len();
+
+    match result {
+        Ok(word) => {
+            save_dictionary(&state).await;
+            let toast =
+                html! { div .alert .alert-success role="status" { "removed " b { (word) } } };
+            words_markup(count, Some(toast)).into_response()
+        }
+        Err(err) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            words_markup(count, Some(toast_markup(html! { (err) }))),
+        )
+            .into_response(),
+    }
+}
+
+/// Reloads the dictionary from disk, e.g. after editing the words file.
+async fn reload(State(state): State<Arc<AppState>>) -> Response {
+    match state.storage.load_dictionary().await {
+        Ok(dictionary) => {
+            let count = dictionary.words.len();
+            *state.dictionary.write().await = dictionary;
+            let toast = html! { div .alert .alert-success role="status" { "reloaded" } };
+            words_markup(count, Some(toast)).into_response()
+        }
+        Err(err) => {
+            let count = state.dictionary.read().await.words.len();
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                words_markup(count, Some(toast_markup(html! { (err) }))),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ScheduleForm {
+    date: NaiveDate,
+    #[serde(default)]
+    word: String,
+}
+
+async fn schedule(State(state): State<Arc<AppState>>, Form(form): Form<ScheduleForm>) -> Response {
+    let today = Utc::now().date_naive();
+
+    // Today's daily games might already be going, their word can't change.
+    let result = if form.date <= today {
+        Err("only future days can be scheduled".to_string())
+    } else {
+        state
+            .dictionary
+            .write()
+            .await
+            .schedule(form.date, &form.word)
+    };
+
+    if result.is_ok() {
+        save_dictionary(&state).await;
+    }
+
+    let dictionary = state.dictionary.read().await;
+    match result {
+        Ok(_) => schedule_markup(&dictionary.schedule, today, None).into_response(),
+        Err(err) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            schedule_markup(
+                &dictionary.schedule,
+                today,
+                Some(toast_markup(html! { (err) })),
+            ),
+        )
+            .into_response(),
+    }
+}
+
+async fn unschedule(State(state): State<Arc<AppState>>, Path(date): Path<NaiveDate>) -> Response {
+    let today = Utc::now().date_naive();
+
+    let result = if date <= today {
+        Err("only future days can be unscheduled")
+    } else {
+        state
+            .dictionary
+            .write()
+            .await
+            .unschedule(date)
+            .ok_or("nothing is scheduled then")
+    };
+
+    if result.is_ok() {
+        save_dictionary(&state).await;
+    }
+
+    let dictionary = state.dictionary.read().await;
+    match result {
+        Ok(_) => schedule_markup(&dictionary.schedule, today, None).into_response(),
+        Err(err) => (
+            StatusCode::NOT_FOUND,
+            schedule_markup(
+                &dictionary.schedule,
+                today,
+                Some(toast_markup(html! { (err) })),
+            ),
+        )
+            .into_response(),
+    }
+}
+
+async fn inspect_game(State(state): State<Arc<AppState>>, Path(game_id): Path<GameId>) -> Response {
+    let games = state.games.read().await;
+    let Some(game) = games.get(&game_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            toast_markup(html! { "game doesn't exist" }),
+        )
+            .into_response();
+    };
+
+    html! {
+        h2 class="h4" { (game.mode.emoji()) " " (short_id(game.id)) }
+        dl class="row" {
+            dt class="col-3" { "Id" } dd class="col-9 font-monospace" { (game.id) }
+            dt class="col-3" { "Word" } dd class="col-9 font-monospace" { (game.word) }
+            dt class="col-3" { "Mode" } dd class="col-9" { (game.mode.label()) }
+            dt class="col-3" { "Result" } dd class="col-9" { (game.share_score(Utc::now())) }
+            dt class="col-3" { "Score" } dd class="col-9" { (game.score) }
+            dt class="col-3" { "Hints" } dd class="col-9" { (game.hints.len()) }
+            dt class="col-3" { "Player" } dd class="col-9" { (game.player.map(short_id).unwrap_or_default()) }
+            dt class="col-3" { "Created" } dd class="col-9" { (game.created.map(|created| created.to_rfc3339()).unwrap_or_default()) }
+        }
+        @for guess in &game.guesses {
+            (WordState::guess(guess, &game.word))
+        }
+    }
+    .into_response()
+}
+
+/// Deletes a game, the row of the game is removed from the table.
+async fn delete_game(State(state): State<Arc<AppState>>, Path(game_id): Path<GameId>) -> Response {
+    if state.games.write().await.remove(&game_id).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            toast_markup(html! { "game doesn't exist" }),
+        )
+            .into_response();
+    }
+
+    match state.storage.save(state.get_save_data().await).await {
+        Ok(_) => {}
+        Err(err) => panic!("{err}"),
+    }
+
+    html! {}.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret!"));
+        assert!(!tokens_match("", "secret"));
+    }
+
+    #[test]
+    fn sessions() {
+        let sessions = Sessions::default();
+        let now = Instant::now();
+        let id = sessions.start(now).to_string();
+        assert!(sessions.is_valid(&id, now));
+        assert!(!sessions.is_valid(&Uuid::new_v4().to_string(), now));
+        assert!(!sessions.is_valid("secret", now));
+
+        let later = now + Duration::from_secs(SESSION_SECS);
+        assert!(!sessions.is_valid(&id, later));
+
+        sessions.end(&id);
+        assert!(!sessions.is_valid(&id, now));
+    }
+}
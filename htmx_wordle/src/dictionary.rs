@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+
+use crate::game::parse_guess;
+
+/// The words that can be guessed and the answers scheduled for daily games,
+/// both can be changed at runtime by admins.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionary {
+    pub words: Vec<String>,
+    pub schedule: BTreeMap<NaiveDate, String>,
+}
+
+impl Dictionary {
+    /// The dictionary that comes with the game.
+    pub fn bundled() -> Self {
+        Self::from_words(include_str!("../words.txt"))
+    }
+
+    /// A dictionary with one word per line, invalid words are skipped.
+    pub fn from_words(words: &str) -> Self {
+        let mut dictionary = Self::default();
+        for word in words.lines().filter_map(parse_guess) {
+            if !dictionary.contains(&word) {
+                dictionary.words.push(word);
+            }
+        }
+        dictionary
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.iter().any(|w| w == word)
+    }
+
+    pub fn add(&mut self, word: &str) -> Result<String, String> {
+        let word = parse_guess(word).ok_or("words have to be five letters")?;
+        if self.contains(&word) {
+            return Err(format!("{word} is already in the dictionary"));
+        }
+        self.words.push(word.clone());
+        Ok(word)
+    }
+
+    /// Removes a word, words that are scheduled from `today` on can't be
+    /// removed or players couldn't guess them. New games need a word, so the
+    /// last one stays.
+    pub fn remove(&mut self, word: &str, today: NaiveDate) -> Result<String, String> {
+        let word = word.trim().to_lowercase();
+
+        if self.words.len() == 1 && self.contains(&word) {
+            return Err(format!("{word} is the last word"));
+        }
+
+        if let Some((date, _)) = self
+            .schedule
+            .range(today..)
+            .find(|(_, scheduled)| **scheduled == word)
+        {
+            return Err(format!("{word} is scheduled for {date}"));
+        }
+
+        let index = self
+            .words
+            .iter()
+            .position(|w| *w == word)
+            .ok_or(format!("{word} isn't in the dictionary"))?;
+
+        Ok(self.words.remove(index))
+    }
+
+    /// Schedules the answer of the daily game of a date.
+    pub fn schedule(&mut self, date: NaiveDate, word: &str) -> Result<String, String> {
+        let word = parse_guess(word).ok_or("words have to be five letters")?;
+        if !self.contains(&word) {
+            return Err(format!("{word} isn't in the dictionary"));
+        }
+        self.schedule.insert(date, word.clone());
+        Ok(word)
+    }
+
+    pub fn unschedule(&mut self, date: NaiveDate) -> Option<String> {
+        self.schedule.remove(&date)
+    }
+
+    /// The scheduled answer of a date.
+    pub fn daily_word(&self, date: NaiveDate) -> Option<&str> {
+        self.schedule.get(&date).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    #[test]
+    fn bundled_words() {
+        let dictionary = Dictionary::bundled();
+        assert!(dictionary.contains("crane"));
+        assert!(dictionary.words.iter().all(|word| word.len() == 5));
+    }
+
+    #[test]
+    fn add_and_remove_words() {
+        let mut dictionary = Dictionary::from_words("crane\nslate\n\nnope\n");
+        assert_eq!(dictionary.words, vec!["crane", "slate"]);
+
+        assert_eq!(dictionary.add(" Final "), Ok("final".to_string()));
+        assert!(dictionary.add("final").is_err());
+        assert!(dictionary.add("finale").is_err());
+
+        assert_eq!(dictionary.remove("crane", date(1)), Ok("crane".to_string()));
+        assert!(dictionary.remove("crane", date(1)).is_err());
+        assert!(!dictionary.contains("crane"));
+
+        assert!(dictionary.remove("slate", date(1)).is_ok());
+        assert!(dictionary.remove("final", date(1)).is_err());
+        assert_eq!(dictionary.words, vec!["final"]);
+    }
+
+    #[test]
+    fn scheduled_words() {
+        let mut dictionary = Dictionary::from_words("crane\nslate");
+        assert!(dictionary.schedule(date(2), "final").is_err());
+        assert!(dictionary.schedule(date(2), "crane").is_ok());
+        assert_eq!(dictionary.daily_word(date(2)), Some("crane"));
+        assert_eq!(dictionary.daily_word(date(3)), None);
+
+        // Upcoming answers are kept in the dictionary, past ones can go.
+        assert!(dictionary.remove("crane", date(1)).is_err());
+        assert!(dictionary.remove("crane", date(3)).is_ok());
+
+        assert_eq!(dictionary.unschedule(date(2)), Some("crane".to_string()));
+        assert_eq!(dictionary.daily_word(date(2)), None);
+    }
+}
@@ -1,10 +1,19 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-pub const LETTERS: &'static str = "qwertyuiopasdfghjklzxcvbnm";
+use crate::player::PlayerId;
+
+pub const LETTERS: &str = "qwertyuiopasdfghjklzxcvbnm";
+
+pub const MAX_HINTS: usize = 3;
+pub const HINT_COOLDOWN_SECS: i64 = 20;
+pub const TIMED_SECS: i64 = 180;
 
 pub type GameId = Uuid;
 
@@ -12,12 +21,80 @@ pub fn short_id(id: Uuid) -> String {
     id.to_string().chars().take(8).collect::<String>()
 }
 
+/// Cleans up a guess, it has to be five letters.
+pub fn parse_guess(guess: &str) -> Option<String> {
+    let guess = guess.trim().to_lowercase();
+    let valid = guess.chars().count() == 5 && guess.chars().all(|ch| LETTERS.contains(ch));
+    valid.then_some(guess)
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    /// A single untimed puzzle.
+    #[default]
+    Classic,
+    /// The puzzle of the day, everyone gets the same answer.
+    Daily,
+    /// A single puzzle that's lost when the deadline passes.
+    Timed,
+    /// Puzzles are chained until the first one that's lost.
+    Endless,
+}
+
+impl GameMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameMode::Classic => "classic",
+            GameMode::Daily => "daily",
+            GameMode::Timed => "timed",
+            GameMode::Endless => "endless",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameMode::Classic => "Classic",
+            GameMode::Daily => "Daily",
+            GameMode::Timed => "Timed",
+            GameMode::Endless => "Endless",
+        }
+    }
+
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            GameMode::Classic => "⭐️",
+            GameMode::Daily => "📅",
+            GameMode::Timed => "⏱️",
+            GameMode::Endless => "♾️",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Game {
     pub id: Uuid,
     pub word: String,
     pub guesses: Vec<String>,
     pub created: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub hints: Vec<DateTime<Utc>>,
+    #[serde(default)]
+    pub mode: GameMode,
+    /// Timed games are lost once this passes.
+    #[serde(default)]
+    pub deadline: Option<DateTime<Utc>>,
+    /// Endless games: how many puzzles were solved before this one.
+    #[serde(default)]
+    pub streak: u32,
+    /// Endless games: the game that continues the run.
+    #[serde(default)]
+    pub next: Option<GameId>,
+    /// Set when the game is won, see `victory_score`.
+    #[serde(default)]
+    pub score: u32,
+    #[serde(default)]
+    pub player: Option<PlayerId>,
 }
 
 impl Game {
@@ -27,23 +104,104 @@ impl Game {
             word,
             guesses: vec![],
             created: Some(Utc::now()),
+            hints: vec![],
+            mode: GameMode::Classic,
+            deadline: None,
+            streak: 0,
+            next: None,
+            score: 0,
+            player: None,
         }
     }
 
-    pub fn is_complete(&self) -> bool {
-        self.guesses.len() >= 6 || self.is_victory()
+    /// Changes the mode, timed games get a deadline from when they were
+    /// created.
+    pub fn with_mode(mut self, mode: GameMode) -> Self {
+        self.mode = mode;
+        self.deadline = match mode {
+            GameMode::Timed => self
+                .created
+                .map(|created| created + chrono::Duration::try_seconds(TIMED_SECS).unwrap()),
+            _ => None,
+        };
+        self
+    }
+
+    /// The next puzzle of an endless run, it carries the streak over.
+    pub fn next_in_run(&self, id: Uuid, word: String) -> Self {
+        let mut game = Self::new(id, word).with_mode(GameMode::Endless);
+        game.streak = self.streak + 1;
+        game.player = self.player;
+        game
+    }
+
+    pub fn is_complete(&self, now: DateTime<Utc>) -> bool {
+        self.guesses.len() >= 6 || self.is_victory() || self.is_out_of_time(now)
     }
 
     pub fn is_victory(&self) -> bool {
         self.guesses.contains(&self.word)
     }
 
-    pub fn is_loss(&self) -> bool {
-        self.guesses.len() >= 6 && !self.is_victory()
+    pub fn is_loss(&self, now: DateTime<Utc>) -> bool {
+        (self.guesses.len() >= 6 || self.is_out_of_time(now)) && !self.is_victory()
+    }
+
+    /// Timed games that weren't won before the deadline are lost.
+    pub fn is_out_of_time(&self, now: DateTime<Utc>) -> bool {
+        self.deadline.is_some_and(|deadline| now >= deadline) && !self.is_victory()
+    }
+
+    /// Seconds left of a timed game.
+    pub fn seconds_left(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.deadline
+            .map(|deadline| (deadline - now).num_seconds().max(0))
     }
 
-    pub fn add_guess(&mut self, word: String) {
+    pub fn add_guess(&mut self, word: String, now: DateTime<Utc>) {
         self.guesses.push(word);
+
+        if self.is_victory() {
+            self.score = self.victory_score(now);
+        }
+    }
+
+    /// The score of a won game depends on the mode:
+    /// - classic and daily: one point for every guess that was left
+    /// - timed: the same plus one point for every second that was left
+    /// - endless: how many puzzles were solved in a row
+    pub fn victory_score(&self, now: DateTime<Utc>) -> u32 {
+        let guesses_left = 7 - self.guesses.len().min(6) as u32;
+        match self.mode {
+            GameMode::Classic | GameMode::Daily => guesses_left,
+            GameMode::Timed => guesses_left + self.seconds_left(now).unwrap_or(0) as u32,
+            GameMode::Endless => self.streak + 1,
+        }
+    }
+
+    /// Checks if a hint can be given, hints are limited per game and there's a
+    /// cooldown between them.
+    pub fn check_hint(&self, now: DateTime<Utc>) -> Result<(), HintError> {
+        if self.is_complete(now) {
+            return Err(HintError::GameOver);
+        }
+
+        if self.hints.len() >= MAX_HINTS {
+            return Err(HintError::AllUsed);
+        }
+
+        if let Some(last) = self.hints.last() {
+            let wait = HINT_COOLDOWN_SECS - (now - *last).num_seconds();
+            if wait > 0 {
+                return Err(HintError::Cooldown(wait));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn add_hint(&mut self, now: DateTime<Utc>) {
+        self.hints.push(now);
     }
 
     pub fn get_guesses(&self) -> Vec<Option<String>> {
@@ -66,25 +224,86 @@ impl Game {
             .map(|guess| WordState::guess(guess, &self.word))
             .flat_map(|state| state.letters)
             .for_each(|letter| {
-                if let Some(existing_letter) = letter_map.get_mut(&letter.id) {
+                match letter_map.get_mut(&letter.id) {
                     // New letter state is better than the previous? e.g. Correct > WrongPlace
-                    if letter.state > existing_letter.state {
+                    Some(existing_letter) => {
+                        if letter.state > existing_letter.state {
+                            *existing_letter = letter;
+                        }
+                    }
+                    None => {
                         letter_map.insert(letter.id, letter);
                     }
-                } else {
-                    letter_map.insert(letter.id, letter);
                 }
             });
 
         // Add all other characters (they're all empty)
         LETTERS.chars().for_each(|ch| {
-            if !letter_map.contains_key(&ch) {
-                letter_map.insert(ch, Letter::new(ch, LetterState::Empty));
-            }
+            letter_map
+                .entry(ch)
+                .or_insert_with(|| Letter::new(ch, LetterState::Empty));
         });
 
         letter_map
     }
+
+    /// The score of a game as it appears when shared, e.g. `3/6` or `X/6`
+    /// when the word was never found.
+    pub fn share_score(&self, now: DateTime<Utc>) -> String {
+        if self.is_victory() {
+            format!("{}/6", self.guesses.len())
+        } else if self.is_loss(now) {
+            "X/6".to_string()
+        } else {
+            format!("{}/6?", self.guesses.len())
+        }
+    }
+
+    /// What happened with the last guess, announced to screen readers.
+    pub fn announcement(&self, now: DateTime<Utc>) -> String {
+        let Some(guess) = self.guesses.last() else {
+            return String::new();
+        };
+
+        let result = WordState::guess(guess, &self.word).label();
+        let outcome = if self.is_victory() {
+            "you won!".to_string()
+        } else if self.is_loss(now) {
+            format!("the word was {}.", self.word)
+        } else {
+            format!("{} guesses left.", 6 - self.guesses.len())
+        };
+
+        format!("{guess}: {result}. {outcome}")
+    }
+
+    /// The spoiler-free emoji grid of all guesses, one line per guess.
+    pub fn share_grid(&self) -> String {
+        self.guesses
+            .iter()
+            .map(|guess| WordState::guess(guess, &self.word).emoji())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Why a hint can't be given.
+#[derive(PartialEq, Eq, Debug)]
+pub enum HintError {
+    GameOver,
+    AllUsed,
+    /// The seconds until the next hint.
+    Cooldown(i64),
+}
+
+impl fmt::Display for HintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HintError::GameOver => write!(f, "the game is over"),
+            HintError::AllUsed => write!(f, "all {MAX_HINTS} hints are used"),
+            HintError::Cooldown(wait) => write!(f, "wait {wait}s for the next hint"),
+        }
+    }
 }
 
 pub struct WordState {
@@ -104,7 +323,7 @@ impl WordState {
         }
     }
 
-    pub fn guess(guess: &String, word: &String) -> WordState {
+    pub fn guess(guess: &str, word: &str) -> WordState {
         let mut duplicates = HashSet::new();
         let letters = guess
             .to_lowercase()
@@ -124,6 +343,23 @@ impl WordState {
 
         WordState { letters }
     }
+
+    /// The labels of all letters, e.g. `s, correct position; m, not in the word`.
+    pub fn label(&self) -> String {
+        self.letters
+            .iter()
+            .map(|letter| letter.label())
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// The word-state as a row of emojis, e.g. `🟩🟨⬛⬛🟩`.
+    pub fn emoji(&self) -> String {
+        self.letters
+            .iter()
+            .map(|letter| letter.state.emoji())
+            .collect()
+    }
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -136,9 +372,17 @@ impl Letter {
     fn new(id: char, state: LetterState) -> Self {
         Self { id, state }
     }
+
+    /// The label of a tile for screen readers, e.g. `s, correct position`.
+    pub fn label(&self) -> String {
+        match self.state {
+            LetterState::Empty => self.state.description().to_string(),
+            _ => format!("{}, {}", self.id, self.state.description()),
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Debug, Ord)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum LetterState {
     Correct,
     WrongPlace,
@@ -146,10 +390,47 @@ pub enum LetterState {
     Empty,
 }
 
+impl LetterState {
+    /// What the state means, e.g. for screen readers.
+    pub fn description(&self) -> &'static str {
+        match self {
+            LetterState::Correct => "correct position",
+            LetterState::WrongPlace => "wrong position",
+            LetterState::Wrong => "not in the word",
+            LetterState::Empty => "empty",
+        }
+    }
+
+    /// A css class of the state so that palettes can restyle it.
+    pub fn class(&self) -> &'static str {
+        match self {
+            LetterState::Correct => "state-correct",
+            LetterState::WrongPlace => "state-wrong-place",
+            LetterState::Wrong => "state-wrong",
+            LetterState::Empty => "state-empty",
+        }
+    }
+
+    pub fn emoji(&self) -> char {
+        match self {
+            LetterState::Correct => '🟩',
+            LetterState::WrongPlace => '🟨',
+            LetterState::Wrong => '⬛',
+            LetterState::Empty => '⬜',
+        }
+    }
+}
+
+impl Ord for LetterState {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Note: compare in reverse so that we can do: Correct(0) > Wrong(1)
+        (*other as u8).cmp(&(*self as u8))
+    }
+}
+
 impl PartialOrd for LetterState {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        // Note: compare in reverse so that we can do: Correct(0) > Wrong(1)
-        Some(other.cmp(self))
+        Some(self.cmp(other))
     }
 }
 
@@ -159,7 +440,7 @@ mod tests {
 
     #[test]
     fn guess_word_into_state() {
-        let state = WordState::guess(&"smell".to_string(), &"state".to_string());
+        let state = WordState::guess("smell", "state");
         assert_eq!(state.letters[0].state, LetterState::Correct);
         assert_eq!(state.letters[1].state, LetterState::Wrong);
         assert_eq!(state.letters[2].state, LetterState::WrongPlace);
@@ -169,7 +450,7 @@ mod tests {
 
     #[test]
     fn guess_with_double_letter_into_word_state() {
-        let state = WordState::guess(&"smell".to_string(), &"slate".to_string());
+        let state = WordState::guess("smell", "slate");
         assert_eq!(state.letters[0].state, LetterState::Correct);
         assert_eq!(state.letters[1].state, LetterState::Wrong);
         assert_eq!(state.letters[2].state, LetterState::WrongPlace);
@@ -179,52 +460,177 @@ mod tests {
         assert_eq!(state.letters[4].state, LetterState::Wrong);
     }
 
+    #[test]
+    fn word_state_into_emoji() {
+        let state = WordState::guess("smell", "slate");
+        assert_eq!(state.emoji(), "🟩⬛🟨🟨⬛");
+        assert_eq!(WordState::empty().emoji(), "⬜⬜⬜⬜⬜");
+    }
+
+    #[test]
+    fn letter_labels() {
+        let state = WordState::guess("smell", "slate");
+        assert_eq!(state.letters[0].label(), "s, correct position");
+        assert_eq!(state.letters[2].label(), "e, wrong position");
+        assert_eq!(state.letters[4].label(), "l, not in the word");
+        assert_eq!(WordState::empty().letters[0].label(), "empty");
+    }
+
+    #[test]
+    fn announce_last_guess() {
+        let now = Utc::now();
+        let mut game = Game::new(Uuid::new_v4(), "slate".to_string());
+        assert_eq!(game.announcement(now), "");
+        game.add_guess("smell".to_string(), now);
+        assert_eq!(
+            game.announcement(now),
+            "smell: s, correct position; m, not in the word; e, wrong position; l, wrong \
+             position; l, not in the word. 5 guesses left."
+        );
+        game.add_guess("slate".to_string(), now);
+        assert!(game.announcement(now).ends_with("you won!"));
+    }
+
+    #[test]
+    fn share_grid_and_score() {
+        let now = Utc::now();
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string());
+        assert_eq!(game.share_score(now), "0/6?");
+        game.add_guess("crane".to_string(), now);
+        game.add_guess("final".to_string(), now);
+        assert_eq!(game.share_score(now), "2/6");
+        assert_eq!(game.share_grid(), "⬛⬛🟨🟨⬛\n🟩🟩🟩🟩🟩");
+        // The grid never spells out the word.
+        assert!(!game.share_grid().contains("final"));
+
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string());
+        for _ in 0..6 {
+            game.add_guess("wrong".to_string(), now);
+        }
+        assert_eq!(game.share_score(now), "X/6");
+    }
+
+    #[test]
+    fn parse_guesses() {
+        assert_eq!(parse_guess(" Crane "), Some("crane".to_string()));
+        assert_eq!(parse_guess("cran"), None);
+        assert_eq!(parse_guess("cranes"), None);
+        assert_eq!(parse_guess("cr4ne"), None);
+        assert_eq!(parse_guess(""), None);
+    }
+
+    #[test]
+    fn hints_are_rate_limited() {
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string());
+        let now = Utc::now();
+        assert!(game.check_hint(now).is_ok());
+
+        game.add_hint(now);
+        assert_eq!(
+            game.check_hint(now),
+            Err(HintError::Cooldown(HINT_COOLDOWN_SECS))
+        );
+
+        let later = now + chrono::Duration::try_seconds(HINT_COOLDOWN_SECS).unwrap();
+        assert!(game.check_hint(later).is_ok());
+
+        game.add_hint(later);
+        game.add_hint(later);
+        let much_later = later + chrono::Duration::try_days(1).unwrap();
+        assert_eq!(game.check_hint(much_later), Err(HintError::AllUsed));
+
+        let mut won = Game::new(Uuid::new_v4(), "final".to_string());
+        won.add_guess("final".to_string(), now);
+        assert_eq!(won.check_hint(now), Err(HintError::GameOver));
+    }
+
+    #[test]
+    fn timed_game_is_lost_after_deadline() {
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string()).with_mode(GameMode::Timed);
+        let deadline = game.deadline.unwrap();
+        let before = deadline - chrono::Duration::try_seconds(1).unwrap();
+        assert!(!game.is_complete(before));
+        assert!(!game.is_loss(before));
+        assert!(game.is_complete(deadline));
+        assert!(game.is_loss(deadline));
+        assert_eq!(game.seconds_left(deadline), Some(0));
+
+        // Winning in time is never a loss.
+        game.add_guess("final".to_string(), before);
+        assert!(!game.is_out_of_time(deadline));
+        assert_eq!(game.score, 7);
+    }
+
+    #[test]
+    fn mode_scores() {
+        let now = Utc::now();
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string());
+        game.add_guess("crane".to_string(), now);
+        game.add_guess("final".to_string(), now);
+        assert_eq!(game.score, 5);
+
+        let mut timed = Game::new(Uuid::new_v4(), "final".to_string()).with_mode(GameMode::Timed);
+        let deadline = timed.deadline.unwrap();
+        let ten_left = deadline - chrono::Duration::try_seconds(10).unwrap();
+        timed.add_guess("final".to_string(), ten_left);
+        assert_eq!(timed.score, 16);
+
+        let first = Game::new(Uuid::new_v4(), "final".to_string()).with_mode(GameMode::Endless);
+        let mut second = first.next_in_run(Uuid::new_v4(), "crane".to_string());
+        assert_eq!(second.mode, GameMode::Endless);
+        second.add_guess("crane".to_string(), now);
+        assert_eq!(second.score, 2);
+    }
+
     #[test]
     fn quick_victory() {
+        let now = Utc::now();
         let mut game = Game::new(Uuid::new_v4(), "final".to_string());
-        game.add_guess("guess".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("final".to_string());
-        assert_eq!(game.is_complete(), true);
-        assert_eq!(game.is_victory(), true);
-        assert_eq!(game.is_loss(), false);
+        game.add_guess("guess".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("final".to_string(), now);
+        assert!(game.is_complete(now));
+        assert!(game.is_victory());
+        assert!(!game.is_loss(now));
     }
 
     #[test]
     fn victory() {
+        let now = Utc::now();
         let mut game = Game::new(Uuid::new_v4(), "final".to_string());
-        game.add_guess("crane".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("pilot".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("husky".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("badge".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("epoxy".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("final".to_string());
-        assert_eq!(game.is_complete(), true);
-        assert_eq!(game.is_victory(), true);
-        assert_eq!(game.is_loss(), false);
+        game.add_guess("crane".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("pilot".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("husky".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("badge".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("epoxy".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("final".to_string(), now);
+        assert!(game.is_complete(now));
+        assert!(game.is_victory());
+        assert!(!game.is_loss(now));
     }
 
     #[test]
     fn loss() {
+        let now = Utc::now();
         let mut game = Game::new(Uuid::new_v4(), "final".to_string());
-        game.add_guess("crane".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("pilot".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("husky".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("badge".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("epoxy".to_string());
-        assert_eq!(game.is_complete(), false);
-        game.add_guess("wrong".to_string());
-        assert_eq!(game.is_complete(), true);
-        assert_eq!(game.is_victory(), false);
-        assert_eq!(game.is_loss(), true);
+        game.add_guess("crane".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("pilot".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("husky".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("badge".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("epoxy".to_string(), now);
+        assert!(!game.is_complete(now));
+        game.add_guess("wrong".to_string(), now);
+        assert!(game.is_complete(now));
+        assert!(!game.is_victory());
+        assert!(game.is_loss(now));
     }
 }
@@ -0,0 +1,369 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::value::StrDeserializer, Deserialize, Deserializer};
+use uuid::Uuid;
+
+use crate::game::Game;
+
+pub const PAGE_SIZE: usize = 20;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GameStatus {
+    Won,
+    Lost,
+    Playing,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GameSort {
+    #[default]
+    Newest,
+    Oldest,
+    FewestGuesses,
+    MostGuesses,
+}
+
+impl GameStatus {
+    pub const ALL: [GameStatus; 3] = [GameStatus::Won, GameStatus::Lost, GameStatus::Playing];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameStatus::Won => "won",
+            GameStatus::Lost => "lost",
+            GameStatus::Playing => "playing",
+        }
+    }
+
+    fn matches(&self, game: &Game, now: DateTime<Utc>) -> bool {
+        match self {
+            GameStatus::Won => game.is_victory(),
+            GameStatus::Lost => game.is_loss(now),
+            GameStatus::Playing => !game.is_complete(now),
+        }
+    }
+}
+
+impl GameSort {
+    pub const ALL: [GameSort; 4] = [
+        GameSort::Newest,
+        GameSort::Oldest,
+        GameSort::FewestGuesses,
+        GameSort::MostGuesses,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameSort::Newest => "newest",
+            GameSort::Oldest => "oldest",
+            GameSort::FewestGuesses => "fewest_guesses",
+            GameSort::MostGuesses => "most_guesses",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameSort::Newest => "Newest",
+            GameSort::Oldest => "Oldest",
+            GameSort::FewestGuesses => "Fewest guesses",
+            GameSort::MostGuesses => "Most guesses",
+        }
+    }
+
+    /// The order of the rows, ties are broken by the newest game first and
+    /// then by id so that no two games are equal.
+    fn compare(&self, a: &Cursor, b: &Cursor) -> Ordering {
+        let newest = (b.created, b.id).cmp(&(a.created, a.id));
+        match self {
+            GameSort::Newest => newest,
+            GameSort::Oldest => newest.reverse(),
+            GameSort::FewestGuesses => a.guesses.cmp(&b.guesses).then(newest),
+            GameSort::MostGuesses => b.guesses.cmp(&a.guesses).then(newest),
+        }
+    }
+}
+
+/// The last row of a page of the games table, the next page starts after it.
+/// It's written as `<guesses>.<created in nanos>.<id>` in urls, the guesses
+/// only matter when sorting by them.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String")]
+pub struct Cursor {
+    pub guesses: usize,
+    pub created: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn of(game: &Game) -> Self {
+        Cursor {
+            guesses: game.guesses.len(),
+            created: game.created.unwrap_or_default(),
+            id: game.id,
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Games are created at any nanosecond, a rounded time would repeat
+        // or skip rows.
+        let nanos = self.created.timestamp_nanos_opt().unwrap_or_default();
+        write!(f, "{}.{nanos}.{}", self.guesses, self.id.simple())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cursor `{s}`");
+        let mut parts = s.splitn(3, '.');
+        let (Some(guesses), Some(nanos), Some(id)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(Cursor {
+            guesses: guesses.parse().map_err(|_| invalid())?,
+            created: DateTime::from_timestamp_nanos(nanos.parse().map_err(|_| invalid())?),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Filters, sorting and where the page of the games table starts, all of it
+/// lives in the query string.
+#[derive(Deserialize, Default, Debug)]
+pub struct GamesQuery {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub status: Option<GameStatus>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub from: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub to: Option<NaiveDate>,
+    #[serde(default)]
+    pub sort: GameSort,
+    /// The first page has none.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub after: Option<Cursor>,
+}
+
+impl GamesQuery {
+    /// The query string of the same filters on the page after `after`.
+    pub fn to_query_string(&self, after: Cursor) -> String {
+        let mut query = format!("sort={}&after={after}", self.sort.as_str());
+        if let Some(status) = self.status {
+            query.push_str(&format!("&status={}", status.as_str()));
+        }
+        if let Some(from) = self.from {
+            query.push_str(&format!("&from={from}"));
+        }
+        if let Some(to) = self.to {
+            query.push_str(&format!("&to={to}"));
+        }
+        query
+    }
+
+    fn matches(&self, game: &Game, now: DateTime<Utc>) -> bool {
+        let date = game.created.map(|created| created.date_naive());
+
+        self.status.is_none_or(|status| status.matches(game, now))
+            && self.from.is_none_or(|from| date >= Some(from))
+            && self.to.is_none_or(|to| date.is_some_and(|date| date <= to))
+    }
+
+    /// The games on the current page, returns if there are more pages. Pages
+    /// start after their cursor, so games added while scrolling don't move
+    /// the rows that are left.
+    pub fn page<'a>(
+        &self,
+        games: impl Iterator<Item = &'a Game>,
+        now: DateTime<Utc>,
+    ) -> (Vec<&'a Game>, bool) {
+        let mut games = games
+            .filter(|game| self.matches(game, now))
+            .filter(|game| {
+                self.after.is_none_or(|after| {
+                    self.sort.compare(&after, &Cursor::of(game)) == Ordering::Less
+                })
+            })
+            .collect::<Vec<_>>();
+        games.sort_by(|a, b| self.sort.compare(&Cursor::of(a), &Cursor::of(b)));
+
+        let has_more = games.len() > PAGE_SIZE;
+        games.truncate(PAGE_SIZE);
+
+        (games, has_more)
+    }
+}
+
+/// Html forms send empty strings for empty inputs.
+fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match Option::<String>::deserialize(de)?.as_deref() {
+        None | Some("") => Ok(None),
+        Some(value) => T::deserialize(StrDeserializer::new(value)).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn game(day: u32, guesses: &[&str]) -> Game {
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string());
+        let created = Utc.with_ymd_and_hms(2024, 3, day, 12, 0, 0).unwrap();
+        game.created = Some(created);
+        for guess in guesses {
+            game.add_guess(guess.to_string(), created);
+        }
+        game
+    }
+
+    fn games() -> Vec<Game> {
+        vec![
+            game(1, &["final"]),
+            game(2, &["crane", "final"]),
+            game(3, &["crane"]),
+            game(4, &["wrong"; 6]),
+        ]
+    }
+
+    #[test]
+    fn filter_by_status() {
+        let games = games();
+        let query = GamesQuery {
+            status: Some(GameStatus::Won),
+            ..Default::default()
+        };
+        let (page, has_more) = query.page(games.iter(), Utc::now());
+        assert_eq!(page.len(), 2);
+        assert!(page.iter().all(|game| game.is_victory()));
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn filter_by_date_range() {
+        let games = games();
+        let query = GamesQuery {
+            from: NaiveDate::from_ymd_opt(2024, 3, 2),
+            to: NaiveDate::from_ymd_opt(2024, 3, 3),
+            ..Default::default()
+        };
+        let (page, _) = query.page(games.iter(), Utc::now());
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].guesses, vec!["crane".to_string()]);
+    }
+
+    #[test]
+    fn sort_by_guesses() {
+        let games = games();
+        let query = GamesQuery {
+            sort: GameSort::MostGuesses,
+            ..Default::default()
+        };
+        let (page, _) = query.page(games.iter(), Utc::now());
+        let guesses = page
+            .iter()
+            .map(|game| game.guesses.len())
+            .collect::<Vec<_>>();
+        assert_eq!(guesses, vec![6, 2, 1, 1]);
+        // Ties are broken by the newest game first.
+        assert_eq!(page[2].guesses, vec!["crane".to_string()]);
+    }
+
+    #[test]
+    fn pages() {
+        let games = (1..=25).map(|day| game(day, &[])).collect::<Vec<_>>();
+
+        let (page, has_more) = GamesQuery::default().page(games.iter(), Utc::now());
+        assert_eq!(page.len(), PAGE_SIZE);
+        assert!(has_more);
+
+        let query = GamesQuery {
+            after: Some(Cursor::of(page[PAGE_SIZE - 1])),
+            ..Default::default()
+        };
+        let (page, has_more) = query.page(games.iter(), Utc::now());
+        assert_eq!(page.len(), 5);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn pages_start_after_the_cursor() {
+        let mut games = (1..=25).map(|day| game(day, &[])).collect::<Vec<_>>();
+        let query = GamesQuery {
+            sort: GameSort::MostGuesses,
+            ..Default::default()
+        };
+        let (first, _) = query.page(games.iter(), Utc::now());
+        let after = Cursor::of(first[PAGE_SIZE - 1]);
+
+        // Games started while scrolling are on the first page, not the next.
+        games.push(game(28, &[]));
+        let query = GamesQuery {
+            after: Some(after),
+            ..query
+        };
+        let (next, has_more) = query.page(games.iter(), Utc::now());
+        let days = next
+            .iter()
+            .map(|game| game.created.unwrap().format("%d").to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(days, ["05", "04", "03", "02", "01"]);
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn games_on_the_same_time() {
+        let games = (0..30).map(|_| game(1, &[])).collect::<Vec<_>>();
+        let (first, _) = GamesQuery::default().page(games.iter(), Utc::now());
+        let query = GamesQuery {
+            after: Some(Cursor::of(first[PAGE_SIZE - 1])),
+            ..Default::default()
+        };
+        let (next, _) = query.page(games.iter(), Utc::now());
+
+        let mut ids = first
+            .iter()
+            .chain(&next)
+            .map(|game| game.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 30);
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let mut game = game(1, &["crane"]);
+        game.created = game
+            .created
+            .map(|created| created + chrono::Duration::nanoseconds(7));
+        let cursor = Cursor::of(&game);
+        assert_eq!(cursor.to_string().parse::<Cursor>(), Ok(cursor));
+
+        for invalid in ["", "1", "1.2", "x.2.3", "1.x.3", "1.2.not-a-uuid"] {
+            assert!(
+                invalid.parse::<Cursor>().is_err(),
+                "{invalid} should be invalid"
+            );
+        }
+    }
+}
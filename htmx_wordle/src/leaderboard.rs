@@ -0,0 +1,261 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use chrono::{DateTime, Datelike, Utc};
+use serde::Deserialize;
+
+use crate::{game::Game, player::PlayerId};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    #[default]
+    Daily,
+    Weekly,
+    AllTime,
+}
+
+impl Period {
+    pub const ALL: [Period; 3] = [Period::Daily, Period::Weekly, Period::AllTime];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+            Period::AllTime => "all_time",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Period::Daily => "Today",
+            Period::Weekly => "This week",
+            Period::AllTime => "All time",
+        }
+    }
+
+    fn contains(&self, created: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        match self {
+            Period::Daily => created.date_naive() == now.date_naive(),
+            Period::Weekly => created.iso_week() == now.iso_week(),
+            Period::AllTime => true,
+        }
+    }
+}
+
+/// A player's row on the leaderboard.
+#[derive(PartialEq, Debug)]
+pub struct Entry {
+    pub player: PlayerId,
+    pub played: u32,
+    pub wins: u32,
+    /// Guesses per won game.
+    pub average_guesses: f64,
+    /// The most games won in a row.
+    pub best_streak: u32,
+    /// When the last game was won, earlier is better on a tie.
+    last_win: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn new(player: PlayerId) -> Self {
+        Self {
+            player,
+            played: 0,
+            wins: 0,
+            average_guesses: 0.0,
+            best_streak: 0,
+            last_win: None,
+        }
+    }
+
+    /// Ranks entries, on ties the next rule decides:
+    /// 1. more wins
+    /// 2. fewer guesses on average
+    /// 3. a longer best streak
+    /// 4. fewer games played
+    /// 5. reaching the score first
+    fn rank(&self, other: &Self) -> Ordering {
+        other
+            .wins
+            .cmp(&self.wins)
+            .then(self.average_guesses.total_cmp(&other.average_guesses))
+            .then(other.best_streak.cmp(&self.best_streak))
+            .then(self.played.cmp(&other.played))
+            .then(self.last_win.cmp(&other.last_win))
+    }
+}
+
+/// Ranks all players by their finished games in the period.
+pub fn leaderboard<'a>(
+    games: impl Iterator<Item = &'a Game>,
+    period: Period,
+    now: DateTime<Utc>,
+) -> Vec<Entry> {
+    let mut games_by_player: HashMap<PlayerId, Vec<&Game>> = HashMap::new();
+
+    for game in games {
+        let (Some(player), Some(created)) = (game.player, game.created) else {
+            continue;
+        };
+        if game.is_complete(now) && period.contains(created, now) {
+            games_by_player.entry(player).or_default().push(game);
+        }
+    }
+
+    let mut entries = games_by_player
+        .into_iter()
+        .map(|(player, mut games)| {
+            games.sort_by_key(|game| game.created);
+
+            let mut entry = Entry::new(player);
+            let mut streak = 0;
+            let mut guesses = 0;
+
+            for game in games {
+                entry.played += 1;
+                if game.is_victory() {
+                    entry.wins += 1;
+                    guesses += game.guesses.len();
+                    entry.last_win = game.created;
+                    streak += 1;
+                    entry.best_streak = entry.best_streak.max(streak);
+                } else {
+                    streak = 0;
+                }
+            }
+
+            if entry.wins > 0 {
+                entry.average_guesses = guesses as f64 / entry.wins as f64;
+            }
+
+            entry
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(Entry::rank);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, hour, 0, 0).unwrap()
+    }
+
+    /// A finished game, won in `guesses` or lost when `None`.
+    fn game(player: PlayerId, created: DateTime<Utc>, guesses: Option<usize>) -> Game {
+        let mut game = Game::new(Uuid::new_v4(), "final".to_string());
+        game.player = Some(player);
+        game.created = Some(created);
+        match guesses {
+            Some(guesses) => {
+                for _ in 1..guesses {
+                    game.add_guess("crane".to_string(), created);
+                }
+                game.add_guess("final".to_string(), created);
+            }
+            None => {
+                for _ in 0..6 {
+                    game.add_guess("crane".to_string(), created);
+                }
+            }
+        }
+        game
+    }
+
+    fn players() -> (PlayerId, PlayerId) {
+        (Uuid::new_v4(), Uuid::new_v4())
+    }
+
+    #[test]
+    fn more_wins_first() {
+        let (a, b) = players();
+        let games = [
+            game(a, at(1, 1), Some(6)),
+            game(a, at(1, 2), Some(6)),
+            game(b, at(1, 3), Some(1)),
+        ];
+        let board = leaderboard(games.iter(), Period::AllTime, at(1, 12));
+        assert_eq!(board[0].player, a);
+        assert_eq!(board[0].wins, 2);
+    }
+
+    #[test]
+    fn tie_on_wins_fewer_guesses_first() {
+        let (a, b) = players();
+        let games = [game(a, at(1, 1), Some(4)), game(b, at(1, 2), Some(2))];
+        let board = leaderboard(games.iter(), Period::AllTime, at(1, 12));
+        assert_eq!(board[0].player, b);
+        assert_eq!(board[0].average_guesses, 2.0);
+    }
+
+    #[test]
+    fn tie_on_guesses_longer_streak_first() {
+        let (a, b) = players();
+        let games = [
+            game(a, at(1, 1), Some(3)),
+            game(a, at(1, 2), None),
+            game(a, at(1, 3), Some(3)),
+            game(b, at(1, 4), Some(3)),
+            game(b, at(1, 5), Some(3)),
+            game(b, at(1, 6), None),
+        ];
+        let board = leaderboard(games.iter(), Period::AllTime, at(1, 12));
+        assert_eq!(board[0].player, b);
+        assert_eq!(board[0].best_streak, 2);
+        assert_eq!(board[1].best_streak, 1);
+    }
+
+    #[test]
+    fn tie_on_streak_fewer_games_first() {
+        let (a, b) = players();
+        let games = [
+            game(a, at(1, 1), Some(3)),
+            game(a, at(1, 2), None),
+            game(b, at(1, 3), Some(3)),
+        ];
+        let board = leaderboard(games.iter(), Period::AllTime, at(1, 12));
+        assert_eq!(board[0].player, b);
+    }
+
+    #[test]
+    fn full_tie_first_to_win_first() {
+        let (a, b) = players();
+        let games = [game(b, at(1, 1), Some(3)), game(a, at(1, 2), Some(3))];
+        let board = leaderboard(games.iter(), Period::AllTime, at(1, 12));
+        assert_eq!(board[0].player, b);
+        assert_eq!(board[1].player, a);
+    }
+
+    #[test]
+    fn periods() {
+        let (a, _) = players();
+        // 2024-03-04 is a monday.
+        let games = [
+            game(a, at(3, 1), Some(3)),
+            game(a, at(4, 1), Some(3)),
+            game(a, at(5, 1), Some(3)),
+        ];
+        let now = at(5, 12);
+        assert_eq!(leaderboard(games.iter(), Period::Daily, now)[0].wins, 1);
+        assert_eq!(leaderboard(games.iter(), Period::Weekly, now)[0].wins, 2);
+        assert_eq!(leaderboard(games.iter(), Period::AllTime, now)[0].wins, 3);
+    }
+
+    #[test]
+    fn unfinished_and_anonymous_games_are_skipped() {
+        let (a, _) = players();
+        let mut playing = game(a, at(1, 1), Some(3));
+        playing.guesses.clear();
+        let mut anonymous = game(a, at(1, 2), Some(3));
+        anonymous.player = None;
+
+        let board = leaderboard([playing, anonymous].iter(), Period::AllTime, at(1, 12));
+        assert!(board.is_empty());
+    }
+}
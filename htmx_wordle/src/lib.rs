@@ -1,55 +1,64 @@
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use axum::{
     body::Body,
     extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
-    response::Response,
-    routing::get,
-    Router,
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Router,
 };
-use game::{short_id, Game, GameId, Letter};
+use chrono::{DateTime, Utc};
+use game::{short_id, Game, GameId, GameMode, HintError, Letter};
 use maud::{html, Markup, PreEscaped, Render, DOCTYPE};
 use serde::Deserialize;
-use tinyrand::{RandRange, Seeded, StdRand};
+use tinyrand::{Rand, Seeded, StdRand};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
-use crate::game::{LetterState, WordState};
+use crate::{
+    game::{LetterState, WordState},
+    history::{Cursor, GameSort, GameStatus, GamesQuery},
+    leaderboard::Period,
+    rate_limit::RateLimiter,
+};
 
+mod admin;
+mod cookie;
+mod dictionary;
 mod game;
+mod history;
+mod leaderboard;
+mod player;
+mod rate_limit;
+mod solver;
 mod storage;
 
+/// How many guesses a player can make per `GUESS_WINDOW`.
+const GUESS_LIMIT: u32 = 30;
+const GUESS_WINDOW: Duration = Duration::from_secs(60);
+
+pub use crate::{dictionary::Dictionary, storage::Storage};
+
 struct AppState {
-    words: Vec<&'static str>,
+    dictionary: RwLock<Dictionary>,
     games: RwLock<HashMap<GameId, Game>>,
+    storage: Storage,
+    /// Picks the words of new games.
+    rng: Mutex<Box<dyn Rand + Send>>,
+    /// Keyed on both the game and the player.
+    guess_limiter: RateLimiter<Uuid>,
+    /// Admins log in with this token, there's no admin area without it.
+    admin_token: Option<String>,
+    admin_sessions: admin::Sessions,
 }
 
 impl AppState {
-    async fn new() -> Arc<AppState> {
-        let words: Vec<&'static str> = include_str!("../words.txt").lines().collect();
-
-        // Try and load the save data from disk
-        match storage::load().await {
-            Ok(save_data) => {
-                return Arc::new(AppState {
-                    words,
-                    games: RwLock::new(
-                        save_data
-                            .games
-                            .into_iter()
-                            .map(|g| (g.id, g))
-                            .collect::<HashMap<GameId, Game>>(),
-                    ),
-                });
-            }
-            Err(err) => println!("{err}"),
-        }
-        Arc::new(AppState {
-            words,
-            games: RwLock::new(HashMap::new()),
-        })
-    }
     async fn get_save_data(&self) -> Vec<Game> {
         self.games
             .read()
@@ -58,28 +67,143 @@ impl AppState {
             .cloned()
             .collect::<Vec<_>>()
     }
+
+    /// The words of the dictionary, the answer of the game included even if
+    /// it was removed after the game started.
+    async fn words_for(&self, game: &Game) -> Vec<String> {
+        let mut words = self.dictionary.read().await.words.clone();
+        if !words.contains(&game.word) {
+            words.push(game.word.clone());
+        }
+        words
+    }
+
+    /// Picks a random word for a new game.
+    fn random_word(&self, words: &[String]) -> Result<String, String> {
+        if words.is_empty() {
+            return Err("there are no words to play".to_string());
+        }
+        let random_index = self.rng.lock().unwrap().next_lim_usize(words.len());
+        Ok(words[random_index].clone())
+    }
 }
 
-pub async fn run() {
-    let state = AppState::new().await;
+/// Builds the app's router, the dictionary, storage and RNG can be swapped
+/// out, e.g. to test the app without touching the disk.
+pub struct App {
+    dictionary: Option<Dictionary>,
+    storage: Storage,
+    rng: Box<dyn Rand + Send>,
+    admin_token: Option<String>,
+}
 
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/new_game", get(new_game))
-        .route("/game/:id", get(game))
-        .route("/games", get(games))
-        .with_state(state);
+impl App {
+    /// An app with the dictionary saved in `storage` (or the bundled one),
+    /// random words are seeded from the OS so that they can't be predicted.
+    pub fn new(storage: Storage) -> Self {
+        let seed = Uuid::new_v4().as_u64_pair().0;
+        Self {
+            dictionary: None,
+            storage,
+            rng: Box::new(StdRand::seed(seed)),
+            admin_token: None,
+        }
+    }
+
+    pub fn dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    pub fn rng(mut self, rng: impl Rand + Send + 'static) -> Self {
+        self.rng = Box::new(rng);
+        self
+    }
+
+    pub fn admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token.filter(|token| !token.is_empty());
+        self
+    }
+
+    /// Loads the saved games and builds the router.
+    pub async fn router(self) -> Router {
+        // Admins may have changed the words, otherwise use the bundled ones.
+        let dictionary = match self.dictionary {
+            Some(dictionary) => dictionary,
+            None => match self.storage.load_dictionary().await {
+                Ok(dictionary) => dictionary,
+                Err(err) => {
+                    println!("{err}");
+                    Dictionary::bundled()
+                }
+            },
+        };
+
+        // Try and load the save data from disk
+        let saved_games = match self.storage.load().await {
+            Ok(save_data) => save_data
+                .games
+                .into_iter()
+                .map(|g| (g.id, g))
+                .collect::<HashMap<GameId, Game>>(),
+            Err(err) => {
+                println!("{err}");
+                HashMap::new()
+            }
+        };
+
+        let state = Arc::new(AppState {
+            dictionary: RwLock::new(dictionary),
+            games: RwLock::new(saved_games),
+            storage: self.storage,
+            rng: Mutex::new(self.rng),
+            guess_limiter: RateLimiter::new(GUESS_LIMIT, GUESS_WINDOW),
+            admin_token: self.admin_token,
+            admin_sessions: admin::Sessions::default(),
+        });
+
+        Router::new()
+            .route("/", get(index))
+            .route("/new_game", get(new_game))
+            .route("/game/:id", get(game))
+            .route("/game/:id/guesses", post(add_guess))
+            .route("/game/:id/next", post(next_game))
+            .route("/game/:id/share", get(share))
+            .route("/game/:id/hint", post(hint))
+            .route("/game/:id/analysis", get(analysis))
+            .route("/games", get(games))
+            .route("/games/rows", get(games_rows))
+            .route("/leaderboard", get(leaderboard))
+            .nest("/admin", admin::router(state.clone()))
+            .with_state(state)
+    }
+}
+
+pub async fn run() {
+    let app = App::new(Storage::Disk("data".into()))
+        .admin_token(std::env::var("WORDLE_ADMIN_TOKEN").ok())
+        .router()
+        .await;
 
     let address = "0.0.0.0:4202";
     let listener = tokio::net::TcpListener::bind(address).await.unwrap();
     println!("🚀 Server Started: {address} 🚀");
 
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    // Connect info is the address of clients that aren't behind a proxy.
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 fn base(content: Markup) -> Markup {
+    base_with_meta(html! {}, content)
+}
+
+/// Base page with extra `meta` tags in the head, e.g. OpenGraph tags.
+fn base_with_meta(meta: Markup, content: Markup) -> Markup {
     let style = include_str!("../style.css");
     let scripts = PreEscaped(include_str!("../scripts.js"));
 
@@ -90,6 +214,7 @@ fn base(content: Markup) -> Markup {
                 meta charset="utf-8";
                 meta name="viewport" content="width=device-width, initial-scale=3, maximum-scale=1, user-scalable=no" {}
                 title { "Wordle (htmx)" }
+                (meta)
 
                 // Styles
                 link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/bootstrap@5.3.3/dist/css/bootstrap.min.css" {}
@@ -104,6 +229,13 @@ fn base(content: Markup) -> Markup {
 
             }
             body {
+                button
+                #palette-toggle
+                class="btn btn-sm btn-outline-secondary position-absolute top-0 end-0 m-2"
+                aria-label="Toggle the colour-blind palette"
+                title="Colour-blind palette"
+                onclick="togglePalette()"
+                { "🎨" }
                 div class="container p-3" {
                     (content)
                 }
@@ -124,7 +256,11 @@ async fn index(State(state): State<Arc<AppState>>) -> Markup {
                     small .card-text .text-secondary { "Made by " a href="https://iggyzuk.com/" { "Iggy Zuk" } }
                     div class="text-center" {
                         (new_game_btn_markup())
+                        (new_game_mode_btn_markup(GameMode::Daily))
+                        (new_game_mode_btn_markup(GameMode::Timed))
+                        (new_game_mode_btn_markup(GameMode::Endless))
                         (all_games_btn_markup(state.games.read().await.len()))
+                        (leaderboard_btn_markup())
                     }
                 }
             }
@@ -134,21 +270,62 @@ async fn index(State(state): State<Arc<AppState>>) -> Markup {
     })
 }
 
-async fn new_game(State(state): State<Arc<AppState>>) -> Response {
-    // Pick a random word.
-    let seed = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let mut rand = StdRand::seed(seed);
-    let random_index = rand.next_range(0..state.words.len());
+/// The answer of today's daily game, when admins didn't schedule one a random
+/// word is scheduled so that every daily game of the day has the same answer.
+async fn daily_word(state: &AppState) -> Result<String, String> {
+    let today = Utc::now().date_naive();
+
+    let mut dictionary = state.dictionary.write().await;
+    if let Some(word) = dictionary.daily_word(today) {
+        return Ok(word.to_string());
+    }
+
+    let word = state.random_word(&dictionary.words)?;
+    dictionary.schedule.insert(today, word.clone());
+
+    match state.storage.save_dictionary(&dictionary).await {
+        Ok(_) => {}
+        Err(err) => panic!("{err}"),
+    }
+
+    Ok(word)
+}
+
+#[derive(Deserialize)]
+struct NewGameQuery {
+    #[serde(default)]
+    mode: GameMode,
+}
+
+async fn new_game(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<NewGameQuery>,
+) -> Response {
+    let word = match query.mode {
+        GameMode::Daily => daily_word(&state).await,
+        _ => state.random_word(&state.dictionary.read().await.words),
+    };
+    let word = match word {
+        Ok(word) => word,
+        Err(err) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                toast_markup(html! { (err) }),
+            )
+                .into_response()
+        }
+    };
 
-    let word = state.words[random_index].to_string();
+    // Remember new players so that their games show up on the leaderboard.
+    let known_player = player::from_headers(&headers);
+    let player = known_player.unwrap_or_else(Uuid::new_v4);
 
-    // Create a new game with a unique id.
+    // Create a new game with an unguessable id, it's the only way to play it.
     let id = Uuid::new_v4();
 
-    let game = Game::new(id, word.clone());
+    let mut game = Game::new(id, word).with_mode(query.mode);
+    game.player = Some(player);
 
     // Drop write lock at end of block
     {
@@ -157,104 +334,230 @@ async fn new_game(State(state): State<Arc<AppState>>) -> Response {
     }
 
     // Save state with new game
-    match storage::save(state.get_save_data().await).await {
+    match state.storage.save(state.get_save_data().await).await {
         Ok(_) => {}
         Err(err) => panic!("{err}"),
     }
 
-    println!("id: {id}, word: {word}");
+    // Never log the word.
+    println!("new game: {}", short_id(id));
 
     // HX-Location
     // This response header can be used to trigger a client side redirection without
     // reloading the whole page. Instead of changing the page’s location it will act
     // like following a hx-boost link, creating a new history entry, issuing an ajax
     // request to the value of the header and pushing the path into history.
+    let mut response = Response::builder()
+        .status(StatusCode::CREATED)
+        .header("HX-Location", format!("/game/{id}"));
+    if known_player.is_none() {
+        response = response.header(SET_COOKIE, player::cookie(player));
+    }
+    response.body(Body::empty()).unwrap()
+}
+
+/// Continues an endless run with the next puzzle, only won games can be
+/// continued and each game is continued once.
+async fn next_game(State(state): State<Arc<AppState>>, Path(game_id): Path<GameId>) -> Response {
+    let next_id = {
+        let mut games = state.games.write().await;
+        let Some(game) = games.get_mut(&game_id) else {
+            return (
+                StatusCode::NOT_FOUND,
+                toast_markup(html! { "game doesn't exist" }),
+            )
+                .into_response();
+        };
+
+        if game.mode != GameMode::Endless || !game.is_victory() {
+            return (
+                StatusCode::CONFLICT,
+                toast_markup(html! { "only won endless games continue" }),
+            )
+                .into_response();
+        }
+
+        match game.next {
+            // The run was already continued, e.g. a double click.
+            Some(next_id) => next_id,
+            None => {
+                let next_id = Uuid::new_v4();
+                let word = match state.random_word(&state.dictionary.read().await.words) {
+                    Ok(word) => word,
+                    Err(err) => {
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            toast_markup(html! { (err) }),
+                        )
+                            .into_response()
+                    }
+                };
+                let next = game.next_in_run(next_id, word);
+                game.next = Some(next_id);
+                games.insert(next_id, next);
+                next_id
+            }
+        }
+    };
+
+    match state.storage.save(state.get_save_data().await).await {
+        Ok(_) => {}
+        Err(err) => panic!("{err}"),
+    }
+
     Response::builder()
         .status(StatusCode::CREATED)
-        .header("HX-Location", format!("/game/{id}"))
+        .header("HX-Location", format!("/game/{next_id}"))
         .body(Body::empty())
         .unwrap()
 }
 
-#[derive(Deserialize)]
-struct GuessQuery {
-    guess: Option<String>,
-}
-
+/// Renders the game, guesses are made with `add_guess`.
 async fn game(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     Path(game_id): Path<GameId>,
-    Query(query): Query<GuessQuery>,
-) -> Markup {
+) -> Response {
     // Check headers if we should send a fragment or a full page back.
     let is_fragment = headers.get("hx-request").is_some()
         && headers
             .get("hx-target")
-            .map_or(false, |target| target == "wordle-content");
+            .is_some_and(|target| target == "wordle-content");
 
-    let markup = match state.games.write().await.get_mut(&game_id) {
+    match state.games.read().await.get(&game_id) {
         Some(game) => {
-            let mut valid_word = true;
-
-            if let Some(guess) = &query.guess {
-                let guess = guess.trim().to_lowercase();
-                if state.words.contains(&guess.as_str()) {
-                    game.add_guess(guess);
-                } else {
-                    valid_word = false;
-                }
-            }
-
             // Send the fragment or the full page.
-            let fragment = game_fragment(&game, query.guess, valid_word);
+            let fragment = game_fragment(game, None);
             if is_fragment {
-                fragment
+                fragment.into_response()
             } else {
                 base(html! {
                     h1 { "📕 " a hx-boost="true" href="/" .text-dark { "Wordle" } }
-                    p { (short_id(game.id)) }
+                    p { (game.mode.emoji()) " " (short_id(game.id)) }
                     div #wordle-content {
                         (fragment)
                     }
+                    // Live region, guesses swap their result into it.
+                    div #announcer .visually-hidden role="status" aria-live="polite" {}
                 })
+                .into_response()
             }
         }
-        None => base(html! {
-            div class="text-center p-2" {
-                h1 { "Game doesn't exist!" }
-                p { (game_id) }
-                (new_game_btn_markup())
+        None => (
+            StatusCode::NOT_FOUND,
+            base(html! {
+                div class="text-center p-2" {
+                    h1 { "Game doesn't exist!" }
+                    p { (game_id) }
+                    (new_game_btn_markup())
+                }
+            }),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct GuessForm {
+    #[serde(default)]
+    guess: String,
+    /// How many guesses the player had seen when submitting, used to tell
+    /// double submits apart from new guesses.
+    turn: Option<usize>,
+}
+
+/// Adds a guess to the game and sends back the game fragment, problems with
+/// the guess are sent as a toast with a client error status.
+async fn add_guess(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(game_id): Path<GameId>,
+    Form(form): Form<GuessForm>,
+) -> Response {
+    // Limit guesses per game and per player so that words can't be brute
+    // forced, players can make up new cookies but not new games.
+    let rate_limit = {
+        let now = Instant::now();
+        let by_game = state.guess_limiter.check(game_id, now).err();
+        let by_player = player::from_headers(&headers)
+            .and_then(|player| state.guess_limiter.check(player, now).err());
+        by_game.max(by_player)
+    };
+
+    let mut games = state.games.write().await;
+    let Some(game) = games.get_mut(&game_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            toast_markup(html! { "game doesn't exist" }),
+        )
+            .into_response();
+    };
+
+    let guess = game::parse_guess(&form.guess);
+    let turn = form.turn.unwrap_or(game.guesses.len());
+    let now = Utc::now();
+
+    let error = if let Some(wait) = rate_limit {
+        Some((
+            StatusCode::TOO_MANY_REQUESTS,
+            html! { "too many guesses, wait " (wait.as_secs()) "s" },
+        ))
+    } else if turn + 1 == game.guesses.len() && game.guesses.last() == guess.as_ref() {
+        // The same guess was submitted twice, the first one already counted.
+        return game_fragment(game, None).into_response();
+    } else if turn != game.guesses.len() {
+        Some((
+            StatusCode::CONFLICT,
+            html! { "the game has changed, try again" },
+        ))
+    } else if game.is_complete(now) {
+        Some((StatusCode::CONFLICT, html! { "the game is over" }))
+    } else {
+        match guess {
+            // The answer may have been removed from the dictionary since.
+            Some(guess) if guess == game.word || state.dictionary.read().await.contains(&guess) => {
+                game.add_guess(guess, now);
+                None
             }
-        }),
+            Some(guess) => Some((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                html! { b { (guess) }" is not a valid word" },
+            )),
+            None => Some((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                html! { "guesses have to be five letters" },
+            )),
+        }
+    };
+
+    if let Some((status, message)) = error {
+        return (status, game_fragment(game, Some(toast_markup(message)))).into_response();
+    }
+
+    // The result of the guess is announced to screen readers.
+    let fragment = html! {
+        (game_fragment(game, None))
+        div #announcer hx-swap-oob="innerHTML" { (game.announcement(now)) }
     };
+    drop(games);
 
     // Save the state after each guess
-    match storage::save(state.get_save_data().await).await {
+    match state.storage.save(state.get_save_data().await).await {
         Ok(_) => {}
         Err(err) => panic!("{err}"),
     }
 
-    markup
+    fragment.into_response()
 }
 
-fn game_fragment(game: &Game, guess: Option<String>, valid_word: bool) -> Markup {
-    html! {
-
-        // Is this a guess attempt?
-        @if let Some(guess) = guess {
-            // h3 { "Guess: " (guess) }
+fn game_fragment(game: &Game, toast: Option<Markup>) -> Markup {
+    let now = Utc::now();
+    let complete = game.is_complete(now);
 
-            @if valid_word == false {
-                // Toast – maybe need to use hx-swap-oob="true"
-                div
-                role="alert"
-                #toast .alert .alert-danger
-                style="position: absolute; top: 10px; left: 10px;"
-                x-data="{ show: true }" x-show="show"
-                x-init="setTimeout(() => show = false, 2000)" "x-transition.duration.500ms"
-                { b { (guess) }" is not a valid word" }
-            }
+    html! {
+        // Did the guess attempt go wrong?
+        @if let Some(toast) = toast {
+            (toast)
         }
 
         div
@@ -265,26 +568,51 @@ fn game_fragment(game: &Game, guess: Option<String>, valid_word: bool) -> Markup
         "@click-letter.window"="addLetter($event.detail.letter)" // add letter on `click`
         "@click-erase.window"="removeLetter()" // remove letter on `click`
         {
-            @let mut show_dynamic = !game.is_complete();
-            @for guess in &game.get_guesses() {
+            // The first empty row is where the player types.
+            @let dynamic_row = (!complete).then_some(game.guesses.len());
+            @for (row, guess) in game.get_guesses().iter().enumerate() {
                 @if let Some(guess) = guess {
                     (WordState::guess(guess, &game.word))
-                } @else if show_dynamic {
-                    @let _ = show_dynamic = false;
+                } @else if dynamic_row == Some(row) {
                     (dynamic_word_markup())
                 } @else {
                     (WordState::empty())
                 }
             }
 
+            // Reload the game when time runs out, the server decides it's lost.
+            @if let (false, Some(seconds_left)) = (complete, game.seconds_left(now)) {
+                div
+                .text-center .fw-bold
+                hx-get={"/game/"(game.id)}
+                hx-target="#wordle-content"
+                hx-trigger={"load delay:"(seconds_left)"s"}
+                x-data={"{ left: "(seconds_left)" }"}
+                x-init="setInterval(() => left = Math.max(0, left - 1), 1000)"
+                x-text="'⏱️ ' + Math.floor(left / 60) + ':' + String(left % 60).padStart(2, '0')"
+                { "⏱️ " (seconds_left / 60) ":" (format!("{:02}", seconds_left % 60)) }
+            }
+
+            @if !complete {
+                div .text-center {
+                    button
+                    class="btn btn-sm btn-outline-secondary"
+                    hx-post={"/game/"(game.id)"/hint"}
+                    hx-target="#hint"
+                    { "💡 Hint " (hints_used_markup(game, false)) }
+                    div #hint .m-2 {}
+                }
+            }
+
             // The form for guessing.
             // Note: we replace the entire body, could look into hx-select and target a specific id.
-            @if !game.is_complete() {
+            @if !complete {
                 form
                 #guess-form .text-center
-                hx-get={"/game/"(game.id)}
+                hx-post={"/game/"(game.id)"/guesses"}
                 hx-target="#wordle-content"
                 hx-swap="innerHTML"
+                hx-sync="this:drop" // ignore submits while one is in flight
                 hx-trigger="keydown[keyCode==13] from:body, click-guess from:body" // submit on `enter` or `click`
                 {
                     input
@@ -293,10 +621,29 @@ fn game_fragment(game: &Game, guess: Option<String>, valid_word: bool) -> Markup
                     id="guess"
                     name="guess"
                     {}
+                    input type="hidden" name="turn" value=(game.guesses.len()) {}
                 }
             } @else {
                 div class="text-center" {
+                    @if game.is_out_of_time(now) {
+                        h5 .text-danger { "⏱️ out of time" }
+                    }
                     h3 { "the word was: " b { (game.word) } }
+                    @if game.is_victory() {
+                        p { (game.mode.emoji()) " score: " b { (game.score) } }
+                    }
+                    @if game.mode == GameMode::Endless {
+                        @if game.is_victory() {
+                            button
+                            hx-post={"/game/"(game.id)"/next"}
+                            class="btn btn-success m-2"
+                            { "♾️ Next puzzle" }
+                        } @else {
+                            p { "♾️ run over, solved " b { (game.streak) } " in a row" }
+                        }
+                    }
+                    (share_markup(game, now))
+                    a hx-boost="true" href={"/game/"(game.id)"/analysis"} class="btn btn-outline-secondary m-2" { "🔎 Analysis" }
                     (new_game_btn_markup())
                 }
             }
@@ -306,18 +653,229 @@ fn game_fragment(game: &Game, guess: Option<String>, valid_word: bool) -> Markup
     }
 }
 
-async fn games(State(state): State<Arc<AppState>>) -> Markup {
+/// Suggests the next guess, hints are rate-limited per game.
+async fn hint(State(state): State<Arc<AppState>>, Path(game_id): Path<GameId>) -> Response {
+    let now = Utc::now();
+
+    // Record the hint while holding the lock so that hints can't be raced.
+    let game = {
+        let mut games = state.games.write().await;
+        let Some(game) = games.get_mut(&game_id) else {
+            return (
+                StatusCode::NOT_FOUND,
+                toast_markup(html! { "game doesn't exist" }),
+            )
+                .into_response();
+        };
+
+        if let Err(err) = game.check_hint(now) {
+            let status = match err {
+                HintError::GameOver => StatusCode::CONFLICT,
+                HintError::AllUsed | HintError::Cooldown(_) => StatusCode::TOO_MANY_REQUESTS,
+            };
+            return (status, toast_markup(html! { (err) })).into_response();
+        }
+
+        game.add_hint(now);
+        game.clone()
+    };
+
+    match state.storage.save(state.get_save_data().await).await {
+        Ok(_) => {}
+        Err(err) => panic!("{err}"),
+    }
+
+    // The counter on the button is out of the target, it's swapped by id.
+    let hints_used = hints_used_markup(&game, true);
+
+    // Scoring guesses is expensive so keep it off the async workers.
+    let words = state.words_for(&game).await;
+    let (candidates, best) = tokio::task::spawn_blocking(move || {
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+        let candidates = solver::candidates(&words, &game, &game.guesses);
+        let best = solver::best_guess(&words, &candidates).map(str::to_string);
+        (candidates.len(), best)
+    })
+    .await
+    .unwrap();
+
+    html! {
+        @if let Some(best) = best {
+            span { "💡 try " b { (best) } " – " (candidates) " possible words left" }
+        } @else {
+            span { "💡 no words left" }
+        }
+        (hints_used)
+    }
+    .into_response()
+}
+
+/// How many of the hints of a game are used, e.g. `1/3`.
+fn hints_used_markup(game: &Game, oob: bool) -> Markup {
+    html! {
+        small #hints-used hx-swap-oob=[oob.then_some("true")] {
+            (game.hints.len())"/"(game::MAX_HINTS)
+        }
+    }
+}
+
+/// Post-game breakdown of how many candidates each guess eliminated.
+async fn analysis(State(state): State<Arc<AppState>>, Path(game_id): Path<GameId>) -> Markup {
+    let game = state.games.read().await.get(&game_id).cloned();
+
+    let Some(game) = game else {
+        return base(html! {
+            div class="text-center p-2" {
+                h1 { "Game doesn't exist!" }
+                p { (game_id) }
+                (new_game_btn_markup())
+            }
+        });
+    };
+
+    // The analysis would be a free hint for games that are still going.
+    if !game.is_complete(Utc::now()) {
+        return base(html! {
+            div class="text-center p-2" {
+                h1 { "Game isn't over yet!" }
+                a hx-boost="true" href={"/game/"(game.id)} { (short_id(game.id)) }
+            }
+        });
+    }
+
+    let words = state.words_for(&game).await;
+    let game_clone = game.clone();
+    let analysis = tokio::task::spawn_blocking(move || {
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+        solver::analysis(&words, &game_clone)
+    })
+    .await
+    .unwrap();
+
+    base(html! {
+        h1 { "📕 " a hx-boost="true" href="/" .text-dark { "Wordle" } }
+        p { a hx-boost="true" href={"/game/"(game.id)} { (short_id(game.id)) } }
+        table class="table" {
+            thead {
+                tr {
+                    th scope="col" { "#" }
+                    th scope="col" { "Guess" }
+                    th scope="col" { "Before" }
+                    th scope="col" { "After" }
+                    th scope="col" { "Eliminated" }
+                }
+            }
+            tbody {
+                @for (index, row) in analysis.iter().enumerate() {
+                    tr {
+                        td { (index + 1) }
+                        td { (row.guess) " " (WordState::guess(&row.guess, &game.word).emoji()) }
+                        td { (row.before) }
+                        td { (row.after) }
+                        td { (row.eliminated()) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Public page of a game's result, it only shows the emoji grid so it never
+/// reveals the word.
+async fn share(State(state): State<Arc<AppState>>, Path(game_id): Path<GameId>) -> Markup {
     let games = state.games.read().await;
 
-    let mut games_sorted = games.values().collect::<Vec<_>>();
-    games_sorted.sort_by_key(|g| g.created);
-    games_sorted.reverse();
+    let Some(game) = games.get(&game_id) else {
+        return base(html! {
+            div class="text-center p-2" {
+                h1 { "Game doesn't exist!" }
+                p { (game_id) }
+                (new_game_btn_markup())
+            }
+        });
+    };
+
+    let title = format!("📕 Wordle {}", game.share_score(Utc::now()));
+    let grid = game.share_grid();
+
+    let meta = html! {
+        meta property="og:type" content="website";
+        meta property="og:title" content=(title);
+        meta property="og:description" content=(grid);
+        meta property="og:site_name" content="Wordle (htmx)";
+    };
+
+    base_with_meta(
+        meta,
+        html! {
+            div class="mx-auto text-center" style="max-width:400px;" {
+                div .card {
+                    div .card-body {
+                        h1 .card-title { (title) }
+                        p .text-secondary { (short_id(game.id)) }
+                        pre .fs-3 { (grid) }
+                        (new_game_btn_markup())
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// The spoiler-free result of a finished game with a copy-to-clipboard
+/// button, the copied text links to the public share page.
+fn share_markup(game: &Game, now: DateTime<Utc>) -> Markup {
+    let text = format!(
+        "📕 Wordle {}\n\n{}",
+        game.share_score(now),
+        game.share_grid()
+    );
 
     html! {
+        div x-data="{ copied: false }" {
+            pre x-ref="grid" .fs-5 { (text) }
+            button
+            class="btn btn-outline-primary m-2"
+            "@click"={"navigator.clipboard.writeText($refs.grid.innerText + '\\n' + location.origin + '/game/"(game.id)"/share'); copied = true"}
+            x-text="copied ? '✅ Copied' : '📋 Share'"
+            { "📋 Share" }
+        }
+    }
+}
+
+/// The games table with its filters, only the first page of rows is sent,
+/// the rest are loaded with `games_rows` while scrolling.
+async fn games(State(state): State<Arc<AppState>>, Query(query): Query<GamesQuery>) -> Markup {
+    let games = state.games.read().await;
+    let now = Utc::now();
+    let (page, has_more) = query.page(games.values(), now);
+
+    html! {
+        form
+        #games-filters
+        class="d-flex flex-wrap gap-2 mb-2"
+        hx-get="/games"
+        hx-target="#all-games"
+        hx-trigger="change"
+        {
+            select name="status" class="form-select form-select-sm w-auto" {
+                option value="" { "All" }
+                @for status in GameStatus::ALL {
+                    option value=(status.as_str()) selected[query.status == Some(status)] { (status.as_str()) }
+                }
+            }
+            select name="sort" class="form-select form-select-sm w-auto" {
+                @for sort in GameSort::ALL {
+                    option value=(sort.as_str()) selected[query.sort == sort] { (sort.label()) }
+                }
+            }
+            input type="date" name="from" class="form-control form-control-sm w-auto" value=[query.from];
+            input type="date" name="to" class="form-control form-control-sm w-auto" value=[query.to];
+        }
         table class="table" {
             thead {
                 tr {
-                    th scope="col" { "Word" }
+                    th scope="col" { "Result" }
                     th scope="col" { "Final" }
                     th scope="col" { "Guess" }
                     th scope="col" { "Date" }
@@ -325,26 +883,125 @@ async fn games(State(state): State<Arc<AppState>>) -> Markup {
                 }
             }
             tbody {
-                @for game in games_sorted {
-                    @let complete = game.is_complete();
-                    @let victory = game.is_victory();
-                    @let loss = game.is_loss();
-                    @let guesses = game.guesses.len();
-                    @let last_guess = game.guesses.iter().last();
-                    tr .table-warning[victory] .table-danger[loss] .fw-bold[complete] {
-                        @if complete {
-                            td .text-warning[victory] .text-danger[loss] { (game.word) }
-                        } @else {
-                            td { "?????" }
-                        }
-                        @if last_guess.is_some() {
-                            td .text-warning[victory] .text-danger[loss] { (last_guess.unwrap()) }
-                        } @else {
-                            td { "-----" }
+                (games_rows_markup(&query, &page, has_more, now))
+            }
+        }
+    }
+}
+
+/// A page of rows of the games table.
+async fn games_rows(State(state): State<Arc<AppState>>, Query(query): Query<GamesQuery>) -> Markup {
+    let games = state.games.read().await;
+    let now = Utc::now();
+    let (page, has_more) = query.page(games.values(), now);
+    games_rows_markup(&query, &page, has_more, now)
+}
+
+fn games_rows_markup(
+    query: &GamesQuery,
+    games: &[&Game],
+    has_more: bool,
+    now: DateTime<Utc>,
+) -> Markup {
+    html! {
+        // Words and links to games are never shown, other people's games could
+        // still be going and their links would let anyone play them. Finished
+        // games link to their share page instead.
+        @for (index, game) in games.iter().enumerate() {
+            @let complete = game.is_complete(now);
+            @let victory = game.is_victory();
+            @let loss = game.is_loss(now);
+            @let guesses = game.guesses.len();
+            @let last_guess = game.guesses.iter().last();
+            @let next_page = (has_more && index == games.len() - 1).then(|| {
+                format!("/games/rows?{}", query.to_query_string(Cursor::of(game)))
+            });
+
+            // On reveal of the last row the next page is added after it.
+            tr
+            .table-warning[victory] .table-danger[loss] .fw-bold[complete]
+            hx-get=[next_page.as_deref()]
+            hx-trigger=[next_page.as_ref().map(|_| "revealed")]
+            hx-swap=[next_page.as_ref().map(|_| "afterend")]
+            {
+                td .text-warning[victory] .text-danger[loss] { (game.mode.emoji()) " " (game.share_score(now)) }
+                @if let Some(last_guess) = last_guess {
+                    td { (WordState::guess(last_guess, &game.word).emoji()) }
+                } @else {
+                    td { "-----" }
+                }
+                td { (guesses)"/6" }
+                td { (game.created.unwrap().format("%y/%m/%d")) }
+                @if complete {
+                    td { a hx-boost="true" href={"/game/"(game.id)"/share"} { "share" } }
+                } @else {
+                    td { "-" }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default)]
+    period: Period,
+}
+
+/// The leaderboard of a period, it refreshes itself every so often.
+async fn leaderboard(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<LeaderboardQuery>,
+) -> Markup {
+    let you = player::from_headers(&headers);
+    let entries = {
+        let games = state.games.read().await;
+        leaderboard::leaderboard(games.values(), query.period, Utc::now())
+    };
+
+    html! {
+        div
+        #leaderboard
+        hx-get={"/leaderboard?period="(query.period.as_str())}
+        hx-trigger="every 30s"
+        hx-swap="outerHTML"
+        {
+            div class="btn-group btn-group-sm mb-2" {
+                @for period in Period::ALL {
+                    button
+                    .btn .btn-outline-warning .active[period == query.period]
+                    hx-get={"/leaderboard?period="(period.as_str())}
+                    hx-target="#leaderboard"
+                    hx-swap="outerHTML"
+                    { (period.label()) }
+                }
+            }
+            table class="table" {
+                thead {
+                    tr {
+                        th scope="col" { "#" }
+                        th scope="col" { "Player" }
+                        th scope="col" { "Wins" }
+                        th scope="col" { "Avg" }
+                        th scope="col" { "Streak" }
+                        th scope="col" { "Played" }
+                    }
+                }
+                tbody {
+                    @for (index, entry) in entries.iter().enumerate() {
+                        @let is_you = you == Some(entry.player);
+                        tr .table-warning[is_you] .fw-bold[is_you] {
+                            td { (index + 1) }
+                            td { (short_id(entry.player)) @if is_you { " (you)" } }
+                            td { (entry.wins) }
+                            td { (format!("{:.2}", entry.average_guesses)) }
+                            td { (entry.best_streak) }
+                            td { (entry.played) }
                         }
-                        td { (guesses)"/6" }
-                        td { (game.created.unwrap().format("%y/%m/%d")) }
-                        td { a hx-boost="true" href={"/game/"(game.id)} { (short_id(game.id)) } }
+                    }
+                    @if entries.is_empty() {
+                        tr { td colspan="6" .text-center { "no finished games yet" } }
                     }
                 }
             }
@@ -352,10 +1009,38 @@ async fn games(State(state): State<Arc<AppState>>) -> Markup {
     }
 }
 
+/// A toast that disappears after a couple of seconds.
+fn toast_markup(content: Markup) -> Markup {
+    // Toast – maybe need to use hx-swap-oob="true"
+    html! {
+        div
+        role="alert"
+        #toast .alert .alert-danger
+        style="position: absolute; top: 10px; left: 10px;"
+        x-data="{ show: true }" x-show="show"
+        x-init="setTimeout(() => show = false, 2000)" "x-transition.duration.500ms"
+        { (content) }
+    }
+}
+
 fn new_game_btn_markup() -> Markup {
     html! { button hx-get="/new_game" hx-target="body" class="btn btn-primary m-2" { "⭐️ Play" } }
 }
 
+fn new_game_mode_btn_markup(mode: GameMode) -> Markup {
+    html! {
+        button
+        hx-get={"/new_game?mode="(mode.as_str())}
+        hx-target="body"
+        class="btn btn-outline-primary m-2"
+        { (mode.emoji()) " " (mode.label()) }
+    }
+}
+
+fn leaderboard_btn_markup() -> Markup {
+    html! { button hx-target="#all-games" hx-get="/leaderboard" class="btn btn-warning m-2" { "🏆 Leaderboard" } }
+}
+
 fn all_games_btn_markup(count: usize) -> Markup {
     html! { button hx-target="#all-games" hx-get="/games" class="btn btn-warning m-2" { "📘 Games " small { (count) } } }
 }
@@ -380,21 +1065,31 @@ fn available_letters_markup(available_letters: &HashMap<char, Letter>) -> Markup
                     @for letter in segment {
                         @if let Some(event) = letter.1 {
                             // Special buttons: guess/erase – they dispatch events
+                            @let label = if event == "click-guess" { "submit guess" } else { "erase letter" };
                             div
                             .p-2 .btn .btn-outline-primary
+                            role="button"
+                            aria-label=(label)
                             "@mousedown"={"$dispatch('"(event)"')"}
                             { (letter.0) }
                         } @else {
                             // Basic buttons – they dispatch events
-                            @let class = match available_letters[&letter.0].state {
+                            @let available_letter = &available_letters[&letter.0];
+                            @let class = match available_letter.state {
                                 LetterState::Correct => "btn btn-success",
                                 LetterState::WrongPlace => "btn btn-warning",
                                 LetterState::Wrong => "btn btn-secondary",
                                 LetterState::Empty => "btn btn-primary",
                             };
+                            @let label = match available_letter.state {
+                                LetterState::Empty => letter.0.to_string(),
+                                _ => available_letter.label(),
+                            };
 
                             div
-                            class={"p-2 " (class)}
+                            class={"p-2 " (class) " " (available_letter.state.class())}
+                            role="button"
+                            aria-label=(label)
                             "@mousedown"={"$dispatch('click-letter', { letter: '"(letter.0)"' })"}
                             { (letter.0) }
                         }
@@ -410,7 +1105,7 @@ fn available_letters_markup(available_letters: &HashMap<char, Letter>) -> Markup
 impl Render for WordState {
     fn render(&self) -> Markup {
         html! {
-            div class = "d-flex justify-content-center gap-1 mb-1" {
+            div class = "d-flex justify-content-center gap-1 mb-1" role="group" {
                 @for letter in &self.letters {
                     @let class = match letter.state {
                         LetterState::Correct => "bg-success",
@@ -418,7 +1113,12 @@ impl Render for WordState {
                         LetterState::Wrong => "bg-secondary",
                         LetterState::Empty => "bg-light",
                     };
-                    div class={"p-2 text-white border " (class)} { (letter.id) }
+                    // The colour alone doesn't say anything to screen readers.
+                    div
+                    class={"p-2 text-white border " (class) " " (letter.state.class())}
+                    role="img"
+                    aria-label=(letter.label())
+                    { (letter.id) }
                 }
             }
         }
@@ -428,7 +1128,7 @@ impl Render for WordState {
 /// The markup for the text that the player is typing (uses alpine)
 fn dynamic_word_markup() -> Markup {
     html! {
-        div class="d-flex justify-content-center gap-1 mb-1" {
+        div class="d-flex justify-content-center gap-1 mb-1" aria-label="current guess" x-bind:aria-description="combine" {
             template x-for="(letter, index) in fill" ":key"="index" {
                 div
                 x-from-template
@@ -0,0 +1,42 @@
+use axum::http::HeaderMap;
+use uuid::Uuid;
+
+use crate::cookie;
+
+/// Players are anonymous, they're only known by a random id in a cookie.
+pub type PlayerId = Uuid;
+
+const COOKIE_NAME: &str = "player";
+
+/// The player of the request, if they have played before.
+pub fn from_headers(headers: &HeaderMap) -> Option<PlayerId> {
+    cookie::get(headers, COOKIE_NAME).and_then(|value| value.parse().ok())
+}
+
+/// The `Set-Cookie` value that remembers the player for a year.
+pub fn cookie(id: PlayerId) -> String {
+    format!("{COOKIE_NAME}={id}; Path=/; Max-Age=31536000; HttpOnly; SameSite=Lax")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderValue};
+
+    use super::*;
+
+    #[test]
+    fn player_from_cookie() {
+        let id = Uuid::new_v4();
+        let mut headers = HeaderMap::new();
+        assert_eq!(from_headers(&headers), None);
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; player={id}")).unwrap(),
+        );
+        assert_eq!(from_headers(&headers), Some(id));
+
+        headers.insert(header::COOKIE, HeaderValue::from_static("player=nope"));
+        assert_eq!(from_headers(&headers), None);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{extract::ConnectInfo, http::HeaderMap};
+
+/// Forget about keys once there's this many of them and their windows are
+/// over, so that the map doesn't grow forever.
+const MAX_KEYS: usize = 10_000;
+
+/// A fixed-window rate limiter, every key can be hit `limit` times per
+/// `window`.
+pub struct RateLimiter<K> {
+    limit: u32,
+    window: Duration,
+    hits: Mutex<HashMap<K, (Instant, u32)>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a hit for the key, returns how long to wait when the limit is
+    /// reached.
+    pub fn check(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let mut hits = self.hits.lock().unwrap();
+
+        if hits.len() >= MAX_KEYS {
+            hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let (start, count) = hits.entry(key).or_insert((now, 0));
+
+        // Start a new window once the old one is over.
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+
+        if *count >= self.limit {
+            return Err(self.window - now.duration_since(*start));
+        }
+
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// The address of the client. Behind a proxy it's the last address of
+/// `X-Forwarded-For`, the proxy adds it and clients can only add others
+/// before it.
+pub fn client_address(
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> IpAddr {
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|address| address.trim().parse().ok());
+    forwarded
+        .or(connect_info.map(|ConnectInfo(address)| address.ip()))
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_per_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(limiter.check("a", now).is_ok());
+        assert!(limiter.check("a", now).is_ok());
+        assert_eq!(limiter.check("a", now), Err(Duration::from_secs(10)));
+
+        // Other keys have their own limit.
+        assert!(limiter.check("b", now).is_ok());
+
+        let later = now + Duration::from_secs(4);
+        assert_eq!(limiter.check("a", later), Err(Duration::from_secs(6)));
+
+        let next_window = now + Duration::from_secs(10);
+        assert!(limiter.check("a", next_window).is_ok());
+    }
+
+    #[test]
+    fn client_addresses() {
+        let proxy = ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000)));
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            client_address(&headers, Some(proxy)),
+            IpAddr::from([10, 0, 0, 1])
+        );
+
+        // The client made up the first address, the proxy added the last.
+        headers.insert("x-forwarded-for", "1.2.3.4, 5.6.7.8".parse().unwrap());
+        assert_eq!(
+            client_address(&headers, Some(proxy)),
+            IpAddr::from([5, 6, 7, 8])
+        );
+        assert_eq!(
+            client_address(&HeaderMap::new(), None),
+            IpAddr::from([0, 0, 0, 0])
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use crate::game::Game;
+
+/// How many candidates are used at most when scoring a guess, scoring against
+/// every candidate at the start of a game is too slow for a hint.
+const MAX_SAMPLE: usize = 500;
+
+/// The feedback of a guess against an answer encoded as a base-3 number, it
+/// follows the same rules as `WordState::guess`: 0 is correct, 1 is in the
+/// wrong place and 2 is wrong.
+pub fn feedback(guess: &str, answer: &str) -> u8 {
+    let answer = answer.as_bytes();
+    let mut duplicates = 0u32;
+    let mut code = 0;
+
+    for (position, &letter) in guess.as_bytes().iter().enumerate() {
+        let bit = 1 << (letter % 32);
+        let state = if answer.get(position) == Some(&letter) {
+            0
+        } else if answer.contains(&letter) && duplicates & bit == 0 {
+            duplicates |= bit;
+            1
+        } else {
+            2
+        };
+        code = code * 3 + state;
+    }
+
+    code
+}
+
+/// All words that could still be the answer after the given guesses.
+pub fn candidates<'a>(words: &[&'a str], game: &Game, guesses: &[String]) -> Vec<&'a str> {
+    let feedbacks = guesses
+        .iter()
+        .map(|guess| (guess.as_str(), feedback(guess, &game.word)))
+        .collect::<Vec<_>>();
+
+    words
+        .iter()
+        .copied()
+        .filter(|word| {
+            feedbacks
+                .iter()
+                .all(|(guess, expected)| feedback(guess, word) == *expected)
+        })
+        .collect()
+}
+
+/// The guess that splits the candidates into the most even groups of
+/// feedback, i.e. the one with the highest expected information.
+pub fn best_guess<'a>(words: &[&'a str], candidates: &[&'a str]) -> Option<&'a str> {
+    match candidates {
+        [] => return None,
+        [only] | [only, _] => return Some(only),
+        _ => {}
+    }
+
+    // Keep an evenly distributed sample of the candidates.
+    let step = candidates.len().div_ceil(MAX_SAMPLE);
+    let sample = candidates.iter().step_by(step).collect::<Vec<_>>();
+
+    let mut best: Option<(&str, f64)> = None;
+
+    for &guess in words {
+        let mut groups: HashMap<u8, usize> = HashMap::new();
+        for answer in &sample {
+            *groups.entry(feedback(guess, answer)).or_default() += 1;
+        }
+
+        let total = sample.len() as f64;
+        let mut entropy = groups
+            .values()
+            .map(|&count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum::<f64>();
+
+        // Prefer guesses that could be the answer when they're as good.
+        if candidates.contains(&guess) {
+            entropy += 1e-6;
+        }
+
+        if best.is_none_or(|(_, best_entropy)| entropy > best_entropy) {
+            best = Some((guess, entropy));
+        }
+    }
+
+    best.map(|(guess, _)| guess)
+}
+
+/// How many candidates were left before and after a guess.
+pub struct GuessAnalysis {
+    pub guess: String,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl GuessAnalysis {
+    pub fn eliminated(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+/// Breakdown of every guess of the game.
+pub fn analysis(words: &[&str], game: &Game) -> Vec<GuessAnalysis> {
+    let mut before = words.len();
+
+    game.guesses
+        .iter()
+        .enumerate()
+        .map(|(index, guess)| {
+            let after = candidates(words, game, &game.guesses[..=index]).len();
+            let analysis = GuessAnalysis {
+                guess: guess.clone(),
+                before,
+                after,
+            };
+            before = after;
+            analysis
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::game::{LetterState, WordState};
+
+    const WORDS: [&str; 8] = [
+        "final", "crane", "slate", "smell", "state", "pilot", "flail", "fanal",
+    ];
+
+    fn game_with_guesses(word: &str, guesses: &[&str]) -> Game {
+        let mut game = Game::new(Uuid::new_v4(), word.to_string());
+        for guess in guesses {
+            game.add_guess(guess.to_string(), Utc::now());
+        }
+        game
+    }
+
+    #[test]
+    fn feedback_matches_word_state() {
+        for guess in WORDS {
+            for answer in WORDS {
+                let expected =
+                    WordState::guess(guess, answer)
+                        .letters
+                        .iter()
+                        .fold(0, |code, letter| {
+                            code * 3
+                                + match letter.state {
+                                    LetterState::Correct => 0,
+                                    LetterState::WrongPlace => 1,
+                                    _ => 2,
+                                }
+                        });
+                assert_eq!(feedback(guess, answer), expected, "{guess} -> {answer}");
+            }
+        }
+    }
+
+    #[test]
+    fn candidates_are_consistent_with_guesses() {
+        let game = game_with_guesses("final", &["crane"]);
+        let candidates = candidates(&WORDS, &game, &game.guesses);
+        assert!(candidates.contains(&"final"));
+        assert!(!candidates.contains(&"crane"));
+        assert!(!candidates.contains(&"slate"));
+    }
+
+    #[test]
+    fn best_guess_of_few_candidates() {
+        assert_eq!(best_guess(&WORDS, &[]), None);
+        assert_eq!(best_guess(&WORDS, &["final"]), Some("final"));
+        assert!(best_guess(&WORDS, &WORDS).is_some());
+    }
+
+    #[test]
+    fn analysis_counts_eliminated_candidates() {
+        let game = game_with_guesses("final", &["crane", "final"]);
+        let analysis = analysis(&WORDS, &game);
+        assert_eq!(analysis.len(), 2);
+        assert_eq!(analysis[0].before, WORDS.len());
+        assert_eq!(analysis[1].after, 1);
+        assert_eq!(
+            analysis.iter().map(|a| a.eliminated()).sum::<usize>(),
+            WORDS.len() - 1
+        );
+    }
+}
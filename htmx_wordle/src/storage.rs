@@ -1,35 +1,141 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
-use chrono::Utc;
+use chrono::{NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::{
     fs::{create_dir_all, File},
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
-use crate::game::Game;
+use crate::{dictionary::Dictionary, game::Game};
 
-pub const SAVE_DATA_PATH: &'static str = "data/save_data.json";
+const SAVE_DATA_FILE: &str = "save_data.json";
+const WORDS_FILE: &str = "words.txt";
+const SCHEDULE_FILE: &str = "schedule.json";
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SaveData {
     pub games: Vec<Game>,
 }
 
-pub async fn save(games: Vec<Game>) -> Result<(), String> {
-    let save_data = SaveData { games };
+/// Where games and the dictionary are saved.
+#[derive(Clone, Debug)]
+pub enum Storage {
+    /// Files in a directory.
+    Disk(PathBuf),
+    /// Nothing is saved, e.g. in tests.
+    Memory,
+}
 
-    // Serialize the data to a JSON string
-    let json_string = match serde_json::to_string(&save_data) {
-        Ok(json) => json,
-        Err(e) => {
-            return Err(format!("Error serializing to JSON: {}", e));
+impl Storage {
+    pub async fn save(&self, games: Vec<Game>) -> Result<(), String> {
+        let Storage::Disk(dir) = self else {
+            return Ok(());
+        };
+        let path = dir.join(SAVE_DATA_FILE);
+
+        let save_data = SaveData { games };
+
+        // Serialize the data to a JSON string
+        let json_string = match serde_json::to_string(&save_data) {
+            Ok(json) => json,
+            Err(e) => {
+                return Err(format!("Error serializing to JSON: {}", e));
+            }
+        };
+
+        write(&path, &json_string).await?;
+
+        // Note: never log the games, they contain the words.
+        println!("{:?}: saved {} games", path, save_data.games.len());
+
+        Ok(())
+    }
+
+    pub async fn load(&self) -> Result<SaveData, String> {
+        let Storage::Disk(dir) = self else {
+            return Ok(SaveData { games: vec![] });
+        };
+        let path = dir.join(SAVE_DATA_FILE);
+
+        let contents = read(&path).await?;
+
+        // Deserialize the JSON string into a SaveData struct
+        let mut save_data: SaveData = match serde_json::from_str(&contents) {
+            Ok(data) => data,
+            Err(e) => {
+                return Err(format!("Error deserializing JSON: {}", e));
+            }
+        };
+
+        for x in &mut save_data.games {
+            if x.created.is_none() {
+                x.created = Some(Utc::now());
+            }
         }
-    };
 
-    // Path to write the JSON file
-    let file_path = Path::new(SAVE_DATA_PATH);
+        println!("{:?}: loaded {} games", path, save_data.games.len());
+
+        Ok(save_data)
+    }
+
+    /// Saves the words (one per line) and the schedule of daily answers.
+    pub async fn save_dictionary(&self, dictionary: &Dictionary) -> Result<(), String> {
+        let Storage::Disk(dir) = self else {
+            return Ok(());
+        };
+        let words_path = dir.join(WORDS_FILE);
+
+        let schedule = match serde_json::to_string(&dictionary.schedule) {
+            Ok(json) => json,
+            Err(e) => {
+                return Err(format!("Error serializing to JSON: {}", e));
+            }
+        };
+
+        write(&words_path, &dictionary.words.join("\n")).await?;
+        write(&dir.join(SCHEDULE_FILE), &schedule).await?;
 
+        // Note: never log the schedule, it contains upcoming answers.
+        println!("{:?}: saved {} words", words_path, dictionary.words.len());
+
+        Ok(())
+    }
+
+    /// Loads the dictionary saved by admins, the schedule is optional.
+    pub async fn load_dictionary(&self) -> Result<Dictionary, String> {
+        let Storage::Disk(dir) = self else {
+            return Err("Error loading dictionary: nothing is saved in memory".to_string());
+        };
+        let words_path = dir.join(WORDS_FILE);
+
+        let mut dictionary = Dictionary::from_words(&read(&words_path).await?);
+        if dictionary.words.is_empty() {
+            return Err(format!(
+                "Error loading dictionary: {words_path:?} has no words"
+            ));
+        }
+
+        if let Ok(contents) = read(&dir.join(SCHEDULE_FILE)).await {
+            dictionary.schedule =
+                match serde_json::from_str::<BTreeMap<NaiveDate, String>>(&contents) {
+                    Ok(schedule) => schedule,
+                    Err(e) => {
+                        return Err(format!("Error deserializing JSON: {}", e));
+                    }
+                };
+        }
+
+        println!("{:?}: loaded {} words", words_path, dictionary.words.len());
+
+        Ok(dictionary)
+    }
+}
+
+async fn write(file_path: &Path, contents: &str) -> Result<(), String> {
     // Create directories recursively if they don't exist
     if let Some(parent) = file_path.parent() {
         if !parent.exists() {
@@ -40,29 +146,24 @@ pub async fn save(games: Vec<Game>) -> Result<(), String> {
     }
 
     // Open the file in write mode
-    let mut file = match File::create(&file_path).await {
+    let mut file = match File::create(file_path).await {
         Ok(file) => file,
         Err(e) => {
             return Err(format!("Error creating file: {}", e));
         }
     };
 
-    // Write the JSON string to the file
-    if let Err(e) = file.write_all(json_string.as_bytes()).await {
+    // Write the contents to the file
+    if let Err(e) = file.write_all(contents.as_bytes()).await {
         return Err(format!("Error writing to file: {}", e));
     }
 
-    println!("{:?}: {}", file_path, json_string);
-
     Ok(())
 }
 
-pub async fn load() -> Result<SaveData, String> {
-    // Path to your JSON file
-    let file_path = Path::new(SAVE_DATA_PATH);
-
+async fn read(path: &Path) -> Result<String, String> {
     // Open the file in read mode
-    let mut file = match File::open(&file_path).await {
+    let mut file = match File::open(path).await {
         Ok(file) => file,
         Err(e) => {
             return Err(format!("Error opening file: {}", e));
@@ -75,21 +176,5 @@ pub async fn load() -> Result<SaveData, String> {
         return Err(format!("Error reading file: {}", e));
     }
 
-    // Deserialize the JSON string into a SaveData struct
-    let mut save_data: SaveData = match serde_json::from_str(&contents) {
-        Ok(data) => data,
-        Err(e) => {
-            return Err(format!("Error deserializing JSON: {}", e));
-        }
-    };
-
-    for x in &mut save_data.games {
-        if x.created.is_none() {
-            x.created = Some(Utc::now());
-        }
-    }
-
-    println!("{:?}: {:#?}", file_path, save_data);
-
-    Ok(save_data)
+    Ok(contents)
 }
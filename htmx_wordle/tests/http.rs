@@ -0,0 +1,337 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, HeaderMap, Request, StatusCode},
+    Router,
+};
+use htmx_wordle::{App, Dictionary, Storage};
+use tinyrand::Rand;
+use tower::ServiceExt;
+
+/// Always picks the last word of the dictionary.
+struct Last;
+
+impl Rand for Last {
+    fn next_u64(&mut self) -> u64 {
+        u64::MAX
+    }
+}
+
+/// An app that saves nothing, the answer of every game is "final".
+async fn app() -> Router {
+    App::new(Storage::Memory)
+        .dictionary(Dictionary::from_words("crane\nslate\nfinal"))
+        .rng(Last)
+        .router()
+        .await
+}
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, HeaderMap, String) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+fn guess(game: &str, guess: &str, turn: usize) -> Request<Body> {
+    Request::post(format!("{game}/guesses"))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(format!("guess={guess}&turn={turn}")))
+        .unwrap()
+}
+
+/// Starts a game and returns its path.
+async fn new_game(app: &Router) -> String {
+    let (status, headers, _) = send(app, get("/new_game")).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert!(headers.contains_key(header::SET_COOKIE));
+    headers["HX-Location"].to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn new_game_page() {
+    let app = app().await;
+    let game = new_game(&app).await;
+
+    let (status, _, body) = send(&app, get(&game)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("<!DOCTYPE html>"));
+    assert!(body.contains(r#"name="turn" value="0""#));
+    // The word is never sent while playing.
+    assert!(!body.contains("final"));
+
+    let (status, _, _) = send(&app, get("/game/00000000-0000-0000-0000-000000000000")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn no_words_to_play() {
+    let app = App::new(Storage::Memory)
+        .dictionary(Dictionary::default())
+        .router()
+        .await;
+
+    // Every try fails the same way, nothing panics.
+    for mode in ["classic", "daily", "classic"] {
+        let (status, _, body) = send(&app, get(&format!("/new_game?mode={mode}"))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("there are no words to play"));
+    }
+}
+
+#[tokio::test]
+async fn play_a_game() {
+    let app = app().await;
+    let game = new_game(&app).await;
+
+    let (status, _, body) = send(&app, guess(&game, "crane", 0)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("crane: "));
+    assert!(body.contains("5 guesses left."));
+    assert!(body.contains(r#"name="turn" value="1""#));
+
+    let (status, _, body) = send(&app, guess(&game, "final", 1)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("you won!"));
+    assert!(body.contains("the word was: <b>final</b>"));
+
+    let (status, _, body) = send(&app, guess(&game, "slate", 2)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body.contains("the game is over"));
+}
+
+#[tokio::test]
+async fn invalid_guesses() {
+    let app = app().await;
+    let game = new_game(&app).await;
+
+    let (status, _, body) = send(&app, guess(&game, "words", 0)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body.contains("<b>words</b> is not a valid word"));
+
+    let (status, _, body) = send(&app, guess(&game, "cran", 0)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body.contains("guesses have to be five letters"));
+
+    // A guess made on an old turn, e.g. from another tab.
+    send(&app, guess(&game, "crane", 0)).await;
+    let (status, _, _) = send(&app, guess(&game, "slate", 0)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn guesses_are_limited_per_player() {
+    let app = app().await;
+    let (_, headers, _) = send(&app, get("/new_game")).await;
+    let game = headers["HX-Location"].to_str().unwrap().to_string();
+    let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+    let player_guess = |game: &str| {
+        let mut request = guess(game, "words", 0);
+        let value = cookie.parse().unwrap();
+        request.headers_mut().insert(header::COOKIE, value);
+        request
+    };
+
+    for _ in 0..30 {
+        let (status, _, _) = send(&app, player_guess(&game)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let (status, _, body) = send(&app, player_guess(&game)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("too many guesses"));
+
+    // The player's other games count too, other players have their own limit.
+    let other = new_game(&app).await;
+    let (status, _, _) = send(&app, player_guess(&other)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _, _) = send(&app, guess(&other, "crane", 0)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn guesses_are_limited_per_game() {
+    let app = app().await;
+    let game = new_game(&app).await;
+    // Every guess comes with a made up player.
+    let new_player_guess = || {
+        let mut request = guess(&game, "words", 0);
+        let cookie = format!("player={}", uuid::Uuid::new_v4());
+        request
+            .headers_mut()
+            .insert(header::COOKIE, cookie.parse().unwrap());
+        request
+    };
+
+    for _ in 0..30 {
+        let (status, _, _) = send(&app, new_player_guess()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let (status, _, _) = send(&app, new_player_guess()).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+async fn admin_app() -> Router {
+    App::new(Storage::Memory)
+        .dictionary(Dictionary::from_words("crane\nslate\nfinal"))
+        .rng(Last)
+        .admin_token(Some("secret".to_string()))
+        .router()
+        .await
+}
+
+fn login(token: &str) -> Request<Body> {
+    login_from(token, "1.2.3.4")
+}
+
+/// Logs in from a client behind the proxy.
+fn login_from(token: &str, client: &str) -> Request<Body> {
+    Request::post("/admin/login")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("x-forwarded-for", client)
+        .body(Body::from(format!("token={token}")))
+        .unwrap()
+}
+
+/// The session cookie of a new admin session.
+async fn admin_cookie(app: &Router) -> String {
+    let (_, headers, _) = send(app, login("secret")).await;
+    let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+fn with_cookie(uri: &str, cookie: &str) -> Request<Body> {
+    Request::get(uri)
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn admin_sessions() {
+    let app = admin_app().await;
+    let (status, headers, _) = send(&app, login("secret")).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let set_cookie = headers[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.contains("Secure"));
+    // The cookie holds a session, not the token.
+    assert!(!set_cookie.contains("secret"));
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    let (status, _, _) = send(&app, with_cookie("/admin", &cookie)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send(&app, with_cookie("/admin", "admin=secret")).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let logout = Request::post("/admin/logout")
+        .header(header::COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    send(&app, logout).await;
+    let (status, _, _) = send(&app, with_cookie("/admin", &cookie)).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn admin_login_is_limited() {
+    let app = admin_app().await;
+    for _ in 0..5 {
+        let (status, _, _) = send(&app, login("guess")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    // Even the right token has to wait.
+    let (status, _, body) = send(&app, login("secret")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("too many tries"));
+
+    // Other clients can still log in.
+    let (status, _, _) = send(&app, login_from("secret", "5.6.7.8")).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn admin_token_is_only_for_logging_in() {
+    let app = admin_app().await;
+    let bearer = Request::get("/admin")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, _) = send(&app, bearer).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn removed_answers_can_still_be_guessed() {
+    let app = admin_app().await;
+    let game = new_game(&app).await;
+
+    let remove = Request::delete("/admin/words?word=final")
+        .header(header::COOKIE, admin_cookie(&app).await)
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, _) = send(&app, remove).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, body) = send(&app, guess(&game, "final", 0)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("you won!"));
+}
+
+#[tokio::test]
+async fn hints() {
+    let app = app().await;
+    let game = new_game(&app).await;
+    let hint = || {
+        Request::post(format!("{game}/hint"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, _, body) = send(&app, hint()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("possible words left"));
+    // The counter on the button is updated too.
+    assert!(body.contains(r#"<small id="hints-used" hx-swap-oob="true">1/3</small>"#));
+
+    let (status, _, body) = send(&app, hint()).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("for the next hint"));
+
+    send(&app, guess(&game, "final", 0)).await;
+    let (status, _, body) = send(&app, hint()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body.contains("the game is over"));
+
+    // Unknown games are reported in the toast as well.
+    let unknown = Request::post(format!("/game/{}/hint", uuid::Uuid::new_v4()))
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = send(&app, unknown).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.contains(r#"id="toast""#));
+    assert!(body.contains("game doesn't exist"));
+}
+
+#[tokio::test]
+async fn games_table() {
+    let app = app().await;
+    let won = new_game(&app).await;
+    send(&app, guess(&won, "final", 0)).await;
+    let playing = new_game(&app).await;
+    send(&app, guess(&playing, "crane", 0)).await;
+
+    let (status, _, body) = send(&app, get("/games")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.matches("<tr").count(), 3);
+    assert!(body.contains(&format!(r#"href="{won}/share""#)));
+    // Games that are still going aren't linked.
+    assert!(!body.contains(&playing));
+
+    let (_, _, body) = send(&app, get("/games/rows?status=won")).await;
+    assert_eq!(body.matches("<tr").count(), 1);
+    assert!(body.contains("1/6"));
+}